@@ -0,0 +1,345 @@
+//! Embedded HTTP API for inspecting and commanding a running [`Runtime`](crate::Runtime)
+//!
+//! The server is started by [`Runtime::serve_api`](crate::Runtime::serve_api). Since controllers
+//! are owned by the runtime loop, the server does not access the [`ControllerGroup`] directly.
+//! Instead, each request is forwarded to the runtime loop over a channel and answered in between
+//! polls.
+//!
+//! # Endpoints
+//! * `GET /controllers` - Returns the [`ControllerStatus`](crate::types::ControllerStatus) of
+//!   every controller
+//! * `GET /controllers/{name}` - Returns the status of a single controller
+//! * `POST /controllers/{name}` - Executes a [`Command`] encoded as JSON. For example,
+//!   `{"command": "force", "state": true, "expires_in": 600}` forces an output on for 10 minutes.
+//! * `POST /shutdown` - Gracefully shuts down the runtime
+//!
+//! Controller names are percent-decoded, so "grow light" is found at `/controllers/grow%20light`.
+//! Command bodies are limited to [`MAX_BODY_SIZE`] bytes.
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use chrono::Utc;
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::body::HttpBody;
+use hyper::service::{make_service_fn, service_fn};
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::sync::CancellationToken;
+use crate::ControllerGroup;
+use crate::types::{Command, CommandError, ControllerStatus};
+
+/// The largest command body that is accepted, in bytes
+pub const MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// A request forwarded from the HTTP server to the runtime loop
+#[derive(Debug)]
+pub(crate) enum ApiRequest {
+    Status(oneshot::Sender<Vec<ControllerStatus>>),
+    Execute {
+        name: String,
+        command: Command,
+        reply: oneshot::Sender<Result<(), CommandError>>,
+    },
+}
+
+impl ApiRequest {
+    /// Answer the request using the given group
+    pub(crate) fn handle(self, group: &mut ControllerGroup) {
+        // the requester may have gone away, in which case the reply is dropped
+        match self {
+            ApiRequest::Status(reply) => {
+                let _ = reply.send(group.status());
+            }
+            ApiRequest::Execute { name, command, reply } => {
+                let _ = reply.send(group.execute(&name, command, Utc::now()));
+            }
+        }
+    }
+}
+
+/// Bind the HTTP server and spawn it onto the tokio runtime
+///
+/// Returns the address that the server is bound to.
+/// The server stops once the shutdown token is cancelled.
+pub(crate) fn spawn(
+    addr: SocketAddr,
+    tx: mpsc::Sender<ApiRequest>,
+    shutdown: CancellationToken,
+) -> Result<SocketAddr, hyper::Error> {
+    let token = shutdown.clone();
+    let make_service = make_service_fn(move |_| {
+        let tx = tx.clone();
+        let token = token.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| route(req, tx.clone(), token.clone())))
+        }
+    });
+
+    let server = Server::try_bind(&addr)?
+        .serve(make_service);
+    let addr = server.local_addr();
+    let server = server.with_graceful_shutdown(async move { shutdown.cancelled().await });
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            eprintln!("API server error: {}", e);
+        }
+    });
+    Ok(addr)
+}
+
+pub(crate) fn json_response<T>(status: StatusCode, body: &T) -> Response<Body>
+    where T: Serialize
+{
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_vec(body).unwrap_or_default()))
+        .unwrap()
+}
+
+pub(crate) fn error_response(status: StatusCode, error: String) -> Response<Body> {
+    json_response(status, &serde_json::json!({ "error": error }))
+}
+
+/// Read a request body, failing once it is larger than `limit` bytes
+pub(crate) async fn read_body(mut body: Body, limit: usize) -> Result<Vec<u8>, (StatusCode, String)> {
+    let too_large = || (StatusCode::PAYLOAD_TOO_LARGE, format!("body is larger than {} bytes", limit));
+    if body.size_hint().lower() > limit as u64 {
+        return Err(too_large());
+    }
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+        if bytes.len() + chunk.len() > limit {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+/// Route an HTTP request to the runtime loop
+pub(crate) async fn route(
+    req: Request<Body>,
+    tx: mpsc::Sender<ApiRequest>,
+    shutdown: CancellationToken,
+) -> Result<Response<Body>, Infallible> {
+    let path = req.uri().path()
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| percent_decode_str(s).decode_utf8().map(String::from))
+        .collect::<Result<Vec<String>, _>>();
+    let path = match path {
+        Ok(path) => path,
+        Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, format!("invalid path: {}", e))),
+    };
+    let path: Vec<&str> = path.iter().map(String::as_str).collect();
+
+    let response = match (req.method(), path.as_slice()) {
+        (&Method::GET, ["controllers"]) => {
+            match status(&tx).await {
+                Some(statuses) => json_response(StatusCode::OK, &statuses),
+                None => error_response(StatusCode::SERVICE_UNAVAILABLE, String::from("runtime unavailable")),
+            }
+        }
+        (&Method::GET, ["controllers", name]) => {
+            match status(&tx).await {
+                Some(statuses) => match statuses.into_iter().find(|s| s.get_name() == *name) {
+                    Some(status) => json_response(StatusCode::OK, &status),
+                    None => error_response(
+                        StatusCode::NOT_FOUND,
+                        CommandError::UnknownController(name.to_string()).to_string(),
+                    ),
+                },
+                None => error_response(StatusCode::SERVICE_UNAVAILABLE, String::from("runtime unavailable")),
+            }
+        }
+        (&Method::POST, ["controllers", name]) => {
+            let name = name.to_string();
+            let body = match read_body(req.into_body(), MAX_BODY_SIZE).await {
+                Ok(body) => body,
+                Err((status, e)) => return Ok(error_response(status, e)),
+            };
+            match serde_json::from_slice::<Command>(&body) {
+                Ok(command) => execute(&tx, name, command).await,
+                Err(e) => error_response(StatusCode::BAD_REQUEST, e.to_string()),
+            }
+        }
+        (&Method::POST, ["shutdown"]) => {
+            shutdown.cancel();
+            json_response(StatusCode::OK, &serde_json::json!({ "result": "ok" }))
+        }
+        _ => error_response(StatusCode::NOT_FOUND, String::from("not found")),
+    };
+    Ok(response)
+}
+
+async fn status(tx: &mpsc::Sender<ApiRequest>) -> Option<Vec<ControllerStatus>> {
+    let (reply, rx) = oneshot::channel();
+    tx.send(ApiRequest::Status(reply)).await.ok()?;
+    rx.await.ok()
+}
+
+async fn execute(tx: &mpsc::Sender<ApiRequest>, name: String, command: Command) -> Response<Body> {
+    let (reply, rx) = oneshot::channel();
+    let request = ApiRequest::Execute { name, command, reply };
+    if tx.send(request).await.is_err() {
+        return error_response(StatusCode::SERVICE_UNAVAILABLE, String::from("runtime unavailable"));
+    }
+    match rx.await {
+        Ok(Ok(())) => json_response(StatusCode::OK, &serde_json::json!({ "result": "ok" })),
+        Ok(Err(e @ CommandError::UnknownController(_))) => error_response(StatusCode::NOT_FOUND, e.to_string()),
+        Ok(Err(e)) => error_response(StatusCode::UNPROCESSABLE_ENTITY, e.to_string()),
+        Err(_) => error_response(StatusCode::SERVICE_UNAVAILABLE, String::from("runtime unavailable")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use crate::controllers::{Controller, Threshold};
+    use crate::{Input, Output};
+    use super::*;
+
+    fn build_group() -> ControllerGroup {
+        let mut controller = Threshold::new(
+            70.0,
+            Input::new(|| "69.0".to_string()),
+            Output::default(),
+            Duration::minutes(5),
+        );
+        controller.set_name(String::from("heater"));
+
+        ControllerGroup::new()
+            .add_controller(controller)
+    }
+
+    /// Send a request to the router while answering requests on behalf of the runtime loop
+    async fn request(group: &mut ControllerGroup, req: Request<Body>) -> (StatusCode, serde_json::Value) {
+        let (tx, mut rx) = mpsc::channel(1);
+        let (response, _) = tokio::join!(
+            route(req, tx, CancellationToken::new()),
+            async {
+                if let Some(request) = rx.recv().await {
+                    request.handle(group);
+                }
+            },
+        );
+        let response = response.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_list_controllers() {
+        let mut group = build_group();
+
+        let req = Request::get("/controllers").body(Body::empty()).unwrap();
+        let (status, body) = request(&mut group, req).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body[0]["name"], "heater");
+        assert_eq!(body[0]["kind"], "Threshold");
+        assert_eq!(body[0]["mode"], "auto");
+        assert!(body[0]["next_event"].is_string());
+
+        let req = Request::get("/controllers/cooler").body(Body::empty()).unwrap();
+        let (status, _) = request(&mut group, req).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_execute_command() {
+        let mut group = build_group();
+
+        let req = Request::post("/controllers/heater")
+            .body(Body::from(r#"{"command": "set_setpoint", "value": 72.5}"#))
+            .unwrap();
+        let (status, _) = request(&mut group, req).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(group.status()[0].get_setpoint(), Some(72.5));
+
+        let req = Request::post("/controllers/heater")
+            .body(Body::from(r#"{"command": "force", "state": true, "expires_in": 60}"#))
+            .unwrap();
+        let (status, _) = request(&mut group, req).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(group.status()[0].get_mode(), crate::types::Mode::ManualOn);
+
+        let req = Request::post("/controllers/heater")
+            .body(Body::from(r#"{"command": "explode"}"#))
+            .unwrap();
+        let (status, body) = request(&mut group, req).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_encoded_names() {
+        let mut controller = Threshold::new(
+            70.0,
+            Input::new(|| "69.0".to_string()),
+            Output::default(),
+            Duration::minutes(5),
+        );
+        controller.set_name(String::from("grow light"));
+        let mut group = ControllerGroup::new()
+            .add_controller(controller);
+
+        let req = Request::get("/controllers/grow%20light").body(Body::empty()).unwrap();
+        let (status, body) = request(&mut group, req).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["name"], "grow light");
+
+        let req = Request::post("/controllers/grow%20light")
+            .body(Body::from(r#"{"command": "disable"}"#))
+            .unwrap();
+        let (status, _) = request(&mut group, req).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(group.status()[0].get_mode(), crate::types::Mode::Disabled);
+
+        // segments which do not decode to UTF-8 are rejected
+        let req = Request::get("/controllers/grow%FFlight").body(Body::empty()).unwrap();
+        let (status, _) = request(&mut group, req).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_max_body_size() {
+        let mut group = build_group();
+
+        let command = format!(r#"{{"command": "set_setpoint", "value": 72.5{}}}"#, " ".repeat(MAX_BODY_SIZE));
+        let req = Request::post("/controllers/heater").body(Body::from(command)).unwrap();
+        let (status, _) = request(&mut group, req).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(group.status()[0].get_setpoint(), Some(70.0));
+    }
+
+    #[tokio::test]
+    async fn test_spawn() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let token = CancellationToken::new();
+        let addr = spawn(SocketAddr::from(([127, 0, 0, 1], 0)), tx, token.clone()).unwrap();
+
+        let mut group = build_group();
+        let client = reqwest::Client::new();
+        let (response, _) = tokio::join!(
+            client.get(format!("http://{}/controllers", addr)).send(),
+            async {
+                if let Some(request) = rx.recv().await {
+                    request.handle(&mut group);
+                }
+            },
+        );
+        let statuses: Vec<ControllerStatus> = response.unwrap().json().await.unwrap();
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(statuses[0].get_name(), "heater");
+
+        // shutdown cancels the token
+        let response = client.post(format!("http://{}/shutdown", addr)).send().await.unwrap();
+        assert!(response.status().is_success());
+        assert!(token.is_cancelled());
+    }
+}
@@ -0,0 +1,360 @@
+//! Arbitration of an actuator that is commanded by more than one controller
+//!
+//! Normally each controller owns its [`Output`]. When two controllers legitimately command the
+//! same actuator, such as an aerator that runs on a
+//! [`TimedOutput`](crate::controllers::TimedOutput) schedule but must also run when dissolved
+//! oxygen falls below a [`Threshold`](crate::controllers::Threshold), an [`OutputArbiter`] owns
+//! the low-level callback instead. Each controller is given its own [`Output`] which submits a
+//! request to the arbiter, and the arbiter drives the actuator according to its [`Resolution`]
+//! strategy.
+//!
+//! Once attached to a [`ControllerGroup`](crate::ControllerGroup) with
+//! [`ControllerGroup::add_arbiter`](crate::ControllerGroup::add_arbiter), every arbitration that
+//! changes the actuator, the winning requesters or the overruled requests is reported as a
+//! [`Message`], naming the winning requesters and the losing requests. Requests which are repeated
+//! on every poll are therefore only reported once. On shutdown, the actuator is driven to the safe
+//! state of the arbiter regardless of any requests.
+//!
+//! # Example
+//! ```
+//! use chrono::{Duration, NaiveTime, Utc};
+//! use equilibrium::arbiter::{OutputArbiter, Resolution};
+//! use equilibrium::controllers::{Threshold, TimedOutput};
+//! use equilibrium::{ControllerGroup, Input};
+//!
+//! let aerator = OutputArbiter::new("aerator", |state| {
+//!     // low-level code would go here
+//! }, Resolution::Any).set_safe_state(true);
+//!
+//! let schedule = TimedOutput::new(
+//!     aerator.output("schedule", 0),
+//!     NaiveTime::from_hms_opt(6, 0, 0).unwrap(),
+//!     Duration::hours(2),
+//! );
+//! let oxygen = Threshold::new(
+//!     5.0,
+//!     Input::new(|| String::from("6.5")),
+//!     aerator.output("oxygen", 10),
+//!     Duration::minutes(1),
+//! ).set_inverted();
+//!
+//! let mut group = ControllerGroup::new()
+//!     .add_controller(schedule)
+//!     .add_controller(oxygen)
+//!     .add_arbiter(aerator);
+//!
+//! group.poll(Utc::now());
+//! ```
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use crate::Output;
+use crate::types::Message;
+
+/// Strategy used by an [`OutputArbiter`] to resolve conflicting requests
+///
+/// Only requesters which have written to their output take part in arbitration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Resolution {
+    /// The actuator is on if any requester wants it on
+    #[default]
+    Any,
+
+    /// The actuator is on only if every requester wants it on
+    All,
+
+    /// The requester with the highest priority wins. Ties are won by the most recent request.
+    Priority,
+
+    /// The most recent request wins
+    LastWriter,
+}
+
+#[derive(Debug)]
+struct Request {
+    name: String,
+    priority: i32,
+    state: Option<bool>,
+    sequence: u64,
+}
+
+struct Shared {
+    callback: Box<dyn FnMut(bool) + Send>,
+    resolution: Resolution,
+    requests: Vec<Request>,
+    sequence: u64,
+    state: Option<bool>,
+    reports: Vec<String>,
+    last_report: Option<String>,
+}
+
+fn describe(state: bool) -> &'static str {
+    match state {
+        true => "On",
+        false => "Off",
+    }
+}
+
+impl Shared {
+    /// Record a request and drive the actuator to the resolved state
+    fn request(&mut self, index: usize, state: bool) {
+        self.sequence += 1;
+        self.requests[index].state = Some(state);
+        self.requests[index].sequence = self.sequence;
+
+        let (resolved, winners) = self.resolve();
+        let losers: Vec<String> = self.requests.iter()
+            .filter_map(|r| match r.state {
+                Some(state) if state != resolved => Some(format!("{} ({})", r.name, describe(state))),
+                _ => None,
+            })
+            .collect();
+
+        let changed = self.state != Some(resolved);
+        if changed {
+            self.state = Some(resolved);
+            (self.callback)(resolved);
+        }
+
+        // the report names the winners and the losers, so an unchanged report is not repeated
+        let mut report = format!("Arbitrated {} by {}", describe(resolved), winners.join(", "));
+        if !losers.is_empty() {
+            report.push_str(&format!("; overruled {}", losers.join(", ")));
+        }
+        if changed || self.last_report.as_ref() != Some(&report) {
+            self.last_report = Some(report.clone());
+            self.reports.push(report);
+        }
+    }
+
+    /// Returns the resolved state and the names of the winning requesters
+    fn resolve(&self) -> (bool, Vec<String>) {
+        let active = self.requests.iter().filter(|r| r.state.is_some());
+        let resolved = match self.resolution {
+            Resolution::Any => active.clone().any(|r| r.state == Some(true)),
+            Resolution::All => active.clone().all(|r| r.state == Some(true)),
+            Resolution::Priority | Resolution::LastWriter => {
+                let winner = match self.resolution {
+                    Resolution::Priority => active.max_by_key(|r| (r.priority, r.sequence)),
+                    _ => active.max_by_key(|r| r.sequence),
+                }.expect("at least one request has been made");
+                return (winner.state.unwrap(), vec![winner.name.clone()]);
+            }
+        };
+
+        let winners = active
+            .filter(|r| r.state == Some(resolved))
+            .map(|r| r.name.clone())
+            .collect();
+        (resolved, winners)
+    }
+}
+
+/// Owns an actuator and resolves the requests of the controllers which command it
+///
+/// See the [module documentation](self) for details.
+pub struct OutputArbiter {
+    name: String,
+    shared: Arc<Mutex<Shared>>,
+    safe_state: Option<bool>,
+}
+
+impl OutputArbiter {
+    /// Create a new arbiter
+    ///
+    /// # Arguments
+    /// * `name` - Name of the actuator, used in messages
+    /// * `callback` - Low-level code that accepts a `bool` argument
+    /// * `resolution` - Strategy used to resolve conflicting requests
+    pub fn new<S, F>(name: S, callback: F, resolution: Resolution) -> Self
+        where
+            S: Into<String>,
+            F: FnMut(bool) + Send + 'static,
+    {
+        let shared = Shared {
+            callback: Box::new(callback),
+            resolution,
+            requests: Vec::new(),
+            sequence: 0,
+            state: None,
+            reports: Vec::new(),
+            last_report: None,
+        };
+        Self {
+            name: name.into(),
+            shared: Arc::new(Mutex::new(shared)),
+            safe_state: None,
+        }
+    }
+
+    /// Builder method to declare the state that the actuator should be driven to on shutdown
+    pub fn set_safe_state(mut self, state: bool) -> Self {
+        self.safe_state = Some(state);
+        self
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Create an [`Output`] for a requester
+    ///
+    /// # Arguments
+    /// * `requester` - Name of the requester, used in messages
+    /// * `priority` - Priority of the requester. Only used by [`Resolution::Priority`], where
+    ///   higher values win.
+    pub fn output(&self, requester: &str, priority: i32) -> Output<impl FnMut(bool) + Send> {
+        let index = {
+            let mut shared = self.shared.lock().unwrap();
+            shared.requests.push(Request {
+                name: requester.to_string(),
+                priority,
+                state: None,
+                sequence: 0,
+            });
+            shared.requests.len() - 1
+        };
+
+        let shared = self.shared.clone();
+        Output::new(move |state| shared.lock().unwrap().request(index, state))
+    }
+
+    /// Get the current state of the actuator
+    pub fn get_state(&self) -> Option<bool> {
+        self.shared.lock().unwrap().state
+    }
+
+    /// Return a message for every arbitration since the last call
+    pub fn drain_messages(&self, time: DateTime<Utc>) -> Vec<Message> {
+        self.shared.lock().unwrap().reports
+            .drain(..)
+            .map(|report| Message::new(self.name.clone(), report, time, None))
+            .collect()
+    }
+
+    /// Drive the actuator to its safe state, bypassing arbitration
+    ///
+    /// Returns a message if a safe state has been declared.
+    pub fn apply_safe_state(&self, time: DateTime<Utc>) -> Option<Message> {
+        let state = self.safe_state?;
+        let mut shared = self.shared.lock().unwrap();
+        shared.state = Some(state);
+        (shared.callback)(state);
+        Some(Message::new(self.name.clone(), String::from("Safe State"), time, None))
+    }
+}
+
+impl std::fmt::Debug for OutputArbiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let shared = self.shared.lock().unwrap();
+        f.debug_struct("OutputArbiter")
+            .field("name", &self.name)
+            .field("resolution", &shared.resolution)
+            .field("requests", &shared.requests)
+            .field("state", &shared.state)
+            .field("safe_state", &self.safe_state)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracked_arbiter(resolution: Resolution) -> (OutputArbiter, Arc<Mutex<Vec<bool>>>) {
+        let writes = Arc::new(Mutex::new(Vec::new()));
+        let log = writes.clone();
+        let arbiter = OutputArbiter::new("aerator", move |state| {
+            log.lock().unwrap().push(state);
+        }, resolution);
+        (arbiter, writes)
+    }
+
+    #[test]
+    fn test_any() {
+        let (arbiter, writes) = tracked_arbiter(Resolution::Any);
+        let mut schedule = arbiter.output("schedule", 0);
+        let mut oxygen = arbiter.output("oxygen", 0);
+
+        schedule.deactivate();
+        oxygen.activate();
+        schedule.deactivate();
+        assert_eq!(arbiter.get_state(), Some(true));
+        // the actuator is only written when the resolved state changes
+        assert_eq!(*writes.lock().unwrap(), vec![false, true]);
+
+        // repeated requests which do not change the outcome are not reported again
+        let messages = arbiter.drain_messages(Utc::now());
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].get_content(), "Arbitrated Off by schedule");
+        assert_eq!(messages[1].get_content(), "Arbitrated On by oxygen; overruled schedule (Off)");
+        oxygen.activate();
+        assert!(arbiter.drain_messages(Utc::now()).is_empty());
+
+        // a change of the winners is reported even though the actuator does not change
+        schedule.activate();
+        let messages = arbiter.drain_messages(Utc::now());
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].get_content(), "Arbitrated On by schedule, oxygen");
+    }
+
+    #[test]
+    fn test_all() {
+        let (arbiter, _) = tracked_arbiter(Resolution::All);
+        let mut first = arbiter.output("first", 0);
+        let mut second = arbiter.output("second", 0);
+
+        first.activate();
+        assert_eq!(arbiter.get_state(), Some(true));
+
+        second.deactivate();
+        assert_eq!(arbiter.get_state(), Some(false));
+
+        second.activate();
+        assert_eq!(arbiter.get_state(), Some(true));
+    }
+
+    #[test]
+    fn test_priority() {
+        let (arbiter, _) = tracked_arbiter(Resolution::Priority);
+        let mut schedule = arbiter.output("schedule", 0);
+        let mut oxygen = arbiter.output("oxygen", 10);
+
+        oxygen.activate();
+        schedule.deactivate();
+        assert_eq!(arbiter.get_state(), Some(true));
+
+        let messages = arbiter.drain_messages(Utc::now());
+        assert_eq!(messages[1].get_content(), "Arbitrated On by oxygen; overruled schedule (Off)");
+
+        oxygen.deactivate();
+        schedule.activate();
+        assert_eq!(arbiter.get_state(), Some(false));
+    }
+
+    #[test]
+    fn test_last_writer() {
+        let (arbiter, _) = tracked_arbiter(Resolution::LastWriter);
+        let mut first = arbiter.output("first", 10);
+        let mut second = arbiter.output("second", 0);
+
+        first.activate();
+        second.deactivate();
+        assert_eq!(arbiter.get_state(), Some(false));
+
+        first.activate();
+        assert_eq!(arbiter.get_state(), Some(true));
+    }
+
+    #[test]
+    fn test_apply_safe_state() {
+        let (arbiter, writes) = tracked_arbiter(Resolution::Any);
+        let arbiter = arbiter.set_safe_state(false);
+        let mut output = arbiter.output("schedule", 0);
+
+        output.activate();
+        let message = arbiter.apply_safe_state(Utc::now()).unwrap();
+        assert_eq!(message.get_content(), "Safe State");
+        assert_eq!(arbiter.get_state(), Some(false));
+        assert_eq!(*writes.lock().unwrap(), vec![true, false]);
+    }
+}
@@ -0,0 +1,147 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use chrono::Duration;
+use crate::Input;
+use crate::types::DriverError;
+
+type ReadFuture = Pin<Box<dyn Future<Output = Result<String, DriverError>> + Send>>;
+
+/// Encapsulates an input device that is read asynchronously
+///
+/// This is the asynchronous counterpart of [`Input`], and is used for devices that are accessed
+/// over the network, a serial port, I2C, etc. so that reading the device does not block the tokio
+/// runtime. Every read is bounded by a timeout.
+///
+/// Existing synchronous callbacks may be used via [`AsyncInput::from_blocking`] or by converting an
+/// [`Input`], in which case the callback is executed on a blocking task.
+///
+/// # Example
+/// ```
+/// use equilibrium::AsyncInput;
+///
+/// let input = AsyncInput::new(|| async {
+///     // low-level code would go here
+///     Ok(String::from("1.0"))
+/// });
+/// ```
+pub struct AsyncInput {
+    callback: Box<dyn Fn() -> ReadFuture + Send + Sync>,
+    state: Option<String>,
+    timeout: Duration,
+}
+
+impl AsyncInput {
+    /// Create a new `AsyncInput` instance with a timeout of 5 seconds
+    ///
+    /// # Arguments
+    /// * `callback` - Low-level code that asynchronously returns input as a `String`
+    pub fn new<F, Fut>(callback: F) -> Self
+        where
+            F: Fn() -> Fut + Send + Sync + 'static,
+            Fut: Future<Output = Result<String, DriverError>> + Send + 'static,
+    {
+        Self {
+            callback: Box::new(move || Box::pin(callback())),
+            state: None,
+            timeout: Duration::seconds(5),
+        }
+    }
+
+    /// Create a new `AsyncInput` from a synchronous callback
+    ///
+    /// The callback is executed on a blocking task so that it does not block the tokio runtime.
+    pub fn from_blocking<F>(callback: F) -> Self
+        where F: Fn() -> String + Send + Sync + 'static
+    {
+        let callback = Arc::new(callback);
+        Self::new(move || {
+            let callback = callback.clone();
+            async move {
+                tokio::task::spawn_blocking(move || callback())
+                    .await
+                    .map_err(|e| DriverError::Failed(e.to_string()))
+            }
+        })
+    }
+
+    /// Builder method to set how long a read may take
+    pub fn set_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn get_timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Read the input
+    ///
+    /// The internal state is updated when the read succeeds.
+    pub async fn read(&mut self) -> Result<String, DriverError> {
+        let timeout = self.timeout.to_std().unwrap_or_default();
+        let state = tokio::time::timeout(timeout, (self.callback)())
+            .await
+            .map_err(|_| DriverError::Timeout)??;
+        self.state = Some(state.clone());
+        Ok(state)
+    }
+
+    /// Get the current state of the input
+    ///
+    /// The state is treated as a cache of the last value that was successfully read.
+    pub fn get_state(&self) -> &Option<String> {
+        &self.state
+    }
+}
+
+impl<F> From<Input<F>> for AsyncInput
+    where F: Fn() -> String + Send + Sync + 'static
+{
+    fn from(input: Input<F>) -> Self {
+        Self::from_blocking(input.into_callback())
+    }
+}
+
+impl std::fmt::Debug for AsyncInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AsyncInput")
+            .field("state", &self.state)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read() {
+        let mut input = AsyncInput::new(|| async { Ok(String::from("test")) });
+
+        assert_eq!(input.get_state(), &None);
+
+        let state = input.read().await;
+        assert_eq!(state, Ok(String::from("test")));
+        assert_eq!(input.get_state(), &Some(String::from("test")));
+    }
+
+    #[tokio::test]
+    async fn test_read_timeout() {
+        let mut input = AsyncInput::new(|| async {
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            Ok(String::from("test"))
+        }).set_timeout(Duration::milliseconds(10));
+
+        assert_eq!(input.read().await, Err(DriverError::Timeout));
+        assert_eq!(input.get_state(), &None);
+    }
+
+    #[tokio::test]
+    async fn test_from_input() {
+        let mut input = AsyncInput::from(Input::new(|| String::from("1.0")));
+
+        assert_eq!(input.read().await, Ok(String::from("1.0")));
+    }
+}
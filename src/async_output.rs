@@ -0,0 +1,262 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use chrono::Duration;
+use crate::Output;
+use crate::types::DriverError;
+
+type WriteFuture = Pin<Box<dyn Future<Output = Result<(), DriverError>> + Send>>;
+
+/// Encapsulates an output device that is written to asynchronously
+///
+/// This is the asynchronous counterpart of [`Output`]. Every write is bounded by a timeout. If a
+/// write fails, the cached state is cleared since the state of the physical device is unknown.
+///
+/// Existing synchronous callbacks may be used via [`AsyncOutput::from_blocking`] or by converting an
+/// [`Output`], in which case the callback is executed on a blocking task.
+///
+/// # Example
+/// ```
+/// use equilibrium::AsyncOutput;
+///
+/// let output = AsyncOutput::new(|state| async move {
+///     // low-level code would go here
+///     println!("Output state: {}", state);
+///     Ok(())
+/// }).set_safe_state(false);
+/// ```
+pub struct AsyncOutput {
+    callback: Arc<dyn Fn(bool) -> WriteFuture + Send + Sync>,
+    state: Option<bool>,
+    safe_state: Option<bool>,
+    timeout: Duration,
+}
+
+impl AsyncOutput {
+    /// Create a new `AsyncOutput` instance with a timeout of 5 seconds
+    ///
+    /// # Arguments
+    /// * `callback` - Low-level code that asynchronously accepts a `bool` argument
+    pub fn new<F, Fut>(callback: F) -> Self
+        where
+            F: Fn(bool) -> Fut + Send + Sync + 'static,
+            Fut: Future<Output = Result<(), DriverError>> + Send + 'static,
+    {
+        Self {
+            callback: Arc::new(move |state| Box::pin(callback(state))),
+            state: None,
+            safe_state: None,
+            timeout: Duration::seconds(5),
+        }
+    }
+
+    /// Create a new `AsyncOutput` from a synchronous callback
+    ///
+    /// The callback is executed on a blocking task so that it does not block the tokio runtime.
+    pub fn from_blocking<F>(callback: F) -> Self
+        where F: FnMut(bool) + Send + 'static
+    {
+        let callback = Arc::new(Mutex::new(callback));
+        Self::new(move |state| {
+            let callback = callback.clone();
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    let mut callback = callback.lock()
+                        .map_err(|e| DriverError::Failed(e.to_string()))?;
+                    (callback)(state);
+                    Ok(())
+                })
+                    .await
+                    .map_err(|e| DriverError::Failed(e.to_string()))?
+            }
+        })
+    }
+
+    /// Builder method to set how long a write may take
+    pub fn set_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Builder method to declare the state that the output should be driven to on shutdown
+    pub fn set_safe_state(mut self, state: bool) -> Self {
+        self.safe_state = Some(state);
+        self
+    }
+
+    pub fn get_safe_state(&self) -> Option<bool> {
+        self.safe_state
+    }
+
+    async fn write(&mut self, state: bool) -> Result<(), DriverError> {
+        let timeout = self.timeout.to_std().unwrap_or_default();
+        let result = tokio::time::timeout(timeout, (self.callback)(state))
+            .await
+            .map_err(|_| DriverError::Timeout)
+            .and_then(|result| result);
+
+        self.state = match result {
+            Ok(()) => Some(state),
+            Err(_) => None,
+        };
+        result
+    }
+
+    /// Activate the output
+    pub async fn activate(&mut self) -> Result<(), DriverError> {
+        self.write(true).await
+    }
+
+    /// Deactivate the output
+    pub async fn deactivate(&mut self) -> Result<(), DriverError> {
+        self.write(false).await
+    }
+
+    /// Drive the output to its safe state, if one has been declared
+    ///
+    /// Returns the state that was applied.
+    pub async fn apply_safe_state(&mut self) -> Result<Option<bool>, DriverError> {
+        if let Some(state) = self.safe_state {
+            self.write(state).await?;
+        }
+        Ok(self.safe_state)
+    }
+
+    /// Returns an action which drives the output to its safe state, if one has been declared
+    ///
+    /// The action shares the callback of the output, but not its cached state.
+    pub fn safe_state_action(&self) -> Option<SafeStateAction> {
+        self.safe_state.map(|state| SafeStateAction {
+            callback: self.callback.clone(),
+            state,
+            timeout: self.timeout,
+        })
+    }
+
+    /// Get the current state of the output
+    ///
+    /// The state is treated as a cache of the last value that was successfully written.
+    pub fn get_state(&self) -> Option<bool> {
+        self.state
+    }
+}
+
+/// Drives an [`AsyncOutput`] to its safe state without access to the output itself
+///
+/// This is used to apply the safe state once the output is lost, such as when the
+/// [`AsyncController`](crate::controllers::AsyncController) that owns it panics.
+#[derive(Clone)]
+pub struct SafeStateAction {
+    callback: Arc<dyn Fn(bool) -> WriteFuture + Send + Sync>,
+    state: bool,
+    timeout: Duration,
+}
+
+impl SafeStateAction {
+    /// Write the safe state, bounded by the timeout of the output
+    ///
+    /// Returns the state that was applied.
+    pub async fn apply(&self) -> Result<bool, DriverError> {
+        let timeout = self.timeout.to_std().unwrap_or_default();
+        tokio::time::timeout(timeout, (self.callback)(self.state))
+            .await
+            .map_err(|_| DriverError::Timeout)??;
+        Ok(self.state)
+    }
+}
+
+impl std::fmt::Debug for SafeStateAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SafeStateAction")
+            .field("state", &self.state)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl<F> From<Output<F>> for AsyncOutput
+    where F: FnMut(bool) + Send + 'static
+{
+    fn from(output: Output<F>) -> Self {
+        let safe_state = output.get_safe_state();
+        let mut output = Self::from_blocking(output.into_callback());
+        output.safe_state = safe_state;
+        output
+    }
+}
+
+impl std::fmt::Debug for AsyncOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AsyncOutput")
+            .field("state", &self.state)
+            .field("safe_state", &self.safe_state)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_activate() {
+        let external_state = Arc::new(Mutex::new(false));
+        let state = external_state.clone();
+        let mut output = AsyncOutput::new(move |value| {
+            let state = state.clone();
+            async move {
+                *state.lock().unwrap() = value;
+                Ok(())
+            }
+        });
+
+        assert_eq!(output.get_state(), None);
+
+        output.activate().await.unwrap();
+        assert_eq!(output.get_state(), Some(true));
+        assert!(*external_state.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_write_timeout() {
+        let mut output = AsyncOutput::new(|_| async {
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            Ok(())
+        }).set_timeout(Duration::milliseconds(10));
+
+        assert_eq!(output.activate().await, Err(DriverError::Timeout));
+        assert_eq!(output.get_state(), None);
+    }
+
+    #[tokio::test]
+    async fn test_from_output() {
+        let external_state = Arc::new(Mutex::new(true));
+        let state = external_state.clone();
+        let mut output = AsyncOutput::from(Output::new(move |value| {
+            *state.lock().unwrap() = value;
+        }).set_safe_state(false));
+
+        assert_eq!(output.apply_safe_state().await, Ok(Some(false)));
+        assert_eq!(output.get_state(), Some(false));
+        assert!(!*external_state.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_safe_state_action() {
+        let external_state = Arc::new(Mutex::new(true));
+        let state = external_state.clone();
+        let output = AsyncOutput::new(move |value| {
+            let state = state.clone();
+            async move {
+                *state.lock().unwrap() = value;
+                Ok(())
+            }
+        });
+        assert!(output.safe_state_action().is_none());
+
+        let action = output.set_safe_state(false).safe_state_action().unwrap();
+        assert_eq!(action.apply().await, Ok(false));
+        assert!(!*external_state.lock().unwrap());
+    }
+}
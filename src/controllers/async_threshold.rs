@@ -0,0 +1,247 @@
+use chrono::{DateTime, Duration, Utc};
+use crate::{AsyncInput, AsyncOutput, SafeStateAction};
+use crate::controllers::{AsyncController, PollFuture};
+use crate::scheduler::Scheduler;
+use crate::types::{Action, Message};
+
+/// The asynchronous counterpart of [`Threshold`](crate::controllers::Threshold)
+///
+/// The input is read and the output is written asynchronously, with the timeouts configured on
+/// the [`AsyncInput`] and [`AsyncOutput`]. If the input cannot be read, or returns a value that is
+/// not numeric, the output is left untouched and a "Read Failed" message is returned.
+///
+/// # Example
+/// ```
+/// use chrono::{Duration, Utc};
+/// use equilibrium::controllers::{AsyncController, AsyncThreshold};
+/// use equilibrium::{AsyncInput, AsyncOutput};
+///
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// let mut controller = AsyncThreshold::new(
+///     10.0,
+///     AsyncInput::new(|| async { Ok(String::from("12.0")) }),
+///     AsyncOutput::new(|_| async { Ok(()) }),
+///     Duration::seconds(1),
+/// );
+///
+/// controller.poll(Utc::now()).await;
+/// # });
+/// ```
+#[derive(Debug)]
+pub struct AsyncThreshold {
+    name: Option<String>,
+    threshold: f32,
+    input: AsyncInput,
+    output: AsyncOutput,
+    interval: Duration,
+    schedule: Scheduler,
+    inverted: bool,
+}
+
+impl AsyncThreshold {
+    /// Create a new controller with the first read scheduled one interval from now
+    ///
+    /// This is the recommended API for instantiation.
+    pub fn new(threshold: f32, input: AsyncInput, output: AsyncOutput, interval: Duration) -> Self {
+        Self::new_without_scheduled(threshold, input, output, interval)
+            .schedule_next(None)
+    }
+
+    /// Create a new controller without scheduling the first read
+    ///
+    /// [`AsyncThreshold::schedule_next()`] must be called after this function.
+    ///
+    /// This method is only useful for testing purposes.
+    pub fn new_without_scheduled(threshold: f32, input: AsyncInput, output: AsyncOutput, interval: Duration) -> Self {
+        Self {
+            name: None,
+            threshold,
+            input,
+            output,
+            interval,
+            schedule: Scheduler::new(),
+            inverted: false,
+        }
+    }
+
+    /// Builder method to set the controller to be inverted
+    ///
+    /// This means that the output will be activated when the input is below the threshold and
+    /// deactivated when the input is above the threshold.
+    pub fn set_inverted(mut self) -> Self {
+        self.inverted = true;
+        self
+    }
+
+    pub fn get_threshold(&self) -> f32 {
+        self.threshold
+    }
+
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.threshold = threshold;
+    }
+
+    /// Builder method to schedule the next read for the specified time
+    ///
+    /// If no time is specified, the current time will be used.
+    pub fn schedule_next<T>(mut self, time: T) -> Self
+        where T: Into<Option<DateTime<Utc>>>
+    {
+        let time = time.into().unwrap_or_else(Utc::now);
+        self.schedule.schedule_read(time + self.interval);
+        self
+    }
+
+    /// Read the input and drive the output, returning the content of the resulting message
+    async fn evaluate(&mut self) -> String {
+        let value = match self.input.read().await {
+            Ok(value) => value,
+            Err(e) => return format!("Read Failed: {}", e),
+        };
+        let value = match value.trim().parse::<f32>() {
+            Ok(reading) if reading.is_finite() => reading,
+            _ => return format!("Read Failed: invalid reading {:?}", value),
+        };
+
+        let above = value > self.threshold;
+        let result = match above != self.inverted {
+            true => self.output.activate().await,
+            false => self.output.deactivate().await,
+        };
+
+        match (result, above) {
+            (Err(e), _) => format!("Write Failed: {}", e),
+            (Ok(()), true) => String::from("Above Threshold"),
+            (Ok(()), false) => String::from("Below Threshold"),
+        }
+    }
+}
+
+impl AsyncController for AsyncThreshold {
+    fn set_name(&mut self, name: String) {
+        self.name = Some(name);
+    }
+
+    fn get_name(&self) -> Option<String> {
+        self.name.clone()
+    }
+
+    fn poll(&mut self, time: DateTime<Utc>) -> PollFuture<'_> {
+        Box::pin(async move {
+            let event = self.schedule.attempt_execution(time)?;
+            if event.get_action() != Action::Read {
+                return None;
+            }
+
+            let msg = self.evaluate().await;
+            self.schedule.schedule_read(time + self.interval);
+
+            Some(Message::new(
+                self.get_name().unwrap_or_default(),
+                msg,
+                time,
+                self.input.get_state().clone(),
+            ).set_output_state(self.output.get_state()))
+        })
+    }
+
+    fn shutdown(&mut self, time: DateTime<Utc>) -> PollFuture<'_> {
+        Box::pin(async move {
+            let (msg, state) = match self.output.apply_safe_state().await {
+                Ok(None) => return None,
+                Ok(Some(state)) => (String::from("Safe State"), Some(state)),
+                Err(e) => (format!("Write Failed: {}", e), None),
+            };
+            Some(Message::new(self.get_name().unwrap_or_default(), msg, time, None).set_output_state(state))
+        })
+    }
+
+    fn safe_state_actions(&self) -> Vec<SafeStateAction> {
+        self.output.safe_state_action().into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use crate::types::DriverError;
+    use super::*;
+
+    fn tracked_output(external_state: Arc<Mutex<Option<bool>>>) -> AsyncOutput {
+        AsyncOutput::new(move |value| {
+            let state = external_state.clone();
+            async move {
+                *state.lock().unwrap() = Some(value);
+                Ok(())
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_poll() {
+        let readings = Arc::new(Mutex::new(vec!["10.0", "0.0"]));
+        let input = AsyncInput::new(move || {
+            let readings = readings.clone();
+            async move { Ok(readings.lock().unwrap().remove(0).to_string()) }
+        });
+
+        let external_state = Arc::new(Mutex::new(None));
+        let time = Utc::now();
+        let mut controller = AsyncThreshold::new_without_scheduled(
+            5.0,
+            input,
+            tracked_output(external_state.clone()),
+            Duration::seconds(1),
+        ).schedule_next(time);
+
+        // check before first read
+        assert!(controller.poll(time).await.is_none());
+
+        let message = controller.poll(time + Duration::seconds(1)).await.unwrap();
+        assert_eq!(message.get_content(), "Above Threshold");
+        assert_eq!(message.get_read_state(), Some(String::from("10.0")));
+        assert_eq!(*external_state.lock().unwrap(), Some(true));
+
+        let message = controller.poll(time + Duration::seconds(2)).await.unwrap();
+        assert_eq!(message.get_content(), "Below Threshold");
+        assert_eq!(*external_state.lock().unwrap(), Some(false));
+    }
+
+    #[tokio::test]
+    async fn test_poll_read_failed() {
+        let input = AsyncInput::new(|| async {
+            Err(DriverError::Failed(String::from("bus error")))
+        });
+
+        let external_state = Arc::new(Mutex::new(None));
+        let time = Utc::now();
+        let mut controller = AsyncThreshold::new_without_scheduled(
+            5.0,
+            input,
+            tracked_output(external_state.clone()),
+            Duration::seconds(1),
+        ).schedule_next(time);
+
+        let message = controller.poll(time + Duration::seconds(1)).await.unwrap();
+        assert_eq!(message.get_content(), "Read Failed: bus error");
+        assert_eq!(*external_state.lock().unwrap(), None);
+
+        // reads continue to be scheduled
+        assert!(controller.poll(time + Duration::seconds(2)).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let external_state = Arc::new(Mutex::new(None));
+        let mut controller = AsyncThreshold::new(
+            5.0,
+            AsyncInput::new(|| async { Ok(String::from("0.0")) }),
+            tracked_output(external_state.clone()).set_safe_state(false),
+            Duration::seconds(1),
+        );
+
+        let message = controller.shutdown(Utc::now()).await.unwrap();
+        assert_eq!(message.get_content(), "Safe State");
+        assert_eq!(*external_state.lock().unwrap(), Some(false));
+    }
+}
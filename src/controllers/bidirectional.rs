@@ -1,7 +1,7 @@
-/// Bidirectional Threshold
+//! Bidirectional Threshold
 
 use crate::controllers::Controller;
-use crate::types::{Action, Message};
+use crate::types::{Action, CommandError, ControllerState, Message};
 use chrono::{DateTime, Duration, Utc};
 use crate::input::Input;
 use crate::output::Output;
@@ -123,14 +123,21 @@ impl<I, O, O2> BidirectionalThreshold<I, O, O2>
     }
 
     /// Read the input and determine the state of the controller
-    fn get_state(&mut self) -> State {
-        let value = self.input.read().parse::<f32>().unwrap();
+    ///
+    /// Readings that are not a finite number are rejected with the content of a "Read Failed"
+    /// message.
+    fn get_state(&mut self) -> Result<State, String> {
+        let value = self.input.read();
+        let value = match value.trim().parse::<f32>() {
+            Ok(reading) if reading.is_finite() => reading,
+            _ => return Err(format!("Read Failed: invalid reading {:?}", value)),
+        };
         if value > self.threshold + self.tolerance {
-            State::AboveThreshold
+            Ok(State::AboveThreshold)
         } else if value < self.threshold - self.tolerance {
-            State::BelowThreshold
+            Ok(State::BelowThreshold)
         } else {
-            State::WithinTolerance
+            Ok(State::WithinTolerance)
         }
     }
 
@@ -162,7 +169,7 @@ impl<I, O, O2> BidirectionalThreshold<I, O, O2>
     /// If no time is specified, the current time will be used.
     pub fn schedule_next<T>(mut self, time: T) -> Self
     where T: Into<Option<DateTime<Utc>>>{
-        let time= time.into().unwrap_or_else(|| Utc::now());
+        let time= time.into().unwrap_or_else(Utc::now);
         self.schedule_next_in_place(time);
         self
     }
@@ -182,39 +189,97 @@ impl<I, O, O2> Controller for BidirectionalThreshold<I, O, O2>
         self.name.clone()
     }
 
+    fn get_kind(&self) -> &'static str {
+        "BidirectionalThreshold"
+    }
+
     fn poll(&mut self, time: DateTime<Utc>) -> Option<Message> {
         if let Some(event) = self.schedule.attempt_execution(time) {
-            match event.get_action() {
-                Action::Read => {
-                    let msg = match self.get_state() {
-                        State::AboveThreshold => {
-                            self.handle_above_threshold();
-                            "Above Threshold".to_string()
-                        },
-                        State::BelowThreshold => {
-                            self.handle_below_threshold();
-                            "Below Threshold".to_string()
-                        },
-                        State::WithinTolerance => {
-                            self.handle_within_tolerance();
-                            "Within Tolerance".to_string()
-                        },
-                    };
-                    self.schedule_next_in_place(time);
-
-                    let read_state = self.input.get_state().clone();
-                    return Some(Message::new(
-                        self.get_name().unwrap_or_default(),
-                        msg,
-                        event.get_timestamp().clone(),
-                        read_state,
-                    ));
-                }
-                _ => {}
+            if event.get_action() == Action::Read {
+                let msg = match self.get_state() {
+                    Ok(State::AboveThreshold) => {
+                        self.handle_above_threshold();
+                        "Above Threshold".to_string()
+                    },
+                    Ok(State::BelowThreshold) => {
+                        self.handle_below_threshold();
+                        "Below Threshold".to_string()
+                    },
+                    Ok(State::WithinTolerance) => {
+                        self.handle_within_tolerance();
+                        "Within Tolerance".to_string()
+                    },
+                    // leave the outputs untouched
+                    Err(msg) => msg,
+                };
+                self.schedule_next_in_place(time);
+
+                let read_state = self.input.get_state().clone();
+                return Some(Message::new(
+                    self.get_name().unwrap_or_default(),
+                    msg,
+                    *event.get_timestamp(),
+                    read_state,
+                ));
             }
         }
         None
     }
+
+    fn snapshot(&self) -> Option<ControllerState> {
+        Some(ControllerState::new(
+            vec![self.increase_output.get_state(), self.decrease_output.get_state()],
+            self.schedule.get_future_events().clone(),
+        )
+            .with_input(self.input.get_state().clone())
+            .with_setpoint(self.threshold))
+    }
+
+    fn restore(&mut self, state: ControllerState) {
+        if let [increase, decrease] = state.get_outputs().as_slice() {
+            self.increase_output.restore(*increase);
+            self.decrease_output.restore(*decrease);
+        }
+        self.input.restore(state.get_input().clone());
+        if let Some(setpoint) = state.get_setpoint() {
+            self.threshold = setpoint;
+        }
+        self.schedule.restore(state.get_events().clone());
+    }
+
+    fn set_setpoint(&mut self, setpoint: f32) -> Result<(), CommandError> {
+        self.threshold = setpoint;
+        Ok(())
+    }
+
+    fn set_interval(&mut self, interval: Duration) -> Result<(), CommandError> {
+        self.interval = interval;
+        Ok(())
+    }
+
+    /// Forcing on drives the increase output, as if the reading were below the threshold.
+    /// Forcing off drives both outputs off.
+    fn force_output(&mut self, state: bool) -> Result<(), CommandError> {
+        match state {
+            true => self.handle_below_threshold(),
+            false => self.handle_within_tolerance(),
+        }
+        Ok(())
+    }
+
+    fn shutdown(&mut self, time: DateTime<Utc>) -> Option<Message> {
+        let increase = self.increase_output.apply_safe_state();
+        let decrease = self.decrease_output.apply_safe_state();
+        if increase.is_none() && decrease.is_none() {
+            return None;
+        }
+        Some(Message::new(
+            self.get_name().unwrap_or_default(),
+            String::from("Safe State"),
+            time,
+            None,
+        ))
+    }
 }
 
 impl Default for BidirectionalThreshold<fn() -> String, fn(bool), fn(bool)> {
@@ -470,4 +535,17 @@ mod tests {
 
         assert!(message.is_none());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_force_output() {
+        let mut controller = BidirectionalThreshold::default();
+
+        controller.force_output(true).unwrap();
+        assert_eq!(controller.increase_output.get_state(), Some(true));
+        assert_eq!(controller.decrease_output.get_state(), Some(false));
+
+        controller.force_output(false).unwrap();
+        assert_eq!(controller.increase_output.get_state(), Some(false));
+        assert_eq!(controller.decrease_output.get_state(), Some(false));
+    }
+}
@@ -9,18 +9,26 @@
 //! When a controller is polled, it evaluates whether an [`Action`](crate::types::Action) should be performed or not.
 //! If an [`Action`](crate::types::Action) is performed, a [`Message`] is returned for logging.
 //!
+//! Controllers which use an [`AsyncInput`](crate::AsyncInput) or [`AsyncOutput`](crate::AsyncOutput)
+//! implement [`AsyncController`] instead, which allows IO to be awaited within [`AsyncController::poll`].
+//!
 //! The controllers are fully documented and contain potential use-cases, examples, and more detailed information.
-use chrono::{DateTime, Utc};
+use std::future::Future;
+use std::pin::Pin;
+use chrono::{DateTime, Duration, Utc};
 
 mod threshold;
 mod bidirectional;
 mod timed;
+mod async_threshold;
 
 pub use threshold::Threshold;
+pub use async_threshold::AsyncThreshold;
 pub use bidirectional::BidirectionalThreshold;
-pub use timed::TimedOutput;
+pub use timed::{CatchUpPolicy, TimedOutput};
 
-use crate::types::Message;
+use crate::SafeStateAction;
+use crate::types::{CommandError, ControllerState, Message};
 
 /// A trait that represents a named device that can be polled for events
 ///
@@ -39,8 +47,103 @@ pub trait Controller {
     /// Get the name of the controller
     fn get_name(&self) -> Option<String>;
 
+    /// Get the type of the controller
+    ///
+    /// This is used when inspecting a running [`ControllerGroup`](crate::ControllerGroup).
+    fn get_kind(&self) -> &'static str {
+        "Controller"
+    }
+
     /// Poll the controller for events
     ///
     /// The controller should return a `Message` if an event has occurred
     fn poll(&mut self, time: DateTime<Utc>) -> Option<Message>;
-}
\ No newline at end of file
+
+    /// Take a snapshot of the internal state of the controller
+    ///
+    /// The snapshot is persisted by a [`StateStore`](crate::store::StateStore) and passed to
+    /// [`Controller::restore`] when the node restarts. Controllers that do not support persistence
+    /// return `None`, which is the default.
+    fn snapshot(&self) -> Option<ControllerState> {
+        None
+    }
+
+    /// Restore the internal state of the controller from a snapshot
+    ///
+    /// The default implementation ignores the snapshot.
+    fn restore(&mut self, _state: ControllerState) {}
+
+    /// Change the threshold or other setpoint of the controller
+    ///
+    /// The default implementation does not support setpoints.
+    fn set_setpoint(&mut self, _setpoint: f32) -> Result<(), CommandError> {
+        Err(CommandError::Unsupported)
+    }
+
+    /// Change how often the controller reads its input
+    ///
+    /// The default implementation does not support intervals.
+    fn set_interval(&mut self, _interval: Duration) -> Result<(), CommandError> {
+        Err(CommandError::Unsupported)
+    }
+
+    /// Drive the output of the controller to the given state, bypassing the control logic
+    ///
+    /// The default implementation does not support forcing outputs.
+    fn force_output(&mut self, _state: bool) -> Result<(), CommandError> {
+        Err(CommandError::Unsupported)
+    }
+
+    /// Hand back to automatic control after the output has been held manually
+    ///
+    /// Controllers that need to correct their outputs immediately, rather than waiting for the
+    /// next scheduled event, should override this method. The default implementation does nothing.
+    fn resume(&mut self, _time: DateTime<Utc>) -> Option<Message> {
+        None
+    }
+
+    /// Drive all outputs to their declared safe states
+    ///
+    /// This is called once when the [`Runtime`](crate::Runtime) shuts down. A `Message` should be
+    /// returned if any output was changed. The default implementation does nothing.
+    fn shutdown(&mut self, _time: DateTime<Utc>) -> Option<Message> {
+        None
+    }
+}
+
+/// A boxed future that is returned by [`AsyncController`] methods
+pub type PollFuture<'a> = Pin<Box<dyn Future<Output = Option<Message>> + Send + 'a>>;
+
+/// The asynchronous counterpart of [`Controller`]
+///
+/// This is used by controllers that perform IO asynchronously, so that slow devices do not block
+/// the tokio runtime. Async controllers are added with
+/// [`Runtime::add_async_controller`](crate::Runtime::add_async_controller) and each runs as its own
+/// task.
+pub trait AsyncController {
+    /// Set the name of the controller
+    fn set_name(&mut self, name: String);
+
+    /// Get the name of the controller
+    fn get_name(&self) -> Option<String>;
+
+    /// Poll the controller for events
+    ///
+    /// The controller should return a `Message` if an event has occurred
+    fn poll(&mut self, time: DateTime<Utc>) -> PollFuture<'_>;
+
+    /// Drive all outputs to their declared safe states
+    ///
+    /// The default implementation does nothing.
+    fn shutdown(&mut self, _time: DateTime<Utc>) -> PollFuture<'_> {
+        Box::pin(async { None })
+    }
+
+    /// Returns actions which drive the outputs to their declared safe states
+    ///
+    /// These are held outside of the controller, so that the safe states can still be applied if
+    /// the controller panics. The default implementation returns no actions.
+    fn safe_state_actions(&self) -> Vec<SafeStateAction> {
+        Vec::new()
+    }
+}
@@ -3,7 +3,7 @@ use crate::controllers::Controller;
 use crate::input::Input;
 use crate::output::Output;
 use crate::scheduler::Scheduler;
-use crate::types::Message;
+use crate::types::{CommandError, ControllerState, Message};
 
 /// A controller that reads an input and activates an output if the value is above or below a threshold
 ///
@@ -158,13 +158,15 @@ where
     }
 
     /// Read the input and return true if the value is above the threshold
-    fn above_threshold(&mut self) -> bool {
+    ///
+    /// Readings that are not a finite number, such as the `"NaN"` returned by the
+    /// [`drivers`](crate::drivers) when a read fails, are rejected with the content of a
+    /// "Read Failed" message.
+    fn above_threshold(&mut self) -> Result<bool, String> {
         let value = self.input.read();
-        let value = value.parse::<f32>().unwrap();
-        if value > self.threshold {
-            true
-        } else {
-            false
+        match value.trim().parse::<f32>() {
+            Ok(reading) if reading.is_finite() => Ok(reading > self.threshold),
+            _ => Err(format!("Read Failed: invalid reading {:?}", value)),
         }
     }
 
@@ -191,7 +193,7 @@ where
     pub fn schedule_next<T>(mut self, time: T) -> Self
         where T: Into<Option<DateTime<Utc>>>
     {
-        let time= time.into().unwrap_or_else(|| Utc::now());
+        let time= time.into().unwrap_or_else(Utc::now);
         self.schedule.schedule_read(time + self.interval);
         self
     }
@@ -210,6 +212,10 @@ impl<I, O> Controller for Threshold<I, O>
         self.name.clone()
     }
 
+    fn get_kind(&self) -> &'static str {
+        "Threshold"
+    }
+
     /// Read the input and activate the output if the value is above the threshold
     ///
     /// The next read will be scheduled for the specified interval after the current time.
@@ -217,16 +223,18 @@ impl<I, O> Controller for Threshold<I, O>
         if let Some(event) = self.schedule.attempt_execution(time) {
             match event.get_action() {
                 crate::types::Action::Read => {
-                    // Read the input and handle the result
+                    // Read the input and handle the result, leaving the output untouched if the
+                    // read failed
                     let msg = match self.above_threshold() {
-                        true => {
+                        Ok(true) => {
                             self.handle_above_threshold();
                             "Above Threshold".to_string()
                         },
-                        false => {
+                        Ok(false) => {
                             self.handle_below_threshold();
                             "Below Threshold".to_string()
-                        }
+                        },
+                        Err(msg) => msg,
                     };
 
                     // Schedule the next read
@@ -239,13 +247,60 @@ impl<I, O> Controller for Threshold<I, O>
                         msg,
                         time,
                         read_state,
-                    ))
+                    ).set_output_state(self.output.get_state()))
                 }
                 _ => panic!("Encountered unexpected action in threshold controller")
             }
         }
         None
     }
+
+    fn snapshot(&self) -> Option<ControllerState> {
+        Some(ControllerState::new(
+            vec![self.output.get_state()],
+            self.schedule.get_future_events().clone(),
+        )
+            .with_input(self.input.get_state().clone())
+            .with_setpoint(self.threshold))
+    }
+
+    fn restore(&mut self, state: ControllerState) {
+        if let Some(output) = state.get_outputs().first() {
+            self.output.restore(*output);
+        }
+        self.input.restore(state.get_input().clone());
+        if let Some(setpoint) = state.get_setpoint() {
+            self.threshold = setpoint;
+        }
+        self.schedule.restore(state.get_events().clone());
+    }
+
+    fn set_setpoint(&mut self, setpoint: f32) -> Result<(), CommandError> {
+        self.set_threshold(setpoint);
+        Ok(())
+    }
+
+    fn set_interval(&mut self, interval: Duration) -> Result<(), CommandError> {
+        self.interval = interval;
+        Ok(())
+    }
+
+    fn force_output(&mut self, state: bool) -> Result<(), CommandError> {
+        match state {
+            true => self.output.activate(),
+            false => self.output.deactivate(),
+        }
+        Ok(())
+    }
+
+    fn shutdown(&mut self, time: DateTime<Utc>) -> Option<Message> {
+        self.output.apply_safe_state().map(|state| Message::new(
+            self.get_name().unwrap_or_default(),
+            String::from("Safe State"),
+            time,
+            None,
+        ).set_output_state(state))
+    }
 }
 
 impl Default for Threshold<fn() -> String, fn(bool)> {
@@ -261,6 +316,7 @@ impl Default for Threshold<fn() -> String, fn(bool)> {
 
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::clone_on_copy)]
 mod tests {
     use std::collections::VecDeque;
     use std::sync::{Arc, Mutex};
@@ -379,7 +435,7 @@ mod tests {
             Duration::seconds(1)
         );
 
-        assert_eq!(controller.above_threshold(), false);
+        assert_eq!(controller.above_threshold(), Ok(false));
 
         // check when above threshold
         let input = Input::new(|| String::from("10.0"));
@@ -391,7 +447,7 @@ mod tests {
             Duration::seconds(1)
         );
 
-        assert_eq!(controller.above_threshold(), true);
+        assert_eq!(controller.above_threshold(), Ok(true));
     }
 
     #[test]
@@ -563,6 +619,7 @@ mod tests {
         assert!(message.is_some());
         assert_eq!(message.as_ref().unwrap().get_read_state().unwrap(), "0.0");
         assert_eq!(message.as_ref().unwrap().get_content(), "Below Threshold");
+        assert_eq!(message.as_ref().unwrap().get_output_state(), Some(true));
 
         // check before second poll execution
         let message = controller.poll(time + Duration::milliseconds(1500));
@@ -577,6 +634,7 @@ mod tests {
         assert!(message.is_some());
         assert_eq!(message.as_ref().unwrap().get_read_state().unwrap(), "10.0");
         assert_eq!(message.as_ref().unwrap().get_content(), "Above Threshold");
+        assert_eq!(message.as_ref().unwrap().get_output_state(), Some(false));
 
         // check after second read before third read
         let message = controller.poll(time + Duration::microseconds(2500));
@@ -592,4 +650,32 @@ mod tests {
         assert_eq!(message.as_ref().unwrap().get_read_state().unwrap(), "0.0");
         assert_eq!(message.as_ref().unwrap().get_content(), "Below Threshold");
     }
+
+    #[test]
+    fn test_poll_inverted_read_failed() {
+        let input = Input::new(|| f32::NAN.to_string());
+
+        let external_output_state = Arc::new(Mutex::new(None));
+        let output = Output::new(|state| {
+            let mut external_state = external_output_state.lock().unwrap();
+            *external_state = Some(state);
+        });
+
+        let time = Utc::now();
+        let mut controller = Threshold::new_without_scheduled(
+            5.0,
+            input,
+            output,
+            Duration::seconds(1),
+        ).set_inverted().schedule_next(time);
+
+        // a failed read must not be treated as being below the threshold
+        let message = controller.poll(time + Duration::seconds(1)).unwrap();
+        assert_eq!(*external_output_state.lock().unwrap(), None);
+        assert_eq!(message.get_content(), "Read Failed: invalid reading \"NaN\"");
+        assert_eq!(message.get_output_state(), None);
+
+        // the controller keeps reading
+        assert!(controller.poll(time + Duration::seconds(2)).is_some());
+    }
 }
\ No newline at end of file
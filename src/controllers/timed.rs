@@ -2,7 +2,33 @@ use chrono::{DateTime, Duration, NaiveTime, Timelike, Utc};
 use crate::controllers::Controller;
 use crate::output::Output;
 use crate::scheduler::Scheduler;
-use crate::types::Message;
+use crate::types::{Action, CommandError, ControllerState, Event, Message};
+
+/// Determines how a [`TimedOutput`] handles events that were missed
+///
+/// An event is considered missed when it is polled later than the grace period of the controller
+/// (see [`TimedOutput::set_grace_period`]). This typically happens when the node has been rebooted
+/// or when the runtime has stalled past a scheduled event.
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
+pub enum CatchUpPolicy {
+    /// Missed events are discarded without actuating the output
+    ///
+    /// The controller resumes with the next event that lies in the future.
+    Skip,
+
+    /// Missed events are executed once, as soon as they are detected
+    ///
+    /// This is the default behavior.
+    #[default]
+    RunOnce,
+
+    /// The state that the output should currently be in is computed and applied
+    ///
+    /// This also applies on the first poll when the state of the output is unknown, so that an
+    /// output is restored after a restart. For example, a grow light with a 05:00-13:00 window will
+    /// be turned on immediately if the node restarts at 10:00.
+    Reconstruct,
+}
 
 /// Simple controller that turns on an output at a specific time and turns it off after a duration has passed.
 ///
@@ -39,6 +65,8 @@ where F: FnMut(bool) {
     start_time: NaiveTime,
     duration: Duration,
     scheduler: Scheduler,
+    catch_up: CatchUpPolicy,
+    grace_period: Duration,
 }
 
 impl<F> TimedOutput<F>
@@ -53,6 +81,8 @@ where F: FnMut(bool) {
             start_time,
             duration,
             scheduler: Scheduler::new(),
+            catch_up: CatchUpPolicy::default(),
+            grace_period: Duration::minutes(1),
         }.schedule_first(None)
     }
 
@@ -70,9 +100,42 @@ where F: FnMut(bool) {
             start_time,
             duration,
             scheduler: Scheduler::new(),
+            catch_up: CatchUpPolicy::default(),
+            grace_period: Duration::minutes(1),
         }
     }
 
+    /// Builder method to set how missed events are handled
+    ///
+    /// # Example
+    /// ```
+    /// use chrono::{Duration, NaiveTime};
+    /// use equilibrium::controllers::{CatchUpPolicy, TimedOutput};
+    /// use equilibrium::Output;
+    ///
+    /// let output = TimedOutput::new(
+    ///   Output::default(),
+    ///   NaiveTime::from_hms_opt(5, 0, 0).unwrap(),
+    ///   Duration::hours(8),
+    /// ).set_catch_up(CatchUpPolicy::Reconstruct);
+    /// ```
+    pub fn set_catch_up(mut self, policy: CatchUpPolicy) -> Self {
+        self.catch_up = policy;
+        self
+    }
+
+    /// Builder method to set how late an event may be polled before it is considered missed
+    ///
+    /// This should be larger than the polling interval of the runtime. Defaults to 1 minute.
+    pub fn set_grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+
+    pub fn get_catch_up(&self) -> CatchUpPolicy {
+        self.catch_up
+    }
+
     /// Schedule the first event
     pub fn schedule_first<T>(mut self, time: T) -> Self
         where T: Into<Option<DateTime<Utc>>>
@@ -93,7 +156,7 @@ where F: FnMut(bool) {
     fn schedule_on<T>(&mut self, time: T)
         where T: Into<Option<DateTime<Utc>>>
     {
-        let mut time= time.into().unwrap_or_else(|| Utc::now());
+        let mut time= time.into().unwrap_or_else(Utc::now);
         let current_time = time.naive_utc().time();
 
         // calculate the next time the output should be activated
@@ -118,7 +181,7 @@ where F: FnMut(bool) {
     fn schedule_off<T>(&mut self, time: T)
         where T: Into<Option<DateTime<Utc>>>
     {
-        let mut time= time.into().unwrap_or_else(|| Utc::now());
+        let mut time= time.into().unwrap_or_else(Utc::now);
 
         // calculate the next time the output should be deactivated
         time = time.with_hour(self.start_time.hour()).unwrap();
@@ -129,6 +192,61 @@ where F: FnMut(bool) {
         let end_time = time + self.duration;
         self.scheduler.schedule_off(end_time);
     }
+
+    /// Returns the most recent start time that is not after the given time
+    fn last_start(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        let start = time
+            .with_hour(self.start_time.hour()).unwrap()
+            .with_minute(self.start_time.minute()).unwrap()
+            .with_second(self.start_time.second()).unwrap()
+            .with_nanosecond(0).unwrap();
+
+        if start > time {
+            start - Duration::days(1)
+        } else {
+            start
+        }
+    }
+
+    /// Returns true if the output should be active at the given time
+    fn should_be_active(&self, time: DateTime<Utc>) -> bool {
+        time < self.last_start(time) + self.duration
+    }
+
+    /// Returns true if the event was polled later than the grace period allows
+    fn is_missed(&self, event: &Event, time: DateTime<Utc>) -> bool {
+        time - *event.get_timestamp() > self.grace_period
+    }
+
+    /// Discard the current schedule and schedule the next event that lies in the future
+    fn resynchronize(&mut self, time: DateTime<Utc>) {
+        self.scheduler.clear_future_events();
+        if self.should_be_active(time) {
+            let start = self.last_start(time);
+            self.schedule_off(start);
+        } else {
+            self.schedule_on(time);
+        }
+    }
+
+    /// Apply the state that the output should currently be in and resynchronize the schedule
+    fn reconstruct(&mut self, time: DateTime<Utc>) -> Message {
+        let msg = if self.should_be_active(time) {
+            self.output.activate();
+            "Activated"
+        } else {
+            self.output.deactivate();
+            "Deactivated"
+        };
+        self.resynchronize(time);
+
+        Message::new(
+            self.get_name().unwrap_or_default(),
+            String::from(msg),
+            time,
+            None,
+        ).set_output_state(self.output.get_state())
+    }
 }
 
 impl<F> Controller for TimedOutput<F>
@@ -141,15 +259,42 @@ where F: FnMut(bool) {
         self.name.clone()
     }
 
+    fn get_kind(&self) -> &'static str {
+        "TimedOutput"
+    }
+
     fn poll(&mut self, time: DateTime<Utc>) -> Option<Message> {
+        // the output state is unknown (e.g. after a restart), so restore it
+        if self.catch_up == CatchUpPolicy::Reconstruct && self.output.get_state().is_none() {
+            return Some(self.reconstruct(time));
+        }
+
         if let Some(event) = self.scheduler.attempt_execution(time) {
+            if self.is_missed(&event, time) {
+                match self.catch_up {
+                    CatchUpPolicy::Skip => {
+                        self.resynchronize(time);
+                        return Some(Message::new(
+                            self.get_name().unwrap_or_default(),
+                            String::from("Skipped Missed Event"),
+                            time,
+                            None,
+                        ))
+                    },
+                    CatchUpPolicy::Reconstruct => return Some(self.reconstruct(time)),
+                    CatchUpPolicy::RunOnce => {},
+                }
+            }
+
             let msg = match event.get_action() {
-                crate::types::Action::On => {
+                Action::On => {
                     self.output.activate();
-                    self.schedule_off(time);
+                    // the end of the window is derived from when the event was scheduled so
+                    // that a late event does not shift the window
+                    self.schedule_off(*event.get_timestamp());
                     "Activated"
                 },
-                crate::types::Action::Off => {
+                Action::Off => {
                     self.output.deactivate();
                     self.schedule_on(time);
                     "Deactivated"
@@ -163,10 +308,53 @@ where F: FnMut(bool) {
                 String::from(msg),
                 time,
                 None,
-            ))
+            ).set_output_state(self.output.get_state()))
         }
         None
     }
+
+    fn snapshot(&self) -> Option<ControllerState> {
+        Some(ControllerState::new(
+            vec![self.output.get_state()],
+            self.scheduler.get_future_events().clone(),
+        ))
+    }
+
+    /// Restore the output state and schedule
+    ///
+    /// Any events that were missed while the node was down are handled according to the
+    /// [`CatchUpPolicy`] on the next poll. With [`CatchUpPolicy::Reconstruct`], the saved output
+    /// state is discarded, and the state is computed from the window on the next poll instead.
+    fn restore(&mut self, state: ControllerState) {
+        match (self.catch_up, state.get_outputs().first()) {
+            (CatchUpPolicy::Reconstruct, _) => self.output.restore(None),
+            (_, Some(output)) => self.output.restore(*output),
+            (_, None) => {},
+        }
+        self.scheduler.restore(state.get_events().clone());
+    }
+
+    fn force_output(&mut self, state: bool) -> Result<(), CommandError> {
+        match state {
+            true => self.output.activate(),
+            false => self.output.deactivate(),
+        }
+        Ok(())
+    }
+
+    /// Restore the state that the output should be in, since the next event may be hours away
+    fn resume(&mut self, time: DateTime<Utc>) -> Option<Message> {
+        Some(self.reconstruct(time))
+    }
+
+    fn shutdown(&mut self, time: DateTime<Utc>) -> Option<Message> {
+        self.output.apply_safe_state().map(|state| Message::new(
+            self.get_name().unwrap_or_default(),
+            String::from("Safe State"),
+            time,
+            None,
+        ).set_output_state(state))
+    }
 }
 
 impl Default for TimedOutput<fn(bool)> {
@@ -176,6 +364,7 @@ impl Default for TimedOutput<fn(bool)> {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use chrono::TimeZone;
     use super::*;
@@ -267,4 +456,166 @@ mod tests {
         assert!(message.is_none());
     }
 
+
+    #[test]
+    fn test_set_catch_up() {
+        let controller = TimedOutput::default();
+        assert_eq!(controller.get_catch_up(), CatchUpPolicy::RunOnce);
+
+        let controller = TimedOutput::default().set_catch_up(CatchUpPolicy::Skip);
+        assert_eq!(controller.get_catch_up(), CatchUpPolicy::Skip);
+    }
+
+    #[test]
+    fn test_catch_up_run_once() {
+        let time = Utc.with_ymd_and_hms(2021, 1, 1, 4, 0, 0).unwrap();
+
+        let start_time = NaiveTime::from_hms_opt(5, 0, 0).unwrap();
+        let duration = Duration::hours(8);
+        let mut output = TimedOutput::new_without_scheduled(
+            Output::default(),
+            start_time,
+            duration,
+        ).schedule_first(time);
+
+        // runtime stalls until 10:00AM, the missed event is executed late
+        let time = Utc.with_ymd_and_hms(2021, 1, 1, 10, 0, 0).unwrap();
+        let message = output.poll(time);
+        assert!(output.output.get_state().unwrap());
+        assert_eq!(message.unwrap().get_content(), "Activated");
+
+        // the window is not shifted by the late execution
+        let time = Utc.with_ymd_and_hms(2021, 1, 1, 13, 0, 0).unwrap();
+        let message = output.poll(time);
+        assert!(!output.output.get_state().unwrap());
+        assert_eq!(message.unwrap().get_content(), "Deactivated");
+
+        // next activation occurs on the following day
+        let next = output.scheduler.get_future_events()[0].clone();
+        assert_eq!(next.get_action(), Action::On);
+        assert_eq!(next.get_timestamp(), &Utc.with_ymd_and_hms(2021, 1, 2, 5, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn test_catch_up_skip() {
+        let time = Utc.with_ymd_and_hms(2021, 1, 1, 4, 0, 0).unwrap();
+
+        let start_time = NaiveTime::from_hms_opt(5, 0, 0).unwrap();
+        let duration = Duration::hours(8);
+        let mut output = TimedOutput::new_without_scheduled(
+            Output::default(),
+            start_time,
+            duration,
+        )
+            .set_catch_up(CatchUpPolicy::Skip)
+            .schedule_first(time);
+
+        // runtime stalls until 2:00PM, after the window has ended
+        let time = Utc.with_ymd_and_hms(2021, 1, 1, 14, 0, 0).unwrap();
+        let message = output.poll(time);
+        assert!(output.output.get_state().is_none());
+        assert_eq!(message.unwrap().get_content(), "Skipped Missed Event");
+
+        // nothing else happens until the next day
+        let time = Utc.with_ymd_and_hms(2021, 1, 2, 4, 59, 59).unwrap();
+        assert!(output.poll(time).is_none());
+
+        let time = Utc.with_ymd_and_hms(2021, 1, 2, 5, 0, 0).unwrap();
+        let message = output.poll(time);
+        assert!(output.output.get_state().unwrap());
+        assert_eq!(message.unwrap().get_content(), "Activated");
+    }
+
+    #[test]
+    fn test_catch_up_reconstruct_after_restart() {
+        let start_time = NaiveTime::from_hms_opt(5, 0, 0).unwrap();
+        let duration = Duration::hours(8);
+
+        // node restarts at 10:00AM, within the window
+        let time = Utc.with_ymd_and_hms(2021, 1, 1, 10, 0, 0).unwrap();
+        let mut output = TimedOutput::new_without_scheduled(
+            Output::default(),
+            start_time,
+            duration,
+        )
+            .set_catch_up(CatchUpPolicy::Reconstruct)
+            .schedule_first(time);
+
+        let message = output.poll(time);
+        assert!(output.output.get_state().unwrap());
+        assert_eq!(message.unwrap().get_content(), "Activated");
+
+        // output is deactivated at the end of the window
+        let time = Utc.with_ymd_and_hms(2021, 1, 1, 13, 0, 0).unwrap();
+        let message = output.poll(time);
+        assert!(!output.output.get_state().unwrap());
+        assert_eq!(message.unwrap().get_content(), "Deactivated");
+
+        // node restarts at 2:00PM, outside the window
+        let time = Utc.with_ymd_and_hms(2021, 1, 1, 14, 0, 0).unwrap();
+        let mut output = TimedOutput::new_without_scheduled(
+            Output::default(),
+            start_time,
+            duration,
+        )
+            .set_catch_up(CatchUpPolicy::Reconstruct)
+            .schedule_first(time);
+
+        let message = output.poll(time);
+        assert!(!output.output.get_state().unwrap());
+        assert_eq!(message.unwrap().get_content(), "Deactivated");
+    }
+
+    #[test]
+    fn test_catch_up_reconstruct_after_restore() {
+        let start_time = NaiveTime::from_hms_opt(5, 0, 0).unwrap();
+        let duration = Duration::hours(8);
+
+        // the snapshot holds the safe state along with the pending deactivation
+        let time = Utc.with_ymd_and_hms(2021, 1, 1, 10, 0, 0).unwrap();
+        let state = ControllerState::new(
+            vec![Some(false)],
+            vec![Event::new(Action::Off, Utc.with_ymd_and_hms(2021, 1, 1, 13, 0, 0).unwrap())],
+        );
+
+        let mut output = TimedOutput::new_without_scheduled(
+            Output::default(),
+            start_time,
+            duration,
+        ).set_catch_up(CatchUpPolicy::Reconstruct);
+        output.restore(state);
+
+        let message = output.poll(time);
+        assert!(output.output.get_state().unwrap());
+        assert_eq!(message.unwrap().get_content(), "Activated");
+    }
+
+    #[test]
+    fn test_catch_up_reconstruct_after_stall() {
+        let time = Utc.with_ymd_and_hms(2021, 1, 1, 4, 0, 0).unwrap();
+
+        let start_time = NaiveTime::from_hms_opt(5, 0, 0).unwrap();
+        let duration = Duration::hours(8);
+        let mut output = TimedOutput::new_without_scheduled(
+            Output::default(),
+            start_time,
+            duration,
+        )
+            .set_catch_up(CatchUpPolicy::Reconstruct)
+            .schedule_first(time);
+
+        // output state is known before the stall
+        output.output.deactivate();
+
+        // runtime stalls past the entire window
+        let time = Utc.with_ymd_and_hms(2021, 1, 1, 14, 0, 0).unwrap();
+        let message = output.poll(time);
+        assert!(!output.output.get_state().unwrap());
+        assert_eq!(message.unwrap().get_content(), "Deactivated");
+
+        let next = output.scheduler.get_future_events()[0].clone();
+        assert_eq!(next.get_action(), Action::On);
+        assert_eq!(next.get_timestamp(), &Utc.with_ymd_and_hms(2021, 1, 2, 5, 0, 0).unwrap());
+    }
+
 }
\ No newline at end of file
@@ -0,0 +1,196 @@
+//! GPIO lines via the Linux character-device API
+//!
+//! Lines are requested from a GPIO chip such as `/dev/gpiochip0`, and are held for as long as the
+//! resulting [`Input`] or [`Output`] exists. Unlike the deprecated sysfs GPIO interface, lines are
+//! released automatically when the process exits.
+//!
+//! # Example
+//! ```no_run
+//! use equilibrium::drivers::gpio::Gpio;
+//!
+//! // a relay board which switches on when the line is driven low
+//! let pump = Gpio::new("/dev/gpiochip0", 17)
+//!     .set_active_low()
+//!     .output()
+//!     .unwrap();
+//! ```
+use std::path::PathBuf;
+use gpio_cdev::{Chip, LineHandle, LineRequestFlags};
+use crate::{Input, Output};
+use crate::types::DriverError;
+
+/// Name that lines are requested with, shown by tools such as `gpioinfo`
+const CONSUMER: &str = "equilibrium";
+
+impl From<gpio_cdev::Error> for DriverError {
+    fn from(e: gpio_cdev::Error) -> Self {
+        DriverError::Failed(e.to_string())
+    }
+}
+
+/// The value of a requested line, which is stood in for by a mock in tests
+trait Line {
+    fn get_value(&self) -> Result<u8, gpio_cdev::Error>;
+
+    fn set_value(&self, value: u8) -> Result<(), gpio_cdev::Error>;
+}
+
+impl Line for LineHandle {
+    fn get_value(&self) -> Result<u8, gpio_cdev::Error> {
+        LineHandle::get_value(self)
+    }
+
+    fn set_value(&self, value: u8) -> Result<(), gpio_cdev::Error> {
+        LineHandle::set_value(self, value)
+    }
+}
+
+/// A line of a GPIO chip
+#[derive(Debug, Clone)]
+pub struct Gpio {
+    chip: PathBuf,
+    line: u32,
+    active_low: bool,
+}
+
+impl Gpio {
+    /// Describe a GPIO line
+    ///
+    /// # Arguments
+    /// * `chip` - Character device of the chip, such as `/dev/gpiochip0`
+    /// * `line` - Offset of the line on the chip
+    pub fn new<P>(chip: P, line: u32) -> Self
+        where P: Into<PathBuf>
+    {
+        Self {
+            chip: chip.into(),
+            line,
+            active_low: false,
+        }
+    }
+
+    /// Builder method to treat a low level as active
+    pub fn set_active_low(mut self) -> Self {
+        self.active_low = true;
+        self
+    }
+
+    fn request(&self, flags: LineRequestFlags) -> Result<LineHandle, DriverError> {
+        let flags = match self.active_low {
+            true => flags | LineRequestFlags::ACTIVE_LOW,
+            false => flags,
+        };
+        let handle = Chip::new(&self.chip)?
+            .get_line(self.line)?
+            .request(flags, 0, CONSUMER)?;
+        Ok(handle)
+    }
+
+    /// Request the line as an output, and create an [`Output`] which drives it
+    ///
+    /// The line is inactive until the output is activated.
+    pub fn output(self) -> Result<Output<impl FnMut(bool) + Send>, DriverError> {
+        let handle = self.request(LineRequestFlags::OUTPUT)?;
+        Ok(drive(handle, self.line))
+    }
+
+    /// Request the line as an input, and create an [`Input`] which reads `"1"` when it is active
+    pub fn input(self) -> Result<Input<impl Fn() -> String + Send + Sync>, DriverError> {
+        let handle = self.request(LineRequestFlags::INPUT)?;
+        Ok(sense(handle, self.line))
+    }
+}
+
+/// Create an [`Output`] which writes the line at `offset`
+fn drive<L>(line: L, offset: u32) -> Output<impl FnMut(bool) + Send>
+    where L: Line + Send
+{
+    Output::new(move |state| {
+        if let Err(e) = line.set_value(state as u8) {
+            eprintln!("Failed to write GPIO line {}: {}", offset, e);
+        }
+    })
+}
+
+/// Create an [`Input`] which reads the line at `offset`
+fn sense<L>(line: L, offset: u32) -> Input<impl Fn() -> String + Send + Sync>
+    where L: Line + Send + Sync
+{
+    Input::new(move || match line.get_value() {
+        Ok(value) => value.to_string(),
+        Err(e) => {
+            eprintln!("Failed to read GPIO line {}: {}", offset, e);
+            f32::NAN.to_string()
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use super::*;
+
+    /// A line which holds its value in memory, optionally failing every access
+    #[derive(Clone, Default)]
+    struct MockLine {
+        value: Arc<Mutex<u8>>,
+        failing: bool,
+    }
+
+    impl MockLine {
+        fn check(&self) -> Result<(), gpio_cdev::Error> {
+            match self.failing {
+                true => Err(std::io::Error::from(std::io::ErrorKind::BrokenPipe).into()),
+                false => Ok(()),
+            }
+        }
+    }
+
+    impl Line for MockLine {
+        fn get_value(&self) -> Result<u8, gpio_cdev::Error> {
+            self.check()?;
+            Ok(*self.value.lock().unwrap())
+        }
+
+        fn set_value(&self, value: u8) -> Result<(), gpio_cdev::Error> {
+            self.check()?;
+            *self.value.lock().unwrap() = value;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_output() {
+        let line = MockLine::default();
+        let mut output = drive(line.clone(), 17);
+
+        output.activate();
+        assert_eq!(*line.value.lock().unwrap(), 1);
+        output.deactivate();
+        assert_eq!(*line.value.lock().unwrap(), 0);
+
+        // a failed write is logged rather than propagated
+        let mut output = drive(MockLine { failing: true, ..line }, 17);
+        output.activate();
+    }
+
+    #[test]
+    fn test_input() {
+        let line = MockLine::default();
+        let mut input = sense(line.clone(), 4);
+
+        assert_eq!(input.read(), "0");
+        *line.value.lock().unwrap() = 1;
+        assert_eq!(input.read(), "1");
+
+        let mut input = sense(MockLine { failing: true, ..line }, 4);
+        assert_eq!(input.read(), "NaN");
+    }
+
+    #[test]
+    fn test_missing_chip() {
+        let gpio = Gpio::new("/dev/equilibrium-missing-gpiochip", 0);
+        assert!(gpio.clone().output().is_err());
+        assert!(gpio.input().is_err());
+    }
+}
@@ -0,0 +1,16 @@
+//! Ready-made drivers for common devices
+//!
+//! Each driver produces [`Input`](crate::Input) and [`Output`](crate::Output) instances, as well as
+//! their asynchronous counterparts where IO may be slow, so that they can be passed to any
+//! controller.
+//!
+//! Synchronous inputs cannot report errors. When a read fails, the error is logged and `"NaN"` is
+//! returned, which the controllers reject as a "Read Failed" message, leaving the output
+//! untouched. Use the asynchronous variants where the cause of the failure should be reported.
+pub mod modbus;
+#[cfg(target_os = "linux")]
+pub mod gpio;
+pub mod pwm;
+pub mod w1;
+#[cfg(unix)]
+pub mod serial;
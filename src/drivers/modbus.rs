@@ -0,0 +1,825 @@
+//! Modbus RTU and TCP drivers
+//!
+//! A [`ModbusClient`] owns a connection to a Modbus TCP server or an RTU serial line, and is cheap
+//! to clone so that many inputs and outputs may share it. Requests are serialized, failed requests
+//! are retried on a fresh connection, and the connection is re-established lazily.
+//!
+//! A [`Register`] describes how a value is decoded: its address, [`DataType`], [`ByteOrder`],
+//! scaling and offset. Registers which are next to each other should be read with a
+//! [`ModbusBatch`], so that a single request is made for the whole block.
+//!
+//! # Example
+//! ```no_run
+//! use chrono::Duration;
+//! use equilibrium::controllers::Threshold;
+//! use equilibrium::drivers::modbus::{DataType, ModbusClient, Register};
+//!
+//! let client = ModbusClient::tcp(([192, 168, 1, 10], 502))
+//!     .set_retries(2);
+//!
+//! // temperature is reported in tenths of a degree
+//! let temperature = Register::input(0, DataType::I16).set_scale(0.1);
+//!
+//! let heater = Threshold::new(
+//!     20.0,
+//!     client.input(1, temperature),
+//!     client.coil_output(2, 0),
+//!     Duration::seconds(10),
+//! ).set_inverted();
+//! ```
+use std::fs::OpenOptions;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use chrono::Duration;
+use crate::{AsyncInput, AsyncOutput, Input, Output};
+use crate::types::DriverError;
+
+const READ_HOLDING_REGISTERS: u8 = 0x03;
+const READ_INPUT_REGISTERS: u8 = 0x04;
+const WRITE_SINGLE_COIL: u8 = 0x05;
+const WRITE_SINGLE_REGISTER: u8 = 0x06;
+
+/// The table that a register is read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterKind {
+    /// Read/write registers, read with function code 3
+    Holding,
+
+    /// Read-only registers, read with function code 4
+    Input,
+}
+
+impl RegisterKind {
+    fn function(&self) -> u8 {
+        match self {
+            RegisterKind::Holding => READ_HOLDING_REGISTERS,
+            RegisterKind::Input => READ_INPUT_REGISTERS,
+        }
+    }
+}
+
+/// How the raw register contents are interpreted
+///
+/// 32-bit types span two consecutive registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataType {
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+}
+
+impl DataType {
+    /// Number of registers occupied by the type
+    fn words(&self) -> u16 {
+        match self {
+            DataType::U16 | DataType::I16 => 1,
+            DataType::U32 | DataType::I32 | DataType::F32 => 2,
+        }
+    }
+}
+
+/// Order of the bytes of a value, where `A` is the most significant byte
+///
+/// Modbus transmits each register big-endian, however devices differ in how 32-bit values are
+/// split across registers. For 16-bit types, only the order of the bytes within the register is
+/// relevant, so `Abcd` is equivalent to `Cdab` and `Badc` is equivalent to `Dcba`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ByteOrder {
+    /// Big-endian, as defined by the Modbus specification
+    #[default]
+    Abcd,
+
+    /// Big-endian bytes with the least significant register first
+    Cdab,
+
+    /// Little-endian bytes with the most significant register first
+    Badc,
+
+    /// Little-endian
+    Dcba,
+}
+
+/// Describes where a value is located and how it is decoded
+#[derive(Debug, Clone, PartialEq)]
+pub struct Register {
+    kind: RegisterKind,
+    address: u16,
+    data_type: DataType,
+    byte_order: ByteOrder,
+    scale: f32,
+    offset: f32,
+}
+
+impl Register {
+    /// Create a holding register with a scale of 1 and no offset
+    pub fn holding(address: u16, data_type: DataType) -> Self {
+        Self::new(RegisterKind::Holding, address, data_type)
+    }
+
+    /// Create an input register with a scale of 1 and no offset
+    pub fn input(address: u16, data_type: DataType) -> Self {
+        Self::new(RegisterKind::Input, address, data_type)
+    }
+
+    fn new(kind: RegisterKind, address: u16, data_type: DataType) -> Self {
+        Self {
+            kind,
+            address,
+            data_type,
+            byte_order: ByteOrder::default(),
+            scale: 1.0,
+            offset: 0.0,
+        }
+    }
+
+    /// Builder method to set the byte order of the value
+    pub fn set_byte_order(mut self, byte_order: ByteOrder) -> Self {
+        self.byte_order = byte_order;
+        self
+    }
+
+    /// Builder method to set the factor that the raw value is multiplied by
+    pub fn set_scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// Builder method to set the offset that is added to the scaled value
+    pub fn set_offset(mut self, offset: f32) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn get_address(&self) -> u16 {
+        self.address
+    }
+
+    /// Decode the value from the contents of the registers that it occupies
+    ///
+    /// # Panics
+    /// If fewer registers are given than are occupied by the data type.
+    pub fn decode(&self, words: &[u16]) -> f32 {
+        let mut bytes: Vec<u8> = words[..self.data_type.words() as usize].iter()
+            .flat_map(|word| word.to_be_bytes())
+            .collect();
+        match self.byte_order {
+            ByteOrder::Abcd => {},
+            ByteOrder::Dcba => bytes.reverse(),
+            ByteOrder::Badc => bytes.chunks_mut(2).for_each(|word| word.swap(0, 1)),
+            ByteOrder::Cdab => if bytes.len() == 4 {
+                bytes.swap(0, 2);
+                bytes.swap(1, 3);
+            },
+        }
+
+        let raw = match self.data_type {
+            DataType::U16 => u16::from_be_bytes([bytes[0], bytes[1]]) as f32,
+            DataType::I16 => i16::from_be_bytes([bytes[0], bytes[1]]) as f32,
+            DataType::U32 => u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32,
+            DataType::I32 => i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32,
+            DataType::F32 => f32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        };
+        raw * self.scale + self.offset
+    }
+}
+
+/// CRC used by RTU framing, transmitted least significant byte first
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFF;
+    for byte in data {
+        crc ^= *byte as u16;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xA001,
+                _ => crc >> 1,
+            };
+        }
+    }
+    crc
+}
+
+trait Stream: Read + Write + Send {}
+
+impl<T> Stream for T where T: Read + Write + Send {}
+
+type Connector = Box<dyn Fn(std::time::Duration) -> io::Result<Box<dyn Stream>> + Send>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Framing {
+    Tcp,
+    Rtu,
+}
+
+struct Connection {
+    connect: Connector,
+    framing: Framing,
+    stream: Option<Box<dyn Stream>>,
+    transaction: u16,
+    timeout: Duration,
+    retries: u32,
+}
+
+impl Connection {
+    /// Send a request, retrying on a fresh connection if it fails
+    fn transact(&mut self, unit: u8, request: &[u8]) -> Result<Vec<u8>, DriverError> {
+        let mut error = DriverError::Failed(String::from("no attempt was made"));
+        for _ in 0..=self.retries {
+            match self.attempt(unit, request) {
+                Ok(response) => return check_response(request, response),
+                Err(e) => {
+                    self.stream = None;
+                    error = e.into();
+                }
+            }
+        }
+        Err(error)
+    }
+
+    fn attempt(&mut self, unit: u8, request: &[u8]) -> io::Result<Vec<u8>> {
+        if self.stream.is_none() {
+            let timeout = self.timeout.to_std().unwrap_or_default();
+            self.stream = Some((self.connect)(timeout)?);
+        }
+        let stream = self.stream.as_mut().unwrap();
+
+        match self.framing {
+            Framing::Tcp => {
+                self.transaction = self.transaction.wrapping_add(1);
+                let mut frame = Vec::with_capacity(request.len() + 7);
+                frame.extend(self.transaction.to_be_bytes());
+                frame.extend([0, 0]);
+                frame.extend((request.len() as u16 + 1).to_be_bytes());
+                frame.push(unit);
+                frame.extend(request);
+                stream.write_all(&frame)?;
+
+                let mut header = [0; 7];
+                stream.read_exact(&mut header)?;
+                if header[..2] != self.transaction.to_be_bytes() || header[6] != unit {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "mismatched response"));
+                }
+                let length = u16::from_be_bytes([header[4], header[5]]).saturating_sub(1);
+                let mut response = vec![0; length as usize];
+                stream.read_exact(&mut response)?;
+                Ok(response)
+            }
+            Framing::Rtu => {
+                let mut frame = vec![unit];
+                frame.extend(request);
+                frame.extend(crc16(&frame).to_le_bytes());
+                stream.write_all(&frame)?;
+
+                let mut response = vec![0; 2];
+                stream.read_exact(&mut response)?;
+                let remaining = match response[1] {
+                    function if function & 0x80 != 0 => 1,
+                    READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS => {
+                        let mut count = [0];
+                        stream.read_exact(&mut count)?;
+                        response.push(count[0]);
+                        count[0] as usize
+                    }
+                    _ => 4,
+                };
+                let start = response.len();
+                response.resize(start + remaining + 2, 0);
+                stream.read_exact(&mut response[start..])?;
+
+                let (body, crc) = response.split_at(response.len() - 2);
+                if crc16(body).to_le_bytes() != crc || body[0] != unit {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid response frame"));
+                }
+                Ok(body[1..].to_vec())
+            }
+        }
+    }
+}
+
+/// Ensure that the response answers the request, and is not an exception
+fn check_response(request: &[u8], response: Vec<u8>) -> Result<Vec<u8>, DriverError> {
+    match response.first() {
+        Some(function) if *function == request[0] | 0x80 => Err(DriverError::Failed(format!(
+            "Modbus exception code {}",
+            response.get(1).copied().unwrap_or_default(),
+        ))),
+        Some(function) if *function == request[0] => Ok(response),
+        _ => Err(DriverError::Failed(String::from("unexpected Modbus response"))),
+    }
+}
+
+/// A connection to a Modbus TCP server or RTU serial line
+///
+/// See the [module documentation](self) for details.
+#[derive(Clone)]
+pub struct ModbusClient {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl ModbusClient {
+    fn new(framing: Framing, connect: Connector) -> Self {
+        let connection = Connection {
+            connect,
+            framing,
+            stream: None,
+            transaction: 0,
+            timeout: Duration::seconds(1),
+            retries: 1,
+        };
+        Self { connection: Arc::new(Mutex::new(connection)) }
+    }
+
+    /// Create a client for a Modbus TCP server
+    ///
+    /// The connection is established when the first request is made.
+    pub fn tcp<A>(addr: A) -> Self
+        where A: Into<SocketAddr>
+    {
+        let addr = addr.into();
+        Self::new(Framing::Tcp, Box::new(move |timeout| {
+            let stream = TcpStream::connect_timeout(&addr, timeout)?;
+            stream.set_read_timeout(Some(timeout))?;
+            stream.set_write_timeout(Some(timeout))?;
+            Ok(Box::new(stream))
+        }))
+    }
+
+    /// Create a client for an RTU serial line, such as `/dev/ttyUSB0`
+    ///
+    /// The baud rate and framing of the port are not configured by the client, and should be set
+    /// beforehand, for example with `stty`. The port should be configured to return from a read
+    /// after an inter-byte timeout, otherwise an unresponsive device blocks the request.
+    pub fn rtu<P>(path: P) -> Self
+        where P: Into<PathBuf>
+    {
+        let path = path.into();
+        Self::new(Framing::Rtu, Box::new(move |_| {
+            let port = OpenOptions::new().read(true).write(true).open(&path)?;
+            Ok(Box::new(port))
+        }))
+    }
+
+    /// Builder method to set how many times a failed request is retried
+    ///
+    /// Defaults to 1. Exception responses from the device are not retried.
+    pub fn set_retries(self, retries: u32) -> Self {
+        self.connection.lock().unwrap().retries = retries;
+        self
+    }
+
+    /// Builder method to set how long connecting, and each read or write, may take
+    ///
+    /// Defaults to 1 second. Only applies to TCP connections.
+    pub fn set_timeout(self, timeout: Duration) -> Self {
+        self.connection.lock().unwrap().timeout = timeout;
+        self
+    }
+
+    fn transact(&self, unit: u8, request: &[u8]) -> Result<Vec<u8>, DriverError> {
+        self.connection.lock()
+            .map_err(|e| DriverError::Failed(e.to_string()))?
+            .transact(unit, request)
+    }
+
+    /// Read the raw contents of contiguous registers
+    pub fn read_registers(&self, unit: u8, kind: RegisterKind, address: u16, count: u16) -> Result<Vec<u16>, DriverError> {
+        let mut request = vec![kind.function()];
+        request.extend(address.to_be_bytes());
+        request.extend(count.to_be_bytes());
+
+        let response = self.transact(unit, &request)?;
+        let data = response.get(2..).unwrap_or_default();
+        if data.len() != count as usize * 2 {
+            return Err(DriverError::Failed(String::from("unexpected number of registers")));
+        }
+        Ok(data.chunks(2).map(|word| u16::from_be_bytes([word[0], word[1]])).collect())
+    }
+
+    /// Read and decode a single value
+    pub fn read(&self, unit: u8, register: &Register) -> Result<f32, DriverError> {
+        let words = self.read_registers(unit, register.kind, register.address, register.data_type.words())?;
+        Ok(register.decode(&words))
+    }
+
+    /// Write a single coil
+    pub fn write_coil(&self, unit: u8, address: u16, state: bool) -> Result<(), DriverError> {
+        let value: u16 = match state {
+            true => 0xFF00,
+            false => 0x0000,
+        };
+        let mut request = vec![WRITE_SINGLE_COIL];
+        request.extend(address.to_be_bytes());
+        request.extend(value.to_be_bytes());
+        self.transact(unit, &request).map(|_| ())
+    }
+
+    /// Write a single holding register
+    pub fn write_register(&self, unit: u8, address: u16, value: u16) -> Result<(), DriverError> {
+        let mut request = vec![WRITE_SINGLE_REGISTER];
+        request.extend(address.to_be_bytes());
+        request.extend(value.to_be_bytes());
+        self.transact(unit, &request).map(|_| ())
+    }
+
+    /// Create an [`Input`] which reads a value from a device
+    pub fn input(&self, unit: u8, register: Register) -> Input<impl Fn() -> String + Send + Sync> {
+        let client = self.clone();
+        Input::new(move || match client.read(unit, &register) {
+            Ok(value) => value.to_string(),
+            Err(e) => {
+                eprintln!("Failed to read Modbus register {}: {}", register.address, e);
+                f32::NAN.to_string()
+            }
+        })
+    }
+
+    /// Create an [`AsyncInput`] which reads a value from a device
+    pub fn async_input(&self, unit: u8, register: Register) -> AsyncInput {
+        let client = self.clone();
+        AsyncInput::new(move || {
+            let client = client.clone();
+            let register = register.clone();
+            async move {
+                tokio::task::spawn_blocking(move || client.read(unit, &register))
+                    .await
+                    .map_err(|e| DriverError::Failed(e.to_string()))?
+                    .map(|value| value.to_string())
+            }
+        })
+    }
+
+    /// Create an [`Output`] which writes a coil, such as a relay
+    pub fn coil_output(&self, unit: u8, address: u16) -> Output<impl FnMut(bool) + Send> {
+        let client = self.clone();
+        Output::new(move |state| {
+            if let Err(e) = client.write_coil(unit, address, state) {
+                eprintln!("Failed to write Modbus coil {}: {}", address, e);
+            }
+        })
+    }
+
+    /// Create an [`AsyncOutput`] which writes a coil, such as a relay
+    pub fn async_coil_output(&self, unit: u8, address: u16) -> AsyncOutput {
+        let client = self.clone();
+        AsyncOutput::new(move |state| {
+            let client = client.clone();
+            async move {
+                tokio::task::spawn_blocking(move || client.write_coil(unit, address, state))
+                    .await
+                    .map_err(|e| DriverError::Failed(e.to_string()))?
+            }
+        })
+    }
+
+    /// Create a [`ModbusBatch`] which reads a block of contiguous registers with a single request
+    ///
+    /// # Arguments
+    /// * `unit` - Address of the device
+    /// * `kind` - Table that the registers are read from
+    /// * `address` - Address of the first register
+    /// * `count` - Number of registers in the block
+    /// * `max_age` - How long the block may be reused before it is read again
+    pub fn batch(&self, unit: u8, kind: RegisterKind, address: u16, count: u16, max_age: Duration) -> ModbusBatch {
+        ModbusBatch {
+            client: self.clone(),
+            unit,
+            kind,
+            address,
+            count,
+            max_age: max_age.to_std().unwrap_or_default(),
+            cache: Arc::new(Mutex::new(None)),
+        }
+    }
+}
+
+impl std::fmt::Debug for ModbusClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let connection = self.connection.lock().unwrap();
+        f.debug_struct("ModbusClient")
+            .field("framing", &connection.framing)
+            .field("connected", &connection.stream.is_some())
+            .field("timeout", &connection.timeout)
+            .field("retries", &connection.retries)
+            .finish()
+    }
+}
+
+/// The contents of a block and the time at which it was read
+type Block = (Instant, Vec<u16>);
+
+/// A block of contiguous registers that is read with a single request
+///
+/// Every input created from the batch decodes its value from the cached block, which is read
+/// again once it is older than the maximum age.
+#[derive(Debug, Clone)]
+pub struct ModbusBatch {
+    client: ModbusClient,
+    unit: u8,
+    kind: RegisterKind,
+    address: u16,
+    count: u16,
+    max_age: std::time::Duration,
+    cache: Arc<Mutex<Option<Block>>>,
+}
+
+impl ModbusBatch {
+    /// Read and decode a value from the block
+    pub fn read(&self, register: &Register) -> Result<f32, DriverError> {
+        // the end is computed as a usize, as it may not fit into a register address
+        let start = register.address.checked_sub(self.address)
+            .map(usize::from)
+            .filter(|start| {
+                register.kind == self.kind
+                    && start + usize::from(register.data_type.words()) <= usize::from(self.count)
+            })
+            .ok_or_else(|| DriverError::Failed(format!("register {} is outside of the batch", register.address)))?;
+
+        let mut cache = self.cache.lock().map_err(|e| DriverError::Failed(e.to_string()))?;
+        let fresh = cache.as_ref().is_some_and(|(time, _)| time.elapsed() <= self.max_age);
+        if !fresh {
+            let words = self.client.read_registers(self.unit, self.kind, self.address, self.count)?;
+            *cache = Some((Instant::now(), words));
+        }
+
+        let (_, words) = cache.as_ref().unwrap();
+        Ok(register.decode(&words[start..]))
+    }
+
+    /// Create an [`Input`] which reads a value from the block
+    pub fn input(&self, register: Register) -> Input<impl Fn() -> String + Send + Sync> {
+        let batch = self.clone();
+        Input::new(move || match batch.read(&register) {
+            Ok(value) => value.to_string(),
+            Err(e) => {
+                eprintln!("Failed to read Modbus register {}: {}", register.address, e);
+                f32::NAN.to_string()
+            }
+        })
+    }
+
+    /// Create an [`AsyncInput`] which reads a value from the block
+    pub fn async_input(&self, register: Register) -> AsyncInput {
+        let batch = self.clone();
+        AsyncInput::new(move || {
+            let batch = batch.clone();
+            let register = register.clone();
+            async move {
+                tokio::task::spawn_blocking(move || batch.read(&register))
+                    .await
+                    .map_err(|e| DriverError::Failed(e.to_string()))?
+                    .map(|value| value.to_string())
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    #[cfg(unix)]
+    use std::os::unix::net::UnixStream;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use super::*;
+
+    /// A Modbus device with 16 registers and 16 coils
+    #[derive(Default)]
+    struct Simulator {
+        registers: Mutex<Vec<u16>>,
+        coils: Mutex<Vec<bool>>,
+        requests: AtomicUsize,
+    }
+
+    impl Simulator {
+        fn new(registers: &[u16]) -> Arc<Self> {
+            let mut contents = registers.to_vec();
+            contents.resize(16, 0);
+            Arc::new(Self {
+                registers: Mutex::new(contents),
+                coils: Mutex::new(vec![false; 16]),
+                requests: AtomicUsize::new(0),
+            })
+        }
+
+        /// Answer a request PDU
+        fn handle(&self, request: &[u8]) -> Vec<u8> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            let function = request[0];
+            let address = u16::from_be_bytes([request[1], request[2]]) as usize;
+            let value = u16::from_be_bytes([request[3], request[4]]);
+            match function {
+                READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS => {
+                    let registers = self.registers.lock().unwrap();
+                    match registers.get(address..address + value as usize) {
+                        Some(words) => {
+                            let mut response = vec![function, (words.len() * 2) as u8];
+                            response.extend(words.iter().flat_map(|word| word.to_be_bytes()));
+                            response
+                        }
+                        None => vec![function | 0x80, 2],
+                    }
+                }
+                WRITE_SINGLE_COIL => {
+                    self.coils.lock().unwrap()[address] = value == 0xFF00;
+                    request.to_vec()
+                }
+                WRITE_SINGLE_REGISTER => {
+                    self.registers.lock().unwrap()[address] = value;
+                    request.to_vec()
+                }
+                _ => vec![function | 0x80, 1],
+            }
+        }
+
+        /// Serve Modbus TCP, dropping the first `drop` connections without answering
+        fn serve_tcp(self: &Arc<Self>, drop: usize) -> SocketAddr {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let simulator = self.clone();
+            std::thread::spawn(move || {
+                for (index, stream) in listener.incoming().enumerate() {
+                    let mut stream = stream.unwrap();
+                    if index < drop {
+                        continue;
+                    }
+                    let simulator = simulator.clone();
+                    std::thread::spawn(move || {
+                        let mut header = [0; 7];
+                        while stream.read_exact(&mut header).is_ok() {
+                            let length = u16::from_be_bytes([header[4], header[5]]) as usize - 1;
+                            let mut request = vec![0; length];
+                            stream.read_exact(&mut request).unwrap();
+
+                            let response = simulator.handle(&request);
+                            let mut frame = header[..4].to_vec();
+                            frame.extend((response.len() as u16 + 1).to_be_bytes());
+                            frame.push(header[6]);
+                            frame.extend(response);
+                            stream.write_all(&frame).unwrap();
+                        }
+                    });
+                }
+            });
+            addr
+        }
+
+        /// Serve Modbus RTU requests of a known length on one end of a socket pair
+        #[cfg(unix)]
+        fn serve_rtu(self: &Arc<Self>, mut stream: UnixStream) {
+            let simulator = self.clone();
+            std::thread::spawn(move || {
+                let mut frame = [0; 8];
+                while stream.read_exact(&mut frame).is_ok() {
+                    assert_eq!(crc16(&frame[..6]).to_le_bytes(), frame[6..]);
+                    let mut response = vec![frame[0]];
+                    response.extend(simulator.handle(&frame[1..6]));
+                    response.extend(crc16(&response).to_le_bytes());
+                    stream.write_all(&response).unwrap();
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn test_crc16() {
+        assert_eq!(crc16(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x01]).to_le_bytes(), [0x84, 0x0A]);
+    }
+
+    #[test]
+    fn test_decode() {
+        let register = Register::holding(0, DataType::I16).set_scale(0.1).set_offset(1.0);
+        assert_eq!(register.decode(&[0xFF9C]), -9.0);
+
+        let value = 1234.5f32.to_be_bytes();
+        let high = u16::from_be_bytes([value[0], value[1]]);
+        let low = u16::from_be_bytes([value[2], value[3]]);
+        let swapped = |word: u16| word.swap_bytes();
+
+        let register = Register::holding(0, DataType::F32);
+        assert_eq!(register.decode(&[high, low]), 1234.5);
+        let register = register.set_byte_order(ByteOrder::Cdab);
+        assert_eq!(register.decode(&[low, high]), 1234.5);
+        let register = register.set_byte_order(ByteOrder::Badc);
+        assert_eq!(register.decode(&[swapped(high), swapped(low)]), 1234.5);
+        let register = register.set_byte_order(ByteOrder::Dcba);
+        assert_eq!(register.decode(&[swapped(low), swapped(high)]), 1234.5);
+
+        let register = Register::input(0, DataType::U32).set_byte_order(ByteOrder::Cdab);
+        assert_eq!(register.decode(&[0x0001, 0x0002]), 131073.0);
+    }
+
+    #[test]
+    fn test_tcp_input() {
+        let simulator = Simulator::new(&[215, 0xFFFF]);
+        let client = ModbusClient::tcp(simulator.serve_tcp(0));
+
+        let mut input = client.input(1, Register::holding(0, DataType::U16).set_scale(0.1));
+        assert_eq!(input.read(), "21.5");
+
+        let register = Register::input(1, DataType::I16);
+        assert_eq!(client.read(1, &register), Ok(-1.0));
+    }
+
+    #[test]
+    fn test_tcp_retries() {
+        let simulator = Simulator::new(&[42]);
+        let addr = simulator.serve_tcp(1);
+
+        let client = ModbusClient::tcp(addr)
+            .set_timeout(Duration::milliseconds(200))
+            .set_retries(0);
+        assert!(client.read(1, &Register::holding(0, DataType::U16)).is_err());
+
+        // a second client reconnects after the first connection is dropped
+        let client = ModbusClient::tcp(addr)
+            .set_timeout(Duration::milliseconds(200))
+            .set_retries(1);
+        assert_eq!(client.read(1, &Register::holding(0, DataType::U16)), Ok(42.0));
+    }
+
+    #[test]
+    fn test_exception() {
+        let simulator = Simulator::new(&[]);
+        let client = ModbusClient::tcp(simulator.serve_tcp(0));
+
+        let result = client.read_registers(1, RegisterKind::Holding, 15, 2);
+        assert_eq!(result, Err(DriverError::Failed(String::from("Modbus exception code 2"))));
+
+        let mut input = client.input(1, Register::holding(16, DataType::U16));
+        assert_eq!(input.read(), "NaN");
+    }
+
+    #[test]
+    fn test_batch() {
+        let simulator = Simulator::new(&[100, 200, 300, 400]);
+        let client = ModbusClient::tcp(simulator.serve_tcp(0));
+        let batch = client.batch(1, RegisterKind::Holding, 1, 3, Duration::seconds(60));
+
+        let mut first = batch.input(Register::holding(1, DataType::U16));
+        let mut last = batch.input(Register::holding(3, DataType::U16));
+        assert_eq!(first.read(), "200");
+        assert_eq!(last.read(), "400");
+        assert_eq!(simulator.requests.load(Ordering::SeqCst), 1);
+
+        assert!(batch.read(&Register::holding(0, DataType::U16)).is_err());
+        assert!(batch.read(&Register::holding(3, DataType::U32)).is_err());
+        assert!(batch.read(&Register::input(1, DataType::U16)).is_err());
+
+        // the end of a register at the top of the address space does not overflow
+        let batch = client.batch(1, RegisterKind::Holding, u16::MAX - 1, u16::MAX, Duration::seconds(60));
+        assert!(batch.read(&Register::holding(u16::MAX, DataType::U32)).is_err());
+    }
+
+    #[test]
+    fn test_coil_output() {
+        let simulator = Simulator::new(&[]);
+        let client = ModbusClient::tcp(simulator.serve_tcp(0));
+
+        let mut output = client.coil_output(1, 3);
+        output.activate();
+        assert!(simulator.coils.lock().unwrap()[3]);
+
+        client.write_register(1, 2, 7).unwrap();
+        assert_eq!(simulator.registers.lock().unwrap()[2], 7);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_rtu() {
+        let simulator = Simulator::new(&[0x4148, 0x0000]);
+        let (client_end, device_end) = UnixStream::pair().unwrap();
+        simulator.serve_rtu(device_end);
+
+        let stream = Mutex::new(Some(client_end));
+        let client = ModbusClient::new(Framing::Rtu, Box::new(move |_| {
+            match stream.lock().unwrap().take() {
+                Some(stream) => Ok(Box::new(stream)),
+                None => Err(io::Error::new(io::ErrorKind::NotConnected, "closed")),
+            }
+        }));
+
+        assert_eq!(client.read(7, &Register::holding(0, DataType::F32)), Ok(12.5));
+
+        client.write_coil(7, 1, true).unwrap();
+        assert!(simulator.coils.lock().unwrap()[1]);
+    }
+
+    #[tokio::test]
+    async fn test_async_input() {
+        let simulator = Simulator::new(&[55]);
+        let client = ModbusClient::tcp(simulator.serve_tcp(0));
+
+        let mut input = client.async_input(1, Register::holding(0, DataType::U16));
+        assert_eq!(input.read().await, Ok(String::from("55")));
+
+        let mut input = client.async_input(1, Register::holding(20, DataType::U16));
+        assert!(input.read().await.is_err());
+
+        let mut output = client.async_coil_output(1, 0);
+        output.activate().await.unwrap();
+        assert!(simulator.coils.lock().unwrap()[0]);
+    }
+}
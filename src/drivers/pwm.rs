@@ -0,0 +1,160 @@
+//! PWM outputs via the Linux sysfs interface
+//!
+//! Each PWM controller is exposed as a `pwmchipN` directory under `/sys/class/pwm`. A channel is
+//! exported by writing its number to the `export` file, after which the kernel creates a `pwmM`
+//! directory with `period`, `duty_cycle` and `enable` attributes, in nanoseconds.
+//!
+//! Since outputs are binary, activating a [`Pwm`] output enables the channel at the configured
+//! duty cycle and deactivating it disables the channel. The duty cycle may also be changed
+//! directly with [`Pwm::set_duty_cycle`].
+//!
+//! # Example
+//! ```no_run
+//! use equilibrium::drivers::pwm::Pwm;
+//!
+//! // a fan running at 60% of full speed, at 25kHz
+//! let fan = Pwm::new("/sys/class/pwm/pwmchip0", 0, 40_000)
+//!     .set_duty(0.6)
+//!     .output()
+//!     .unwrap();
+//! ```
+use std::fs;
+use std::path::PathBuf;
+use crate::Output;
+use crate::types::DriverError;
+
+/// A channel of a sysfs PWM controller
+#[derive(Debug, Clone)]
+pub struct Pwm {
+    chip: PathBuf,
+    channel: u32,
+    period: u64,
+    duty: f32,
+}
+
+impl Pwm {
+    /// Create a PWM channel with a 100% duty cycle
+    ///
+    /// # Arguments
+    /// * `chip` - Directory of the controller, such as `/sys/class/pwm/pwmchip0`
+    /// * `channel` - Channel of the controller
+    /// * `period` - Period in nanoseconds
+    pub fn new<P>(chip: P, channel: u32, period: u64) -> Self
+        where P: Into<PathBuf>
+    {
+        Self {
+            chip: chip.into(),
+            channel,
+            period,
+            duty: 1.0,
+        }
+    }
+
+    /// Builder method to set the fraction of the period that the signal is high
+    ///
+    /// The value is clamped between 0 and 1.
+    pub fn set_duty(mut self, duty: f32) -> Self {
+        self.duty = duty.clamp(0.0, 1.0);
+        self
+    }
+
+    fn channel_dir(&self) -> PathBuf {
+        self.chip.join(format!("pwm{}", self.channel))
+    }
+
+    fn write(&self, attribute: &str, value: impl ToString) -> Result<(), DriverError> {
+        fs::write(self.channel_dir().join(attribute), value.to_string())
+            .map_err(|e| DriverError::Failed(format!("{}: {}", attribute, e)))
+    }
+
+    /// Export the channel if needed, and configure its period and duty cycle
+    pub fn configure(&self) -> Result<(), DriverError> {
+        if !self.channel_dir().exists() {
+            fs::write(self.chip.join("export"), self.channel.to_string())?;
+        }
+        // the duty cycle may not exceed the period, so the order of writes depends on both values
+        let _ = self.write("duty_cycle", 0);
+        self.write("period", self.period)?;
+        self.set_duty_cycle(self.duty)
+    }
+
+    /// Set the fraction of the period that the signal is high
+    pub fn set_duty_cycle(&self, duty: f32) -> Result<(), DriverError> {
+        let duty_cycle = (self.period as f64 * duty.clamp(0.0, 1.0) as f64).round() as u64;
+        self.write("duty_cycle", duty_cycle)
+    }
+
+    /// Enable or disable the channel
+    pub fn set_enabled(&self, enabled: bool) -> Result<(), DriverError> {
+        self.write("enable", enabled as u8)
+    }
+
+    /// Configure the channel and create an [`Output`] which enables and disables it
+    pub fn output(self) -> Result<Output<impl FnMut(bool) + Send>, DriverError> {
+        self.configure()?;
+        Ok(Output::new(move |state| {
+            if let Err(e) = self.set_enabled(state) {
+                eprintln!("Failed to write {}: {}", self.channel_dir().display(), e);
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::TempDir;
+    use super::*;
+
+    /// Create a fake controller, with an already exported channel if `exported` is true
+    fn fake_chip(name: &str, exported: bool) -> TempDir {
+        let dir = TempDir::new(&format!("pwm-{}", name));
+        fs::write(dir.join("export"), "").unwrap();
+        if exported {
+            fs::create_dir_all(dir.join("pwm1")).unwrap();
+        }
+        dir
+    }
+
+    fn read(chip: &std::path::Path, attribute: &str) -> String {
+        fs::read_to_string(chip.join("pwm1").join(attribute)).unwrap()
+    }
+
+    #[test]
+    fn test_output() {
+        let chip = fake_chip("output", true);
+        let mut output = Pwm::new(&*chip, 1, 40_000)
+            .set_duty(0.6)
+            .output()
+            .unwrap();
+
+        assert_eq!(read(&chip, "period"), "40000");
+        assert_eq!(read(&chip, "duty_cycle"), "24000");
+        // the channel was already exported
+        assert_eq!(fs::read_to_string(chip.join("export")).unwrap(), "");
+
+        output.activate();
+        assert_eq!(read(&chip, "enable"), "1");
+        output.deactivate();
+        assert_eq!(read(&chip, "enable"), "0");
+    }
+
+    #[test]
+    fn test_export() {
+        let chip = fake_chip("export", false);
+
+        // the fake controller does not create the channel directory
+        assert!(Pwm::new(&*chip, 1, 1000).configure().is_err());
+        assert_eq!(fs::read_to_string(chip.join("export")).unwrap(), "1");
+    }
+
+    #[test]
+    fn test_set_duty_cycle() {
+        let chip = fake_chip("duty", true);
+        let pwm = Pwm::new(&*chip, 1, 1000);
+
+        pwm.set_duty_cycle(0.25).unwrap();
+        assert_eq!(read(&chip, "duty_cycle"), "250");
+        pwm.set_duty_cycle(2.0).unwrap();
+        assert_eq!(read(&chip, "duty_cycle"), "1000");
+    }
+}
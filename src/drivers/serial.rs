@@ -0,0 +1,355 @@
+//! Request/response sensors over a serial line
+//!
+//! Many probe circuits, such as pH, EC and dissolved oxygen circuits, are read by sending an ASCII
+//! command over a UART and reading a terminated response. For example, `R\r` is answered with
+//! `7.02\r`. A [`SerialProbe`] sends the command, reads until the terminator, and extracts the
+//! value with a regular expression. Calibration commands are sent over the same port with
+//! [`SerialProbe::calibrate`], and are serialized with reads.
+//!
+//! The port is configured as a raw terminal when it is opened, with the baud rate set by
+//! [`SerialProbe::set_baud_rate`] if given.
+//!
+//! # Example
+//! ```no_run
+//! use chrono::Duration;
+//! use equilibrium::drivers::serial::SerialProbe;
+//!
+//! let ph = SerialProbe::new("/dev/ttyAMA0")
+//!     .set_baud_rate(9600)
+//!     .set_timeout(Duration::seconds(2));
+//!
+//! // two-point calibration
+//! ph.calibrate("Cal,mid,7.00\r").unwrap();
+//! ph.calibrate("Cal,low,4.00\r").unwrap();
+//!
+//! let input = ph.input();
+//! ```
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use chrono::Duration;
+use nix::sys::termios::{self, BaudRate, FlushArg, SetArg, SpecialCharacterIndices};
+pub use regex::Regex;
+use crate::{AsyncInput, Input};
+use crate::types::DriverError;
+
+/// Longest response that is read before the probe is considered to be misbehaving
+const MAX_RESPONSE: usize = 1024;
+
+impl From<nix::Error> for DriverError {
+    fn from(e: nix::Error) -> Self {
+        DriverError::Failed(e.to_string())
+    }
+}
+
+/// A sensor that is read by sending a command over a serial line
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug, Clone)]
+pub struct SerialProbe {
+    path: PathBuf,
+    baud_rate: Option<u32>,
+    command: String,
+    terminator: u8,
+    pattern: Regex,
+    ack: Regex,
+    timeout: Duration,
+    retries: u32,
+    port: Arc<Mutex<Option<File>>>,
+}
+
+impl SerialProbe {
+    /// Create a probe which is read with `R\r`, answers with a `\r` terminated number, and
+    /// acknowledges calibration with `OK`
+    pub fn new<P>(path: P) -> Self
+        where P: Into<PathBuf>
+    {
+        Self {
+            path: path.into(),
+            baud_rate: None,
+            command: String::from("R\r"),
+            terminator: b'\r',
+            pattern: Regex::new(r"-?\d+(?:\.\d+)?").unwrap(),
+            ack: Regex::new("OK").unwrap(),
+            timeout: Duration::seconds(1),
+            retries: 2,
+            port: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Builder method to set the baud rate of the port
+    ///
+    /// By default, the baud rate of the port is left unchanged.
+    pub fn set_baud_rate(mut self, baud_rate: u32) -> Self {
+        self.baud_rate = Some(baud_rate);
+        self
+    }
+
+    /// Builder method to set the command that requests a reading
+    pub fn set_command<S>(mut self, command: S) -> Self
+        where S: Into<String>
+    {
+        self.command = command.into();
+        self
+    }
+
+    /// Builder method to set the byte that terminates a response
+    pub fn set_terminator(mut self, terminator: u8) -> Self {
+        self.terminator = terminator;
+        self
+    }
+
+    /// Builder method to set the pattern that the value is extracted from
+    ///
+    /// If the pattern has a capture group, the first group is used, otherwise the whole match.
+    pub fn set_pattern(mut self, pattern: Regex) -> Self {
+        self.pattern = pattern;
+        self
+    }
+
+    /// Builder method to set the pattern that a calibration response must match
+    pub fn set_ack_pattern(mut self, ack: Regex) -> Self {
+        self.ack = ack;
+        self
+    }
+
+    /// Builder method to set how long a response may take
+    ///
+    /// Defaults to 1 second.
+    pub fn set_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Builder method to set how many times a failed request is retried
+    ///
+    /// Defaults to 2.
+    pub fn set_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Open the port as a raw terminal, where reads return after a tenth of a second of silence
+    fn open(&self) -> Result<File, DriverError> {
+        let port = OpenOptions::new().read(true).write(true).open(&self.path)?;
+        let mut settings = termios::tcgetattr(&port)?;
+        termios::cfmakeraw(&mut settings);
+        if let Some(baud_rate) = self.baud_rate {
+            let baud_rate = BaudRate::try_from(baud_rate)
+                .map_err(|_| DriverError::Failed(format!("unsupported baud rate {}", baud_rate)))?;
+            termios::cfsetspeed(&mut settings, baud_rate)?;
+        }
+        settings.control_chars[SpecialCharacterIndices::VMIN as usize] = 0;
+        settings.control_chars[SpecialCharacterIndices::VTIME as usize] = 1;
+        termios::tcsetattr(&port, SetArg::TCSANOW, &settings)?;
+        Ok(port)
+    }
+
+    /// Send a command and read a single response without the terminator
+    fn attempt(&self, port: &mut File, command: &str) -> Result<String, DriverError> {
+        // discard anything left over from a previous request
+        termios::tcflush(&*port, FlushArg::TCIFLUSH)?;
+        port.write_all(command.as_bytes())?;
+
+        let deadline = Instant::now() + self.timeout.to_std().unwrap_or_default();
+        let mut response = Vec::new();
+        let mut byte = [0];
+        loop {
+            // a device which keeps sending without a terminator must not hold up the read
+            if Instant::now() >= deadline {
+                return Err(DriverError::Timeout);
+            }
+            if response.len() >= MAX_RESPONSE {
+                return Err(DriverError::Failed(format!("response exceeds {} bytes", MAX_RESPONSE)));
+            }
+            match port.read(&mut byte)? {
+                0 => continue,
+                _ if byte[0] == self.terminator => break,
+                _ => response.push(byte[0]),
+            }
+        }
+        String::from_utf8(response).map_err(|e| DriverError::Failed(e.to_string()))
+    }
+
+    /// Send a command and return the response, retrying on a freshly opened port if it fails
+    pub fn query(&self, command: &str) -> Result<String, DriverError> {
+        let mut port = self.port.lock().map_err(|e| DriverError::Failed(e.to_string()))?;
+        let mut error = DriverError::Failed(String::from("no attempt was made"));
+        for _ in 0..=self.retries {
+            if port.is_none() {
+                match self.open() {
+                    Ok(opened) => *port = Some(opened),
+                    Err(e) => {
+                        error = e;
+                        continue;
+                    }
+                }
+            }
+            match self.attempt(port.as_mut().unwrap(), command) {
+                Ok(response) => return Ok(response),
+                Err(e) => {
+                    *port = None;
+                    error = e;
+                }
+            }
+        }
+        Err(error)
+    }
+
+    /// Request a reading and extract its value
+    pub fn read(&self) -> Result<f32, DriverError> {
+        let response = self.query(&self.command)?;
+        self.pattern.captures(&response)
+            .and_then(|captures| captures.get(1).or_else(|| captures.get(0)))
+            .and_then(|value| value.as_str().parse().ok())
+            .ok_or_else(|| DriverError::Failed(format!("unexpected response {:?}", response)))
+    }
+
+    /// Send a calibration command, returning the response if it is acknowledged
+    pub fn calibrate(&self, command: &str) -> Result<String, DriverError> {
+        let response = self.query(command)?;
+        match self.ack.is_match(&response) {
+            true => Ok(response),
+            false => Err(DriverError::Failed(format!("calibration rejected: {:?}", response))),
+        }
+    }
+
+    /// Create an [`Input`] which requests readings
+    pub fn input(&self) -> Input<impl Fn() -> String + Send + Sync> {
+        let probe = self.clone();
+        Input::new(move || match probe.read() {
+            Ok(value) => value.to_string(),
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", probe.path.display(), e);
+                f32::NAN.to_string()
+            }
+        })
+    }
+
+    /// Create an [`AsyncInput`] which requests readings
+    pub fn async_input(&self) -> AsyncInput {
+        let probe = self.clone();
+        AsyncInput::new(move || {
+            let probe = probe.clone();
+            async move {
+                tokio::task::spawn_blocking(move || probe.read())
+                    .await
+                    .map_err(|e| DriverError::Failed(e.to_string()))?
+                    .map(|value| value.to_string())
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::fd::{AsRawFd, OwnedFd};
+    use nix::pty::openpty;
+    use super::*;
+
+    /// A stand-in device on a pseudo-terminal, which answers each command using `respond`
+    ///
+    /// Returns the path of the terminal, and the end of the terminal which must be kept open.
+    fn fake_device<F>(respond: F) -> (PathBuf, OwnedFd)
+        where F: Fn(&str) -> Option<String> + Send + 'static
+    {
+        let pty = openpty(None, None).unwrap();
+        let path = nix::unistd::ttyname(pty.slave.as_raw_fd()).unwrap();
+
+        // configure the terminal before the device starts reading
+        let mut settings = termios::tcgetattr(&pty.slave).unwrap();
+        termios::cfmakeraw(&mut settings);
+        termios::tcsetattr(&pty.slave, SetArg::TCSANOW, &settings).unwrap();
+
+        let mut device = File::from(pty.master);
+        std::thread::spawn(move || {
+            let mut command = Vec::new();
+            let mut byte = [0];
+            while let Ok(1) = device.read(&mut byte) {
+                command.push(byte[0]);
+                if byte[0] != b'\r' {
+                    continue;
+                }
+                let request = String::from_utf8(std::mem::take(&mut command)).unwrap();
+                if let Some(response) = respond(&request) {
+                    device.write_all(response.as_bytes()).unwrap();
+                }
+            }
+        });
+        (path, pty.slave)
+    }
+
+    #[test]
+    fn test_read() {
+        let (path, _terminal) = fake_device(|command| match command {
+            "R\r" => Some(String::from("7.02\r")),
+            "T\r" => Some(String::from("?T,25.1\r")),
+            _ => Some(String::from("*ER\r")),
+        });
+
+        let probe = SerialProbe::new(&path);
+        assert_eq!(probe.read(), Ok(7.02));
+        assert_eq!(probe.input().read(), "7.02");
+
+        let probe = SerialProbe::new(&path)
+            .set_command("T\r")
+            .set_pattern(Regex::new(r"\?T,(\d+\.\d+)").unwrap());
+        assert_eq!(probe.read(), Ok(25.1));
+
+        let probe = SerialProbe::new(&path).set_command("X\r");
+        assert!(probe.read().is_err());
+    }
+
+    #[test]
+    fn test_calibrate() {
+        let (path, _terminal) = fake_device(|command| match command.starts_with("Cal,") {
+            true => Some(String::from("*OK\r")),
+            false => Some(String::from("*ER\r")),
+        });
+
+        let probe = SerialProbe::new(&path);
+        assert_eq!(probe.calibrate("Cal,mid,7.00\r"), Ok(String::from("*OK")));
+        assert!(probe.calibrate("Bogus\r").is_err());
+    }
+
+    #[test]
+    fn test_timeout() {
+        let (path, _terminal) = fake_device(|_| None);
+
+        let probe = SerialProbe::new(&path)
+            .set_timeout(Duration::milliseconds(200))
+            .set_retries(1);
+        assert_eq!(probe.read(), Err(DriverError::Timeout));
+    }
+
+    #[test]
+    fn test_unterminated_response() {
+        let (path, _terminal) = fake_device(|_| Some("7".repeat(2000)));
+
+        let probe = SerialProbe::new(&path)
+            .set_timeout(Duration::seconds(5))
+            .set_retries(0);
+        let started = Instant::now();
+        assert_eq!(
+            probe.read(),
+            Err(DriverError::Failed(format!("response exceeds {} bytes", MAX_RESPONSE))),
+        );
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_missing_port() {
+        let probe = SerialProbe::new("/dev/equilibrium-missing-tty");
+        assert!(probe.read().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_async_input() {
+        let (path, _terminal) = fake_device(|_| Some(String::from("1413\r")));
+
+        let mut input = SerialProbe::new(&path).async_input();
+        assert_eq!(input.read().await, Ok(String::from("1413")));
+    }
+}
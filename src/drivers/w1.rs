@@ -0,0 +1,207 @@
+//! DS18B20 1-Wire temperature sensors
+//!
+//! The Linux `w1_therm` driver exposes each sensor as a directory under `/sys/bus/w1/devices`,
+//! named after the ROM id of the sensor (such as `28-0316a2797a2d`). Reading its `w1_slave` file
+//! triggers a conversion and returns the scratchpad along with the result of the CRC check:
+//!
+//! ```text
+//! 72 01 4b 46 7f ff 0e 10 57 : crc=57 YES
+//! 72 01 4b 46 7f ff 0e 10 57 t=23125
+//! ```
+//!
+//! Reads which fail the CRC check are retried. A sensor which was reset during a conversion
+//! returns its power-on value of 85°C with a valid CRC, so this value is only accepted once an
+//! immediate re-read returns it again. Otherwise the re-read is used, or the read is retried if it
+//! fails.
+//!
+//! # Example
+//! ```no_run
+//! use equilibrium::drivers::w1::Ds18b20;
+//!
+//! let probe = Ds18b20::from_id("28-0316a2797a2d");
+//! let input = probe.input();
+//! ```
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::{AsyncInput, Input};
+use crate::types::DriverError;
+
+/// Directory that the kernel exposes 1-Wire devices in
+pub const DEVICES_PATH: &str = "/sys/bus/w1/devices";
+
+/// The temperature, in °C, of a sensor which has not completed a conversion since power-on
+const POWER_ON_VALUE: f32 = 85.0;
+
+/// Parse the contents of a `w1_slave` file, returning the temperature in °C
+fn parse(contents: &str) -> Result<f32, DriverError> {
+    let mut lines = contents.lines();
+    let status = lines.next().unwrap_or_default();
+    if !status.trim_end().ends_with("YES") {
+        return Err(DriverError::Failed(String::from("CRC check failed")));
+    }
+
+    let millidegrees = lines.next()
+        .and_then(|line| line.split("t=").nth(1))
+        .and_then(|value| value.trim().parse::<i32>().ok())
+        .ok_or_else(|| DriverError::Failed(String::from("missing temperature")))?;
+    Ok(millidegrees as f32 / 1000.0)
+}
+
+/// Perform a conversion with `read`, confirming the power-on value with a second conversion
+fn convert<F>(mut read: F) -> Result<f32, DriverError>
+    where F: FnMut() -> Result<f32, DriverError>
+{
+    let value = read()?;
+    if value != POWER_ON_VALUE {
+        return Ok(value);
+    }
+    read().map_err(|_| DriverError::Failed(String::from("conversion not complete")))
+}
+
+/// A DS18B20 temperature sensor
+#[derive(Debug, Clone)]
+pub struct Ds18b20 {
+    path: PathBuf,
+    retries: u32,
+}
+
+impl Ds18b20 {
+    /// Create a sensor from its device directory
+    pub fn new<P>(path: P) -> Self
+        where P: Into<PathBuf>
+    {
+        Self {
+            path: path.into(),
+            retries: 2,
+        }
+    }
+
+    /// Create a sensor from its ROM id, in the default device directory
+    pub fn from_id(id: &str) -> Self {
+        Self::new(Path::new(DEVICES_PATH).join(id))
+    }
+
+    /// Builder method to set how many times a failed read is retried
+    ///
+    /// Defaults to 2.
+    pub fn set_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Read the temperature in °C
+    pub fn read(&self) -> Result<f32, DriverError> {
+        let mut result = Err(DriverError::Failed(String::from("no attempt was made")));
+        for _ in 0..=self.retries {
+            result = convert(|| {
+                fs::read_to_string(self.path.join("w1_slave"))
+                    .map_err(DriverError::from)
+                    .and_then(|contents| parse(&contents))
+            });
+            if result.is_ok() {
+                break;
+            }
+        }
+        result
+    }
+
+    /// Create an [`Input`] which reads the temperature
+    pub fn input(&self) -> Input<impl Fn() -> String + Send + Sync> {
+        let sensor = self.clone();
+        Input::new(move || match sensor.read() {
+            Ok(value) => value.to_string(),
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", sensor.path.display(), e);
+                f32::NAN.to_string()
+            }
+        })
+    }
+
+    /// Create an [`AsyncInput`] which reads the temperature
+    ///
+    /// A conversion takes up to 750ms, so the read is performed on a blocking task.
+    pub fn async_input(&self) -> AsyncInput {
+        let sensor = self.clone();
+        AsyncInput::new(move || {
+            let sensor = sensor.clone();
+            async move {
+                tokio::task::spawn_blocking(move || sensor.read())
+                    .await
+                    .map_err(|e| DriverError::Failed(e.to_string()))?
+                    .map(|value| value.to_string())
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::TempDir;
+    use super::*;
+
+    const VALID: &str = "72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n72 01 4b 46 7f ff 0e 10 57 t=23125\n";
+    const CRC_FAILURE: &str = "72 01 4b 46 7f ff 0e 10 57 : crc=00 NO\n72 01 4b 46 7f ff 0e 10 57 t=23125\n";
+
+    /// Create a fake device directory containing a `w1_slave` file
+    fn fake_device(name: &str, contents: &str) -> TempDir {
+        let dir = TempDir::new(&format!("w1-{}", name));
+        fs::write(dir.join("w1_slave"), contents).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(VALID), Ok(23.125));
+        assert_eq!(parse("a : crc=57 YES\na t=-1062\n"), Ok(-1.062));
+        assert_eq!(parse(CRC_FAILURE), Err(DriverError::Failed(String::from("CRC check failed"))));
+        assert_eq!(parse("50 05 : crc=57 YES\n50 05 t=85000\n"), Ok(85.0));
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn test_power_on_value() {
+        let convert_all = |results: Vec<Result<f32, DriverError>>| {
+            let mut results = results.into_iter();
+            convert(|| results.next().unwrap())
+        };
+        let failed = || Err(DriverError::Failed(String::from("CRC check failed")));
+
+        // the power-on value is accepted when a re-read agrees, otherwise the re-read is used
+        assert_eq!(convert_all(vec![Ok(85.0), Ok(85.0)]), Ok(85.0));
+        assert_eq!(convert_all(vec![Ok(85.0), Ok(23.125)]), Ok(23.125));
+        assert_eq!(
+            convert_all(vec![Ok(85.0), failed()]),
+            Err(DriverError::Failed(String::from("conversion not complete"))),
+        );
+        assert_eq!(convert_all(vec![Ok(23.125)]), Ok(23.125));
+        assert_eq!(convert_all(vec![failed()]), failed());
+
+        // a sensor which really is at 85°C keeps reporting it
+        let dir = fake_device("power-on", "50 05 : crc=57 YES\n50 05 t=85000\n");
+        assert_eq!(Ds18b20::new(&*dir).set_retries(0).read(), Ok(85.0));
+    }
+
+    #[test]
+    fn test_read() {
+        let dir = fake_device("read", VALID);
+        let mut input = Ds18b20::new(&*dir).input();
+        assert_eq!(input.read(), "23.125");
+
+        fs::write(dir.join("w1_slave"), CRC_FAILURE).unwrap();
+        assert!(Ds18b20::new(&*dir).read().is_err());
+        assert_eq!(input.read(), "NaN");
+    }
+
+    #[test]
+    fn test_missing_device() {
+        let sensor = Ds18b20::new(std::env::temp_dir().join("equilibrium-w1-missing"));
+        assert!(sensor.read().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_async_input() {
+        let dir = fake_device("async", VALID);
+        let mut input = Ds18b20::new(&*dir).async_input();
+        assert_eq!(input.read().await, Ok(String::from("23.125")));
+    }
+}
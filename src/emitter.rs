@@ -1,24 +1,226 @@
-use reqwest::Client;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::{Certificate, Client, Identity};
+use reqwest::header::CONTENT_TYPE;
+use sha2::Sha256;
 use crate::types::Message;
 
+/// How requests are authenticated
+#[derive(Debug, Clone)]
+enum Auth {
+    Bearer(String),
+    Basic(String, Option<String>),
+}
+
+/// Posts batches of messages to an HTTP endpoint as a JSON array
+///
+/// Requests may be authenticated with a bearer token, basic auth or a TLS client certificate, and
+/// may carry any number of custom headers. A request which does not complete within the timeout
+/// (see [`Emitter::set_timeout`]) fails, and is retried like any other failure.
+///
+/// # Signing
+/// With a signing key, every request carries the current Unix time in the
+/// `X-Equilibrium-Timestamp` header, and the HMAC-SHA256 of `"{timestamp}.{body}"` in the
+/// `X-Equilibrium-Signature` header (see [`Emitter::sign`]). The server should recompute the
+/// signature, and reject requests whose timestamp is too old so that they cannot be replayed.
 pub struct Emitter {
     client: Client,
     url: String,
+    retries: u32,
+    timeout: Duration,
+    auth: Option<Auth>,
+    headers: Vec<(String, String)>,
+    signing_key: Option<Vec<u8>>,
+    identity: Option<Identity>,
+    roots: Vec<Certificate>,
+    failures_total: AtomicU64,
+    retries_total: AtomicU64,
 }
 
 impl Emitter {
+    /// Header carrying the Unix time, in seconds, at which a signed batch was sent
+    pub const TIMESTAMP_HEADER: &'static str = "X-Equilibrium-Timestamp";
+
+    /// Header carrying the signature of a signed batch, as `sha256=<hex digest>`
+    pub const SIGNATURE_HEADER: &'static str = "X-Equilibrium-Signature";
+
     pub fn new<S>(url: S) -> Self
         where S: Into<String>
     {
         Self {
             client: Client::new(),
             url: url.into(),
+            retries: 0,
+            timeout: Duration::from_secs(10),
+            auth: None,
+            headers: Vec::new(),
+            signing_key: None,
+            identity: None,
+            roots: Vec::new(),
+            failures_total: AtomicU64::new(0),
+            retries_total: AtomicU64::new(0),
+        }
+    }
+
+    /// Builder method to authenticate with a bearer token
+    pub fn set_bearer_token<S>(mut self, token: S) -> Self
+        where S: Into<String>
+    {
+        self.auth = Some(Auth::Bearer(token.into()));
+        self
+    }
+
+    /// Builder method to authenticate with a username and password
+    pub fn set_basic_auth<U, P>(mut self, username: U, password: Option<P>) -> Self
+        where U: Into<String>, P: Into<String>
+    {
+        self.auth = Some(Auth::Basic(username.into(), password.map(Into::into)));
+        self
+    }
+
+    /// Builder method to add a header to every request
+    pub fn add_header<N, V>(mut self, name: N, value: V) -> Self
+        where N: Into<String>, V: Into<String>
+    {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Builder method to sign every request with an HMAC-SHA256 key
+    ///
+    /// See [Signing](#signing) for how requests are signed.
+    pub fn set_signing_key<K>(mut self, key: K) -> Self
+        where K: Into<Vec<u8>>
+    {
+        self.signing_key = Some(key.into());
+        self
+    }
+
+    /// Builder method to present a TLS client certificate
+    ///
+    /// # Arguments
+    /// * `certificate` - PEM encoded certificate chain
+    /// * `key` - PEM encoded PKCS #8 private key
+    ///
+    /// # Returns
+    /// An error if the certificate or key could not be parsed
+    pub fn set_client_certificate(mut self, certificate: &[u8], key: &[u8]) -> Result<Self, reqwest::Error> {
+        self.identity = Some(Identity::from_pkcs8_pem(certificate, key)?);
+        self.client = self.build_client()?;
+        Ok(self)
+    }
+
+    /// Builder method to trust an additional root certificate, such as a private CA
+    ///
+    /// # Returns
+    /// An error if the PEM encoded certificate could not be parsed
+    pub fn add_root_certificate(mut self, certificate: &[u8]) -> Result<Self, reqwest::Error> {
+        self.roots.push(Certificate::from_pem(certificate)?);
+        self.client = self.build_client()?;
+        Ok(self)
+    }
+
+    fn build_client(&self) -> Result<Client, reqwest::Error> {
+        let mut builder = Client::builder();
+        if let Some(identity) = &self.identity {
+            builder = builder.identity(identity.clone());
         }
+        for root in self.roots.iter() {
+            builder = builder.add_root_certificate(root.clone());
+        }
+        builder.build()
+    }
+
+    /// Compute the signature of a request body, as sent in the `X-Equilibrium-Signature` header
+    ///
+    /// # Arguments
+    /// * `key` - The signing key
+    /// * `timestamp` - The Unix time sent in the `X-Equilibrium-Timestamp` header
+    /// * `body` - The request body
+    pub fn sign(key: &[u8], timestamp: i64, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key)
+            .expect("HMAC accepts keys of any length");
+        mac.update(timestamp.to_string().as_bytes());
+        mac.update(b".");
+        mac.update(body);
+
+        let mut signature = String::from("sha256=");
+        for byte in mac.finalize().into_bytes() {
+            let _ = write!(signature, "{:02x}", byte);
+        }
+        signature
+    }
+
+    /// Builder method to set how many times a failed batch is retried
+    ///
+    /// Each retry waits 250ms longer than the previous one. Defaults to 0.
+    pub fn set_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Builder method to set how long a request may take, from connecting to reading the response
+    ///
+    /// Defaults to 10 seconds.
+    pub fn set_timeout(mut self, timeout: chrono::Duration) -> Self {
+        self.timeout = timeout.to_std().unwrap_or_default();
+        self
+    }
+
+    /// Returns how many batches could not be emitted, even after retrying
+    pub fn get_failures(&self) -> u64 {
+        self.failures_total.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many times a batch has been retried
+    pub fn get_retries(&self) -> u64 {
+        self.retries_total.load(Ordering::Relaxed)
     }
 
     pub async fn emit(&self, messages: Vec<Message>) -> Result<(), reqwest::Error> {
-        self.client.post(&self.url)
-            .json(&messages)
+        let mut attempt = 0;
+        loop {
+            match self.send(&messages).await {
+                Ok(()) => return Ok(()),
+                Err(_) if attempt < self.retries => {
+                    attempt += 1;
+                    self.retries_total.fetch_add(1, Ordering::Relaxed);
+                    tokio::time::sleep(Duration::from_millis(250) * attempt).await;
+                }
+                Err(e) => {
+                    self.failures_total.fetch_add(1, Ordering::Relaxed);
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    async fn send(&self, messages: &[Message]) -> Result<(), reqwest::Error> {
+        let body = serde_json::to_vec(messages).expect("messages are always serializable");
+        let mut request = self.client.post(&self.url)
+            .header(CONTENT_TYPE, "application/json");
+
+        request = match &self.auth {
+            Some(Auth::Bearer(token)) => request.bearer_auth(token),
+            Some(Auth::Basic(username, password)) => request.basic_auth(username, password.as_ref()),
+            None => request,
+        };
+        for (name, value) in self.headers.iter() {
+            request = request.header(name, value);
+        }
+        if let Some(key) = &self.signing_key {
+            // signed on every attempt, so that retries carry a fresh timestamp
+            let timestamp = Utc::now().timestamp();
+            request = request
+                .header(Self::TIMESTAMP_HEADER, timestamp)
+                .header(Self::SIGNATURE_HEADER, Self::sign(key, timestamp, &body));
+        }
+
+        request.body(body)
+            .timeout(self.timeout)
             .send()
             .await?
             .error_for_status()?;
@@ -28,10 +230,48 @@ impl Emitter {
 
 #[cfg(test)]
 mod tests {
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use hyper::{Body, Request, Response, Server, StatusCode};
+    use hyper::header::HeaderMap;
+    use hyper::service::{make_service_fn, service_fn};
     use super::*;
     use crate::types::Message;
     use chrono::Utc;
 
+    type Requests = Arc<Mutex<Vec<(HeaderMap, Vec<u8>)>>>;
+
+    /// Serve a stand-in endpoint which records the headers and body of every request
+    ///
+    /// Requests without the expected authorization header are rejected.
+    fn serve(authorization: Option<&'static str>) -> (SocketAddr, Requests) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let log = requests.clone();
+        let make_service = make_service_fn(move |_| {
+            let log = log.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let log = log.clone();
+                    async move {
+                        let headers = req.headers().clone();
+                        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                        let authorized = headers.get("authorization")
+                            .and_then(|value| value.to_str().ok()) == authorization;
+                        log.lock().unwrap().push((headers, body.to_vec()));
+                        let status = if authorized { StatusCode::OK } else { StatusCode::UNAUTHORIZED };
+                        Ok::<_, Infallible>(Response::builder().status(status).body(Body::empty()).unwrap())
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        (addr, requests)
+    }
+
     #[tokio::test]
     async fn test_emit() {
         let emitter = Emitter::new("http://localhost:8000");
@@ -42,6 +282,172 @@ mod tests {
 
         // should fail
         assert!(emitter.emit(messages).await.is_err());
+        assert_eq!(emitter.get_failures(), 1);
+        assert_eq!(emitter.get_retries(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_emit_retries() {
+        let emitter = Emitter::new("http://localhost:8000").set_retries(2);
+        let messages = vec![Message::new("test_name", "value", Utc::now(), None)];
+
+        assert!(emitter.emit(messages).await.is_err());
+        assert_eq!(emitter.get_failures(), 1);
+        assert_eq!(emitter.get_retries(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_bearer_token() {
+        let (addr, requests) = serve(Some("Bearer secret"));
+        let messages = vec![Message::new("test_name", "value", Utc::now(), None)];
+
+        assert!(Emitter::new(format!("http://{}", addr)).emit(messages.clone()).await.is_err());
+        let emitter = Emitter::new(format!("http://{}", addr))
+            .set_bearer_token("secret")
+            .add_header("X-Site", "greenhouse");
+        emitter.emit(messages.clone()).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        let (headers, body) = &requests[1];
+        assert_eq!(headers["x-site"], "greenhouse");
+        assert_eq!(headers["content-type"], "application/json");
+        assert_eq!(serde_json::from_slice::<Vec<Message>>(body).unwrap(), messages);
+    }
+
+    #[tokio::test]
+    async fn test_basic_auth() {
+        // "user:pass" in base64
+        let (addr, _) = serve(Some("Basic dXNlcjpwYXNz"));
+        let emitter = Emitter::new(format!("http://{}", addr))
+            .set_basic_auth("user", Some("pass"));
+        let messages = vec![Message::new("test_name", "value", Utc::now(), None)];
+
+        assert!(emitter.emit(messages).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_signing() {
+        let (addr, requests) = serve(None);
+        let emitter = Emitter::new(format!("http://{}", addr))
+            .set_signing_key("key");
+        let messages = vec![Message::new("test_name", "value", Utc::now(), None)];
+        emitter.emit(messages).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        let (headers, body) = &requests[0];
+        let timestamp: i64 = headers[Emitter::TIMESTAMP_HEADER].to_str().unwrap().parse().unwrap();
+        assert!((Utc::now().timestamp() - timestamp).abs() <= 5);
+        assert_eq!(headers[Emitter::SIGNATURE_HEADER], Emitter::sign(b"key", timestamp, body).as_str());
+        assert_ne!(headers[Emitter::SIGNATURE_HEADER], Emitter::sign(b"other", timestamp, body).as_str());
+    }
+
+    #[test]
+    fn test_sign() {
+        // HMAC-SHA256 of "1700000000.[]" with the key "key"
+        assert_eq!(
+            Emitter::sign(b"key", 1700000000, b"[]"),
+            "sha256=2afce10b4f37880815b715635811bcfa4d5334e7213eea5bc2e130e1008055c3",
+        );
+    }
+
+    /// Serve a stand-in HTTPS endpoint which only accepts clients with a certificate signed by the
+    /// fixture CA
+    ///
+    /// Returns the port, and the common names of the clients whose requests were answered.
+    fn serve_tls() -> (u16, Arc<Mutex<Vec<String>>>) {
+        use std::io::{Read, Write as _};
+        use openssl::nid::Nid;
+        use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod, SslVerifyMode};
+
+        let fixture = |name: &str| format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor.set_private_key_file(fixture("server.key"), SslFiletype::PEM).unwrap();
+        acceptor.set_certificate_chain_file(fixture("server.pem")).unwrap();
+        acceptor.set_ca_file(fixture("ca.pem")).unwrap();
+        acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+        let acceptor = acceptor.build();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let log = clients.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                // clients without a valid certificate fail the handshake
+                let mut stream = match acceptor.accept(stream.unwrap()) {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                while !request.ends_with(b"]") {
+                    match stream.read(&mut buffer) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buffer[..n]),
+                    }
+                }
+                let name = stream.ssl().peer_certificate()
+                    .and_then(|certificate| {
+                        let entry = certificate.subject_name().entries_by_nid(Nid::COMMONNAME).next()?;
+                        entry.data().as_utf8().ok().map(|name| name.to_string())
+                    })
+                    .unwrap_or_default();
+                log.lock().unwrap().push(name);
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n");
+                let _ = stream.shutdown();
+            }
+        });
+        (port, clients)
+    }
+
+    #[test]
+    fn test_client_certificate() {
+        let emitter = Emitter::new("https://localhost:8443");
+        assert!(emitter.set_client_certificate(b"not a certificate", b"not a key").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_mutual_tls() {
+        let (port, clients) = serve_tls();
+        let url = format!("https://localhost:{}/messages", port);
+        let ca = include_bytes!("../tests/fixtures/ca.pem");
+        let messages = vec![Message::new("test_name", "value", Utc::now(), None)];
+
+        let emitter = Emitter::new(url.clone())
+            .add_root_certificate(ca).unwrap()
+            .set_client_certificate(
+                include_bytes!("../tests/fixtures/client.pem"),
+                include_bytes!("../tests/fixtures/client.key"),
+            ).unwrap();
+        emitter.emit(messages.clone()).await.unwrap();
+        assert_eq!(*clients.lock().unwrap(), vec![String::from("greenhouse-1")]);
+
+        // the server rejects clients without a certificate
+        let emitter = Emitter::new(url).add_root_certificate(ca).unwrap();
+        assert!(emitter.emit(messages).await.is_err());
+        assert_eq!(clients.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        // the endpoint accepts connections but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                connections.push(stream);
+            }
+        });
+
+        let emitter = Emitter::new(format!("http://{}", addr))
+            .set_timeout(chrono::Duration::milliseconds(200));
+        let messages = vec![Message::new("test_name", "value", Utc::now(), None)];
+        let result = tokio::time::timeout(Duration::from_secs(5), emitter.emit(messages))
+            .await
+            .expect("request did not time out");
+        assert!(result.unwrap_err().is_timeout());
+        assert_eq!(emitter.get_failures(), 1);
     }
 
     #[ignore]
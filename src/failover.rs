@@ -0,0 +1,404 @@
+//! Leader/standby failover between two nodes which run the same controllers
+//!
+//! For critical loops, such as aeration, the same [`ControllerGroup`](crate::ControllerGroup) may
+//! run on two nodes in hot-standby. Both nodes poll their controllers, but only the active node
+//! drives the outputs. Each output is created with [`Failover::output`], which discards writes
+//! while the node is on standby.
+//!
+//! The nodes learn about each other through the heartbeats of a [`Discovery`](crate::node::Discovery)
+//! (see [`Runtime::set_failover`](crate::Runtime::set_failover)). Every heartbeat carries the
+//! [`Leadership`] of the node. Every node starts on standby, and does not take over until the
+//! timeout has passed since it started, so that a restarting node hears its peer before it drives
+//! any output. A standby node takes over once the heartbeat of its peer has been missing for the
+//! timeout, and when both nodes are on standby, the primary node, or else the node with the lower
+//! ID, takes over. When a node becomes active, its outputs are driven to the state that its
+//! controllers have computed.
+//!
+//! # Fencing
+//! Every takeover increments the epoch, which is passed to the output callback as a fencing
+//! token. An active node steps down as soon as it sees an active peer with a higher epoch, but
+//! until then, a former leader which returns after a network partition could still write to the
+//! outputs. Actuators which are shared by both nodes, such as a relay behind a Modbus gateway,
+//! should therefore reject writes with an outdated epoch by using a [`Fence`].
+//!
+//! A fence never admits an epoch lower than the highest it has seen, so the epoch must survive a
+//! restart of both nodes. When the runtime has a [`StateStore`](crate::store::StateStore), the
+//! epoch is saved under [`Failover::STATE_NAME`] whenever the role changes, and restored on start.
+//!
+//! Heartbeat timestamps are taken by the sending node, so the clocks of both nodes should be
+//! synchronized, and the timeout should span several heartbeat intervals.
+//!
+//! # Example
+//! ```
+//! use chrono::Duration;
+//! use equilibrium::controllers::Threshold;
+//! use equilibrium::failover::{Failover, Fence};
+//! use equilibrium::{ControllerGroup, Input};
+//!
+//! // the fence would normally live on the actuator
+//! let fence = Fence::new();
+//! let failover = Failover::new("aeration-b", Duration::seconds(15)).set_primary();
+//!
+//! let aerator = failover.output(move |state, epoch| {
+//!     if fence.admit(epoch) {
+//!         // low-level code would go here
+//!     }
+//! });
+//! let group = ControllerGroup::new()
+//!     .add_controller(Threshold::new(5.0, Input::default(), aerator, Duration::minutes(1)));
+//! ```
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use crate::node::Heartbeat;
+use crate::Output;
+use crate::types::{ControllerState, Message};
+
+/// Whether a node drives its outputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Active,
+    Standby,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Active => write!(f, "Active"),
+            Role::Standby => write!(f, "Standby"),
+        }
+    }
+}
+
+/// The role of a node along with the epoch that it was acquired in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Leadership {
+    pub role: Role,
+    pub epoch: u64,
+}
+
+#[derive(Debug)]
+struct State {
+    leadership: Leadership,
+    started: Option<DateTime<Utc>>,
+}
+
+/// Decides whether this node or its peer is active
+///
+/// Clones share the same state, so outputs created from any clone follow the role of the node.
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug, Clone)]
+pub struct Failover {
+    peer: String,
+    timeout: Duration,
+    primary: bool,
+    state: Arc<Mutex<State>>,
+}
+
+impl Failover {
+    /// Name under which the epoch is saved in a [`StateStore`](crate::store::StateStore)
+    ///
+    /// The name starts with [`RESERVED_PREFIX`](crate::store::RESERVED_PREFIX), so it is never
+    /// overwritten by the snapshot of a controller.
+    pub const STATE_NAME: &'static str = "equilibrium:failover";
+
+    /// Create a failover which starts on standby
+    ///
+    /// # Arguments
+    /// * `peer` - The ID of the other node
+    /// * `timeout` - How long the heartbeat of the peer may be missing before taking over
+    pub fn new<S>(peer: S, timeout: Duration) -> Self
+        where S: Into<String>
+    {
+        Self {
+            peer: peer.into(),
+            timeout,
+            primary: false,
+            state: Arc::new(Mutex::new(State {
+                leadership: Leadership { role: Role::Standby, epoch: 0 },
+                started: None,
+            })),
+        }
+    }
+
+    /// Builder method to prefer this node when both nodes are on standby
+    ///
+    /// The primary node still starts on standby, and only takes over once the timeout has passed
+    /// without hearing an active peer. If both nodes take over in the same epoch, the node with the
+    /// higher ID steps down regardless.
+    pub fn set_primary(mut self) -> Self {
+        self.primary = true;
+        self
+    }
+
+    /// Take a snapshot of the epoch, so that it can be restored after a restart
+    ///
+    /// The epoch is stored as the input of the snapshot.
+    pub fn snapshot(&self) -> ControllerState {
+        ControllerState::new(Vec::new(), Vec::new())
+            .with_input(self.get_leadership().epoch.to_string())
+    }
+
+    /// Restore the epoch from a snapshot
+    ///
+    /// The epoch never decreases, and the node stays on standby.
+    pub fn restore(&self, state: &ControllerState) {
+        let epoch = state.get_input().as_ref().and_then(|epoch| epoch.parse::<u64>().ok());
+        if let Some(epoch) = epoch {
+            let mut state = self.state.lock().unwrap();
+            state.leadership.epoch = state.leadership.epoch.max(epoch);
+        }
+    }
+
+    pub fn get_peer(&self) -> &str {
+        &self.peer
+    }
+
+    pub fn get_leadership(&self) -> Leadership {
+        self.state.lock().unwrap().leadership
+    }
+
+    /// Returns true if this node drives its outputs
+    pub fn is_active(&self) -> bool {
+        self.get_leadership().role == Role::Active
+    }
+
+    /// Decide the role of this node from the latest heartbeat of the peer
+    ///
+    /// Returns a message if the role has changed, for example "Failover: Active (epoch 2)".
+    ///
+    /// # Arguments
+    /// * `id` - The ID of this node
+    /// * `peer` - The latest heartbeat of the peer, if any has been received
+    /// * `time` - The current time
+    pub fn observe(&self, id: &str, peer: Option<&Heartbeat>, time: DateTime<Utc>) -> Option<Message> {
+        let mut state = self.state.lock().unwrap();
+        let started = *state.started.get_or_insert(time);
+        let starting = time - started < self.timeout;
+        let current = state.leadership;
+
+        let last_seen = peer.map_or(started, |heartbeat| heartbeat.get_timestamp().max(started));
+        let alive = time - last_seen <= self.timeout;
+        let theirs = peer.and_then(|heartbeat| heartbeat.get_leadership());
+        let outranked = self.peer.as_str() < id;
+
+        let epoch = theirs.map_or(current.epoch, |theirs| theirs.epoch.max(current.epoch));
+
+        let next = match (current.role, alive, theirs) {
+            // the peer has taken over, or both took over at once and the peer wins the tie
+            (Role::Active, true, Some(Leadership { role: Role::Active, epoch }))
+                if epoch > current.epoch || (epoch == current.epoch && outranked) =>
+            {
+                Leadership { role: Role::Standby, epoch }
+            }
+            (Role::Active, _, _) => current,
+
+            // the peer may not have been heard yet
+            (Role::Standby, _, _) if starting => Leadership { role: Role::Standby, epoch },
+            // the peer is silent
+            (Role::Standby, false, _) => Leadership { role: Role::Active, epoch: epoch + 1 },
+            // both are on standby and this node wins the tie
+            (Role::Standby, true, Some(Leadership { role: Role::Standby, .. })) if self.primary || !outranked => {
+                Leadership { role: Role::Active, epoch: epoch + 1 }
+            }
+            (Role::Standby, true, _) => Leadership { role: Role::Standby, epoch },
+        };
+        state.leadership = next;
+
+        match next.role == current.role {
+            true => None,
+            false => Some(Message::new(
+                String::from("failover"),
+                format!("Failover: {} (epoch {})", next.role, next.epoch),
+                time,
+                None,
+            )),
+        }
+    }
+
+    /// Create an [`Output`] which only calls `callback` while this node is active
+    ///
+    /// The callback receives the current epoch as a fencing token along with the state.
+    pub fn output<F>(&self, mut callback: F) -> Output<impl FnMut(bool) + Send>
+        where F: FnMut(bool, u64) + Send
+    {
+        let state = self.state.clone();
+        Output::new(move |value| {
+            let leadership = state.lock().unwrap().leadership;
+            if leadership.role == Role::Active {
+                callback(value, leadership.epoch);
+            }
+        })
+    }
+}
+
+/// Rejects writes that carry an outdated epoch
+///
+/// Clones share the highest epoch that has been admitted.
+#[derive(Debug, Clone, Default)]
+pub struct Fence {
+    epoch: Arc<AtomicU64>,
+}
+
+impl Fence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns true if the epoch is at least as recent as every epoch admitted so far
+    pub fn admit(&self, epoch: u64) -> bool {
+        self.epoch.fetch_max(epoch, Ordering::SeqCst) <= epoch
+    }
+
+    /// Get the highest epoch that has been admitted
+    pub fn get_epoch(&self) -> u64 {
+        self.epoch.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::node::Health;
+    use crate::types::NodeIdentity;
+    use super::*;
+
+    fn heartbeat(id: &str, time: DateTime<Utc>, role: Role, epoch: u64) -> Heartbeat {
+        Heartbeat::new(NodeIdentity::new(id), time, Duration::zero(), 1, Health::Healthy)
+            .set_leadership(Leadership { role, epoch })
+    }
+
+    #[test]
+    fn test_takeover() {
+        let failover = Failover::new("a", Duration::seconds(10));
+        let start = Utc::now();
+
+        // the peer is active
+        assert!(failover.observe("b", Some(&heartbeat("a", start, Role::Active, 1)), start).is_none());
+        assert_eq!(failover.get_leadership(), Leadership { role: Role::Standby, epoch: 1 });
+
+        // the heartbeat of the peer stops
+        let later = start + Duration::seconds(5);
+        assert!(failover.observe("b", Some(&heartbeat("a", start, Role::Active, 1)), later).is_none());
+        let later = start + Duration::seconds(11);
+        let message = failover.observe("b", Some(&heartbeat("a", start, Role::Active, 1)), later).unwrap();
+        assert_eq!(message.get_content(), "Failover: Active (epoch 2)");
+        assert!(failover.is_active());
+    }
+
+    #[test]
+    fn test_returning_leader() {
+        let failover = Failover::new("b", Duration::seconds(10));
+        let now = Utc::now();
+        failover.observe("a", None, now - Duration::seconds(11));
+        failover.observe("a", None, now);
+        assert_eq!(failover.get_leadership(), Leadership { role: Role::Active, epoch: 1 });
+
+        // a peer on standby, or with an outdated epoch, does not cause the leader to step down
+        assert!(failover.observe("a", Some(&heartbeat("b", now, Role::Standby, 1)), now).is_none());
+        assert!(failover.observe("a", Some(&heartbeat("b", now, Role::Active, 1)), now).is_none());
+
+        let message = failover.observe("a", Some(&heartbeat("b", now, Role::Active, 2)), now).unwrap();
+        assert_eq!(message.get_content(), "Failover: Standby (epoch 2)");
+        assert!(!failover.is_active());
+    }
+
+    #[test]
+    fn test_tie_break() {
+        let start = Utc::now();
+        let now = start + Duration::seconds(10);
+        // the node with the lower ID takes over when both are on standby
+        let a = Failover::new("b", Duration::seconds(10));
+        let b = Failover::new("a", Duration::seconds(10));
+        a.observe("a", None, start);
+        b.observe("b", None, start);
+        assert!(a.observe("a", Some(&heartbeat("b", now, Role::Standby, 0)), now).is_some());
+        assert!(b.observe("b", Some(&heartbeat("a", now, Role::Standby, 0)), now).is_none());
+
+        // the node with the higher ID steps down when both took over in the same epoch
+        assert!(b.observe("b", None, now + Duration::seconds(11)).is_some());
+        let later = now + Duration::seconds(11);
+        assert!(b.observe("b", Some(&heartbeat("a", later, Role::Active, 1)), later).is_some());
+        assert!(!b.is_active());
+        assert!(a.observe("a", Some(&heartbeat("b", now, Role::Active, 1)), now).is_none());
+        assert!(a.is_active());
+    }
+
+    #[test]
+    fn test_startup() {
+        let start = Utc::now();
+        let failover = Failover::new("b", Duration::seconds(10)).set_primary();
+
+        // the primary does not take over before it has had a chance to hear its peer
+        assert!(failover.observe("a", None, start).is_none());
+        let peer = heartbeat("b", start + Duration::seconds(5), Role::Standby, 0);
+        assert!(failover.observe("c", Some(&peer), start + Duration::seconds(5)).is_none());
+        assert!(!failover.is_active());
+
+        // it wins the tie despite the higher ID
+        let peer = heartbeat("b", start + Duration::seconds(10), Role::Standby, 0);
+        let message = failover.observe("c", Some(&peer), start + Duration::seconds(10)).unwrap();
+        assert_eq!(message.get_content(), "Failover: Active (epoch 1)");
+    }
+
+    #[test]
+    fn test_restart_both() {
+        let start = Utc::now();
+        let fence = Fence::new();
+        assert!(fence.admit(7));
+
+        // both nodes restart, one of them having been active in epoch 7
+        let (a, b) = (Failover::new("b", Duration::seconds(10)), Failover::new("a", Duration::seconds(10)));
+        a.restore(&Failover::new("b", Duration::zero()).snapshot());
+        b.restore(&ControllerState::new(Vec::new(), Vec::new()).with_input(String::from("7")));
+        assert_eq!(b.get_leadership(), Leadership { role: Role::Standby, epoch: 7 });
+        assert_eq!(b.snapshot().get_input(), &Some(String::from("7")));
+
+        a.observe("a", None, start);
+        b.observe("b", None, start);
+        let later = start + Duration::seconds(10);
+        let heartbeat_b = heartbeat("b", later, Role::Standby, b.get_leadership().epoch);
+        a.observe("a", Some(&heartbeat_b), later).unwrap();
+
+        // the new leader is admitted by the fence
+        assert_eq!(a.get_leadership(), Leadership { role: Role::Active, epoch: 8 });
+        assert!(fence.admit(a.get_leadership().epoch));
+    }
+
+    #[test]
+    fn test_state_name() {
+        assert!(Failover::STATE_NAME.starts_with(crate::store::RESERVED_PREFIX));
+    }
+
+    #[test]
+    fn test_output() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let failover = Failover::new("a", Duration::seconds(10));
+        let log = written.clone();
+        let mut output = failover.output(move |state, epoch| log.lock().unwrap().push((state, epoch)));
+
+        // writes are discarded on standby
+        output.activate();
+        assert!(written.lock().unwrap().is_empty());
+        assert_eq!(output.get_state(), Some(true));
+
+        let now = Utc::now();
+        failover.observe("b", None, now);
+        failover.observe("b", None, now + Duration::seconds(11));
+        output.activate();
+        assert_eq!(*written.lock().unwrap(), vec![(true, 1)]);
+    }
+
+    #[test]
+    fn test_fence() {
+        let fence = Fence::new();
+        assert!(fence.admit(1));
+        assert!(fence.admit(2));
+        assert!(!fence.admit(1));
+        assert!(fence.admit(2));
+        assert_eq!(fence.get_epoch(), 2);
+    }
+}
@@ -1,6 +1,18 @@
-use chrono::{DateTime, Utc};
+use std::time::Instant;
+use chrono::{DateTime, Duration, Utc};
+use crate::arbiter::OutputArbiter;
 use crate::controllers::Controller;
-use crate::types::Message;
+use crate::sensors::SensorRegistry;
+use crate::store::{StateError, StateStore, RESERVED_PREFIX};
+use crate::types::{Command, CommandError, ControllerStatus, Message, Mode};
+
+/// Operating mode that is applied to a controller by the group, and how long it was last polled for
+#[derive(Debug, Clone, Default)]
+struct Supervision {
+    mode: Mode,
+    expires: Option<DateTime<Utc>>,
+    poll_duration: Option<std::time::Duration>,
+}
 
 /// A container for handling multiple controllers
 ///
@@ -10,8 +22,22 @@ use crate::types::Message;
 /// Once a controller is added to the group, it is owned by the group and can only be accessed via
 /// the `Controller` trait. This means that any other methods exposed by the controller are not
 /// accessible.
+///
+/// Named controllers may be changed at runtime by sending a [`Command`] via
+/// [`ControllerGroup::execute`]. Each controller has a [`Mode`], and only controllers in
+/// [`Mode::Auto`] are polled. Every mode change is reported as a [`Message`] which is returned by
+/// the next call to [`ControllerGroup::poll`].
+///
+/// Controllers which share a sensor should read it through a [`SensorRegistry`] that is attached
+/// with [`ControllerGroup::set_sensors`], so that the sensor is only sampled once per poll.
+/// Similarly, controllers which command the same actuator should do so through an
+/// [`OutputArbiter`] that is attached with [`ControllerGroup::add_arbiter`].
 pub struct ControllerGroup {
     controllers: Vec<Box<dyn Controller>>,
+    supervision: Vec<Supervision>,
+    pending: Vec<Message>,
+    sensors: Option<SensorRegistry>,
+    arbiters: Vec<OutputArbiter>,
 }
 
 impl ControllerGroup {
@@ -19,6 +45,10 @@ impl ControllerGroup {
     pub fn new() -> Self {
         Self {
             controllers: Vec::new(),
+            supervision: Vec::new(),
+            pending: Vec::new(),
+            sensors: None,
+            arbiters: Vec::new(),
         }
     }
 
@@ -52,6 +82,29 @@ impl ControllerGroup {
     {
         let wrapped = Box::new(controller);
         self.controllers.push(wrapped);
+        self.supervision.push(Supervision::default());
+        self
+    }
+
+    /// Builder method to attach a [`SensorRegistry`]
+    ///
+    /// A new tick of the registry is started every time the group is polled.
+    pub fn set_sensors(mut self, sensors: SensorRegistry) -> Self {
+        self.sensors = Some(sensors);
+        self
+    }
+
+    /// Returns the attached [`SensorRegistry`], if any
+    pub fn get_sensors(&self) -> Option<&SensorRegistry> {
+        self.sensors.as_ref()
+    }
+
+    /// Builder method to attach an [`OutputArbiter`]
+    ///
+    /// Arbitrations are reported after the messages of the controllers, and the actuator is
+    /// driven to the safe state of the arbiter on shutdown.
+    pub fn add_arbiter(mut self, arbiter: OutputArbiter) -> Self {
+        self.arbiters.push(arbiter);
         self
     }
 
@@ -71,27 +124,264 @@ impl ControllerGroup {
     /// # Arguments
     /// * `time` - The time to poll the controllers
     ///
+    /// Any mode changes since the last poll are returned before the messages of the controllers.
+    ///
     /// # Returns
     /// A vector of any [`Message`]s that were returned by the controllers. If no messages were
     /// returned, an empty vector is returned.
     pub fn poll(&mut self, time: DateTime<Utc>) -> Vec<Message> {
-        let mut messages = Vec::new();
+        if let Some(sensors) = &self.sensors {
+            sensors.tick(time);
+        }
+
+        // hand back to automatic control once an override has expired
+        for index in 0..self.controllers.len() {
+            if self.supervision[index].expires.is_some_and(|expires| expires <= time) {
+                self.set_mode(index, Mode::Auto, None, time);
+            }
+        }
+
+        let mut messages = std::mem::take(&mut self.pending);
+        for (controller, supervision) in self.controllers.iter_mut().zip(self.supervision.iter_mut()) {
+            if supervision.mode != Mode::Auto {
+                continue;
+            }
+
+            let started = Instant::now();
+            let message = controller.poll(time);
+            supervision.poll_duration = Some(started.elapsed());
+            if let Some(message) = message {
+                messages.push(message);
+            }
+        }
+        if let Some(sensors) = &self.sensors {
+            messages.extend(sensors.drain_messages());
+        }
+        for arbiter in self.arbiters.iter() {
+            messages.extend(arbiter.drain_messages(time));
+        }
+        messages
+    }
+
+    /// Drive the outputs of every controller to their safe states
+    ///
+    /// This should be called once, when the group is no longer going to be polled. Any pending
+    /// mode changes are returned along with the messages of the controllers.
+    pub fn shutdown(&mut self, time: DateTime<Utc>) -> Vec<Message> {
+        let mut messages = std::mem::take(&mut self.pending);
         for controller in self.controllers.iter_mut() {
-            if let Some(message) = controller.poll(time) {
+            if let Some(message) = controller.shutdown(time) {
                 messages.push(message);
             }
         }
+        for arbiter in self.arbiters.iter() {
+            messages.extend(arbiter.drain_messages(time));
+            messages.extend(arbiter.apply_safe_state(time));
+        }
         messages
     }
+
+    /// Returns the index of the controller with the given name
+    fn position(&self, name: &str) -> Result<usize, CommandError> {
+        self.controllers.iter()
+            .position(|c| c.get_name().as_deref() == Some(name))
+            .ok_or_else(|| CommandError::UnknownController(name.to_string()))
+    }
+
+    /// Execute a [`Command`] on the controller with the given name
+    ///
+    /// # Arguments
+    /// * `name` - The name of the controller
+    /// * `command` - The command to execute
+    /// * `time` - The current time, used to determine when forced outputs expire
+    pub fn execute(&mut self, name: &str, command: Command, time: DateTime<Utc>) -> Result<(), CommandError> {
+        let index = self.position(name)?;
+
+        match command.get_mode() {
+            Some((Mode::Auto, Some(_))) => Err(CommandError::Failed(
+                String::from("automatic mode cannot expire"),
+            )),
+            Some((mode, expires_in)) => {
+                let expires = expires_in.map(|seconds| expiry(time, seconds)).transpose()?;
+                hold(self.controllers[index].as_mut(), mode)?;
+                self.set_mode(index, mode, expires, time);
+                Ok(())
+            }
+            None => match command {
+                Command::SetSetpoint { value } => self.controllers[index].set_setpoint(value),
+                Command::SetInterval { seconds } => {
+                    self.controllers[index].set_interval(interval(seconds)?)
+                }
+                _ => Err(CommandError::Unsupported),
+            },
+        }
+    }
+
+    /// Change the mode of a controller and record the change as a pending [`Message`]
+    ///
+    /// The outputs of a manual mode must already be held with [`hold`].
+    ///
+    /// # Arguments
+    /// * `index` - Index of the controller
+    /// * `mode` - The new mode
+    /// * `expires` - When to revert to [`Mode::Auto`]. This is ignored for [`Mode::Auto`].
+    /// * `time` - The current time
+    fn set_mode(
+        &mut self,
+        index: usize,
+        mode: Mode,
+        expires: Option<DateTime<Utc>>,
+        time: DateTime<Utc>,
+    ) {
+        let controller = &mut self.controllers[index];
+        let previous = self.supervision[index].mode;
+
+        let supervision = &mut self.supervision[index];
+        supervision.mode = mode;
+        supervision.expires = if mode == Mode::Auto { None } else { expires };
+
+        let name = controller.get_name().unwrap_or_default();
+        self.pending.push(Message::new(
+            name,
+            format!("Mode Changed: {} -> {}", previous, mode),
+            time,
+            None,
+        ));
+
+        if mode == Mode::Auto && previous != Mode::Auto {
+            if let Some(message) = controller.resume(time) {
+                self.pending.push(message);
+            }
+        }
+    }
+
+    /// Returns the status of every controller in the group
+    pub fn status(&self) -> Vec<ControllerStatus> {
+        self.controllers.iter()
+            .zip(self.supervision.iter())
+            .map(|(controller, supervision)| {
+                let snapshot = controller.snapshot();
+                ControllerStatus {
+                    name: controller.get_name().unwrap_or_default(),
+                    kind: controller.get_kind().to_string(),
+                    mode: supervision.mode,
+                    mode_expires: supervision.expires,
+                    outputs: snapshot.as_ref()
+                        .map(|s| s.get_outputs().clone())
+                        .unwrap_or_default(),
+                    last_reading: snapshot.as_ref().and_then(|s| s.get_input().clone()),
+                    setpoint: snapshot.as_ref().and_then(|s| s.get_setpoint()),
+                    next_event: snapshot.as_ref().and_then(|s| {
+                        s.get_events().iter().map(|e| *e.get_timestamp()).min()
+                    }),
+                    pending_events: snapshot.as_ref().map_or(0, |s| s.get_events().len()),
+                    poll_duration: supervision.poll_duration.map(|d| d.as_secs_f64()),
+                }
+            })
+            .collect()
+    }
+
+    /// Save a snapshot of every named controller to a [`StateStore`]
+    ///
+    /// Controllers are keyed by name, therefore unnamed controllers and controllers that do not
+    /// support snapshots are skipped, as are controllers whose names start with
+    /// [`RESERVED_PREFIX`]. The snapshot includes the mode of the controller.
+    pub fn save_state(&self, store: &mut dyn StateStore) -> Result<(), StateError> {
+        for (controller, supervision) in self.controllers.iter().zip(self.supervision.iter()) {
+            if let (Some(name), Some(state)) = (stored_name(controller.as_ref()), controller.snapshot()) {
+                store.save(&name, &state.with_mode(supervision.mode, supervision.expires))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Restore every named controller from the snapshots held by a [`StateStore`]
+    ///
+    /// Controllers without a saved snapshot, and those skipped by [`ControllerGroup::save_state`],
+    /// are left untouched. Manual modes are applied again, so
+    /// that the outputs are held as they were before the restart. A mode which has expired in the
+    /// meantime reverts to [`Mode::Auto`] on the next poll.
+    pub fn restore_state(&mut self, store: &dyn StateStore) -> Result<(), StateError> {
+        for (controller, supervision) in self.controllers.iter_mut().zip(self.supervision.iter_mut()) {
+            let name = match stored_name(controller.as_ref()) {
+                Some(name) => name,
+                None => continue,
+            };
+            let state = match store.load(&name)? {
+                Some(state) => state,
+                None => continue,
+            };
+            let (mode, expires) = (state.get_mode(), state.get_mode_expires());
+            controller.restore(state);
+
+            match hold(controller.as_mut(), mode) {
+                Ok(()) => {
+                    supervision.mode = mode;
+                    supervision.expires = expires;
+                }
+                Err(e) => eprintln!("Failed to restore the mode of {}: {}", name, e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the name under which a controller is kept in a [`StateStore`], if it may be stored
+fn stored_name(controller: &dyn Controller) -> Option<String> {
+    controller.get_name().filter(|name| !name.starts_with(RESERVED_PREFIX))
+}
+
+/// Hold the outputs of a controller as required by a manual [`Mode`]
+///
+/// Nothing is done for [`Mode::Auto`] and [`Mode::Disabled`].
+fn hold(controller: &mut dyn Controller, mode: Mode) -> Result<(), CommandError> {
+    match mode {
+        Mode::ManualOn => controller.force_output(true),
+        Mode::ManualOff => controller.force_output(false),
+        Mode::Auto | Mode::Disabled => Ok(()),
+    }
+}
+
+/// Returns when an override that is requested at `time` for the given number of seconds expires
+///
+/// The number of seconds comes from a remote request, therefore anything which is not positive
+/// or does not fit in a [`DateTime`] is rejected rather than allowed to panic.
+fn expiry(time: DateTime<Utc>, seconds: i64) -> Result<DateTime<Utc>, CommandError> {
+    if seconds <= 0 {
+        return Err(CommandError::Failed(format!("expiry must be positive: {}", seconds)));
+    }
+    Duration::try_seconds(seconds)
+        .and_then(|duration| time.checked_add_signed(duration))
+        .ok_or_else(|| CommandError::Failed(format!("expiry is out of range: {}", seconds)))
+}
+
+/// Returns the read interval that is requested for the given number of seconds
+///
+/// An interval which is not positive would schedule reads in the past, and is rejected.
+fn interval(seconds: i64) -> Result<Duration, CommandError> {
+    if seconds <= 0 {
+        return Err(CommandError::Failed(format!("interval must be positive: {}", seconds)));
+    }
+    Duration::try_seconds(seconds)
+        .ok_or_else(|| CommandError::Failed(format!("interval is out of range: {}", seconds)))
+}
+
+impl Default for ControllerGroup {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::clone_on_copy)]
 mod tests {
 use super::*;
     use crate::controllers::{TimedOutput, Threshold};
     use crate::Output;
     use crate::Input;
+    use crate::store::MemoryStateStore;
+    use crate::types::Mode;
     use chrono::{Duration, NaiveTime, TimeZone};
 
     #[test]
@@ -176,4 +466,421 @@ use super::*;
         assert_eq!(messages[0].get_controller_name(), timed_output_name);
         assert_eq!(messages[1].get_controller_name(), threshold_name);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_shared_sensor() {
+        use crate::sensors::SensorRegistry;
+        use std::sync::{Arc, Mutex};
+
+        let now = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+
+        // the probe returns a different value every time it is sampled
+        let samples = Arc::new(Mutex::new(68));
+        let probe = samples.clone();
+        let sensors = SensorRegistry::new()
+            .add_sensor("probe", move || {
+                let mut value = probe.lock().unwrap();
+                *value += 1;
+                value.to_string()
+            }, Duration::zero());
+
+        let mut heater = Threshold::new_without_scheduled(
+            70.0,
+            sensors.input("probe").unwrap(),
+            Output::default(),
+            Duration::minutes(5),
+        ).set_inverted().schedule_next(now);
+        heater.set_name(String::from("heater"));
+
+        let mut alarm = Threshold::new_without_scheduled(
+            90.0,
+            sensors.input("probe").unwrap(),
+            Output::default(),
+            Duration::minutes(5),
+        ).schedule_next(now);
+        alarm.set_name(String::from("alarm"));
+
+        let mut group = ControllerGroup::new()
+            .add_controller(heater)
+            .add_controller(alarm)
+            .set_sensors(sensors);
+
+        // both controllers see the same reading
+        let messages = group.poll(now + Duration::minutes(5));
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].get_read_state(), Some(String::from("69")));
+        assert_eq!(messages[1].get_read_state(), Some(String::from("69")));
+        assert_eq!(*samples.lock().unwrap(), 69);
+
+        let reading = group.get_sensors().unwrap().get_reading("probe").unwrap();
+        assert_eq!(reading.get_timestamp(), now + Duration::minutes(5));
+    }
+
+    #[test]
+    fn test_arbiter() {
+        use crate::arbiter::{OutputArbiter, Resolution};
+        use std::sync::{Arc, Mutex};
+
+        let now = Utc.with_ymd_and_hms(2021, 1, 1, 4, 59, 59).unwrap();
+
+        let external_state = Arc::new(Mutex::new(None));
+        let state = external_state.clone();
+        let aerator = OutputArbiter::new("aerator", move |value| {
+            *state.lock().unwrap() = Some(value);
+        }, Resolution::Priority).set_safe_state(true);
+
+        let mut schedule = TimedOutput::new_without_scheduled(
+            aerator.output("schedule", 0),
+            NaiveTime::from_hms_opt(5, 0, 0).unwrap(),
+            Duration::hours(1),
+        ).schedule_first(now);
+        schedule.set_name(String::from("schedule"));
+
+        // dissolved oxygen is low, so the aerator must run
+        let mut oxygen = Threshold::new_without_scheduled(
+            5.0,
+            Input::new(|| "4.0".to_string()),
+            aerator.output("oxygen", 10),
+            Duration::minutes(5),
+        ).set_inverted().schedule_next(now);
+        oxygen.set_name(String::from("oxygen"));
+
+        let mut group = ControllerGroup::new()
+            .add_controller(schedule)
+            .add_controller(oxygen)
+            .add_arbiter(aerator);
+
+        group.poll(now + Duration::seconds(1));
+        group.poll(now + Duration::minutes(5));
+
+        // the schedule turns the aerator off, but is overruled
+        let messages = group.poll(Utc.with_ymd_and_hms(2021, 1, 1, 6, 0, 0).unwrap());
+        let last = messages.last().unwrap();
+        assert_eq!(last.get_controller_name(), "aerator");
+        assert_eq!(last.get_content(), "Arbitrated On by oxygen; overruled schedule (Off)");
+        assert_eq!(*external_state.lock().unwrap(), Some(true));
+
+        let messages = group.shutdown(Utc::now());
+        assert_eq!(messages.last().unwrap().get_content(), "Safe State");
+    }
+
+    #[test]
+    fn test_save_restore_state() {
+        let now = Utc.with_ymd_and_hms(2021, 1, 1, 4, 59, 59).unwrap();
+
+        let build_group = || {
+            let mut timed = TimedOutput::new_without_scheduled(
+                Output::default(),
+                NaiveTime::from_hms_opt(5, 0, 0).unwrap(),
+                Duration::hours(12),
+            ).schedule_first(now);
+            timed.set_name(String::from("timed"));
+
+            let mut threshold = Threshold::new_without_scheduled(
+                70.0,
+                Input::new(|| "69.0".to_string()),
+                Output::default(),
+                Duration::minutes(5),
+            ).schedule_next(now);
+            threshold.set_name(String::from("threshold"));
+
+            ControllerGroup::new()
+                .add_controller(timed)
+                .add_controller(threshold)
+        };
+
+        // activate the timed output and save state
+        let mut group = build_group();
+        let time = now + Duration::seconds(1);
+        assert_eq!(group.poll(time).len(), 1);
+
+        let mut store = MemoryStateStore::new();
+        group.save_state(&mut store).unwrap();
+
+        // a restarted group resumes the saved schedule, so the output is not activated again
+        let mut group = build_group();
+        group.restore_state(&store).unwrap();
+        let messages = group.poll(time + Duration::seconds(1));
+        assert_eq!(messages.len(), 0);
+
+        let messages = group.poll(Utc.with_ymd_and_hms(2021, 1, 1, 17, 0, 0).unwrap());
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].get_content(), "Deactivated");
+    }
+
+    #[test]
+    fn test_reserved_names() {
+        use crate::failover::Failover;
+        use crate::types::ControllerState;
+
+        let mut controller = Threshold::new(
+            70.0,
+            Input::new(|| "69.0".to_string()),
+            Output::default(),
+            Duration::minutes(5),
+        );
+        controller.set_name(Failover::STATE_NAME.to_string());
+        let mut group = ControllerGroup::new()
+            .add_controller(controller);
+
+        // the snapshot of the runtime is neither overwritten nor restored into the controller
+        let epoch = ControllerState::new(Vec::new(), Vec::new()).with_input(String::from("7"));
+        let mut store = MemoryStateStore::new();
+        store.save(Failover::STATE_NAME, &epoch).unwrap();
+
+        group.save_state(&mut store).unwrap();
+        assert_eq!(store.load(Failover::STATE_NAME).unwrap(), Some(epoch));
+        group.restore_state(&store).unwrap();
+        assert_eq!(group.status()[0].get_last_reading(), &None);
+    }
+
+    #[test]
+    fn test_execute() {
+        let now = Utc.with_ymd_and_hms(2021, 1, 1, 4, 59, 59).unwrap();
+
+        let mut controller = Threshold::new_without_scheduled(
+            70.0,
+            Input::new(|| "69.0".to_string()),
+            Output::default(),
+            Duration::minutes(5),
+        ).schedule_next(now);
+        controller.set_name(String::from("heater"));
+
+        let mut group = ControllerGroup::new()
+            .add_controller(controller);
+
+        // unknown controllers are rejected
+        let result = group.execute("cooler", Command::Disable, now);
+        assert_eq!(result, Err(CommandError::UnknownController(String::from("cooler"))));
+
+        // change the setpoint
+        group.execute("heater", Command::SetSetpoint { value: 72.0 }, now).unwrap();
+        assert_eq!(group.status()[0].get_setpoint(), Some(72.0));
+
+        // disabled controllers are not polled, only the mode change is reported
+        group.execute("heater", Command::Disable, now).unwrap();
+        assert_eq!(group.status()[0].get_mode(), Mode::Disabled);
+
+        let messages = group.poll(now + Duration::minutes(5));
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].get_content(), "Mode Changed: Auto -> Disabled");
+
+        group.execute("heater", Command::Enable, now).unwrap();
+        let messages = group.poll(now + Duration::minutes(5));
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].get_content(), "Mode Changed: Disabled -> Auto");
+        assert_eq!(messages[1].get_content(), "Below Threshold");
+        assert_eq!(group.status()[0].get_outputs(), &vec![Some(false)]);
+
+        // the new interval applies after the read that is already scheduled
+        group.execute("heater", Command::SetInterval { seconds: 60 }, now).unwrap();
+        assert_eq!(group.poll(now + Duration::minutes(10)).len(), 1);
+        assert_eq!(group.poll(now + Duration::minutes(11)).len(), 1);
+
+        // intervals which are not positive or out of range are rejected
+        for seconds in [0, -60, i64::MAX] {
+            let result = group.execute("heater", Command::SetInterval { seconds }, now);
+            assert!(matches!(result, Err(CommandError::Failed(_))), "{}", seconds);
+        }
+        assert!(group.poll(now + Duration::minutes(11) + Duration::seconds(30)).is_empty());
+        assert_eq!(group.poll(now + Duration::minutes(12)).len(), 1);
+    }
+
+    #[test]
+    fn test_execute_invalid_expiry() {
+        let now = Utc.with_ymd_and_hms(2021, 1, 1, 4, 59, 59).unwrap();
+
+        let mut controller = Threshold::new_without_scheduled(
+            70.0,
+            Input::new(|| "69.0".to_string()),
+            Output::default(),
+            Duration::minutes(1),
+        ).schedule_next(now);
+        controller.set_name(String::from("heater"));
+
+        let mut group = ControllerGroup::new()
+            .add_controller(controller);
+
+        for expires_in in [0, -600, 9_000_000_000_000, i64::MAX] {
+            let command = Command::Force { state: true, expires_in: Some(expires_in) };
+            let result = group.execute("heater", command, now);
+            assert!(matches!(result, Err(CommandError::Failed(_))), "{}", expires_in);
+        }
+
+        // the controller is left untouched
+        let status = &group.status()[0];
+        assert_eq!(status.get_mode(), Mode::Auto);
+        assert_eq!(status.get_outputs(), &vec![None]);
+        assert!(group.poll(now).is_empty());
+    }
+
+    #[test]
+    fn test_execute_set_mode_expiry() {
+        let now = Utc.with_ymd_and_hms(2021, 1, 1, 4, 59, 59).unwrap();
+
+        let mut controller = Threshold::new_without_scheduled(
+            70.0,
+            Input::new(|| "69.0".to_string()),
+            Output::default(),
+            Duration::minutes(1),
+        ).schedule_next(now);
+        controller.set_name(String::from("heater"));
+
+        let mut group = ControllerGroup::new()
+            .add_controller(controller);
+
+        let command = Command::SetMode { mode: Mode::ManualOff, expires_in: Some(i64::MAX) };
+        assert!(matches!(group.execute("heater", command, now), Err(CommandError::Failed(_))));
+
+        // automatic mode is never overridden, so it cannot expire
+        let command = Command::SetMode { mode: Mode::Auto, expires_in: Some(600) };
+        assert!(matches!(group.execute("heater", command, now), Err(CommandError::Failed(_))));
+        assert!(group.poll(now).is_empty());
+
+        let command = Command::SetMode { mode: Mode::Disabled, expires_in: Some(600) };
+        group.execute("heater", command, now).unwrap();
+        assert_eq!(group.status()[0].get_mode_expires(), Some(now + Duration::minutes(10)));
+    }
+
+    #[test]
+    fn test_save_restore_mode() {
+        use std::sync::{Arc, Mutex};
+
+        let now = Utc.with_ymd_and_hms(2021, 1, 1, 4, 59, 59).unwrap();
+
+        let build_group = |writes: Arc<Mutex<Vec<bool>>>| {
+            let mut controller = Threshold::new_without_scheduled(
+                70.0,
+                Input::new(|| "69.0".to_string()),
+                Output::new(move |state| writes.lock().unwrap().push(state)),
+                Duration::minutes(1),
+            ).schedule_next(now);
+            controller.set_name(String::from("heater"));
+            ControllerGroup::new().add_controller(controller)
+        };
+
+        let mut group = build_group(Arc::new(Mutex::new(Vec::new())));
+        let command = Command::Force { state: true, expires_in: Some(600) };
+        group.execute("heater", command, now).unwrap();
+        let mut store = MemoryStateStore::new();
+        group.save_state(&mut store).unwrap();
+
+        // the restarted group holds the output on until the override expires
+        let writes = Arc::new(Mutex::new(Vec::new()));
+        let mut group = build_group(writes.clone());
+        group.restore_state(&store).unwrap();
+        assert_eq!(writes.lock().unwrap().last(), Some(&true));
+        let status = &group.status()[0];
+        assert_eq!(status.get_mode(), Mode::ManualOn);
+        assert_eq!(status.get_mode_expires(), Some(now + Duration::minutes(10)));
+        assert!(group.poll(now + Duration::minutes(5)).is_empty());
+
+        let messages = group.poll(now + Duration::minutes(10));
+        assert_eq!(messages[0].get_content(), "Mode Changed: Manual On -> Auto");
+        assert_eq!(group.status()[0].get_mode(), Mode::Auto);
+    }
+
+    #[test]
+    fn test_execute_force() {
+        let now = Utc.with_ymd_and_hms(2021, 1, 1, 4, 59, 59).unwrap();
+
+        let mut controller = Threshold::new_without_scheduled(
+            70.0,
+            Input::new(|| "69.0".to_string()),
+            Output::default(),
+            Duration::minutes(1),
+        ).schedule_next(now);
+        controller.set_name(String::from("heater"));
+
+        let mut group = ControllerGroup::new()
+            .add_controller(controller);
+
+        // force the output on for 10 minutes
+        let command = Command::Force { state: true, expires_in: Some(600) };
+        group.execute("heater", command, now).unwrap();
+
+        let status = &group.status()[0];
+        assert_eq!(status.get_mode(), Mode::ManualOn);
+        assert_eq!(status.get_mode_expires(), Some(now + Duration::minutes(10)));
+        assert_eq!(status.get_outputs(), &vec![Some(true)]);
+
+        // control logic does not reverse the forced output
+        let messages = group.poll(now + Duration::minutes(5));
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].get_content(), "Mode Changed: Auto -> Manual On");
+        assert_eq!(group.status()[0].get_outputs(), &vec![Some(true)]);
+
+        // automatic control resumes once the override expires
+        let messages = group.poll(now + Duration::minutes(10));
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].get_content(), "Mode Changed: Manual On -> Auto");
+        assert_eq!(group.status()[0].get_mode(), Mode::Auto);
+        assert_eq!(group.status()[0].get_outputs(), &vec![Some(false)]);
+    }
+
+    #[test]
+    fn test_mode_resume() {
+        let now = Utc.with_ymd_and_hms(2021, 1, 1, 4, 0, 0).unwrap();
+
+        let mut controller = TimedOutput::new_without_scheduled(
+            Output::default(),
+            NaiveTime::from_hms_opt(5, 0, 0).unwrap(),
+            Duration::hours(8),
+        ).schedule_first(now);
+        controller.set_name(String::from("light"));
+
+        let mut group = ControllerGroup::new()
+            .add_controller(controller);
+
+        // operator turns the light on outside of its window
+        let command = Command::SetMode { mode: Mode::ManualOn, expires_in: None };
+        group.execute("light", command, now).unwrap();
+        assert_eq!(group.status()[0].get_outputs(), &vec![Some(true)]);
+
+        // the light is turned off when handed back to automatic control
+        group.execute("light", Command::Release, now + Duration::minutes(1)).unwrap();
+        let messages = group.poll(now + Duration::minutes(1));
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[2].get_content(), "Deactivated");
+        assert_eq!(group.status()[0].get_outputs(), &vec![Some(false)]);
+
+        // the schedule continues as usual
+        let messages = group.poll(now + Duration::hours(1));
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].get_content(), "Activated");
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let now = Utc.with_ymd_and_hms(2021, 1, 1, 4, 0, 0).unwrap();
+
+        let mut heater = Threshold::new_without_scheduled(
+            70.0,
+            Input::new(|| "69.0".to_string()),
+            Output::default().set_safe_state(false),
+            Duration::minutes(1),
+        ).set_inverted().schedule_next(now);
+        heater.set_name(String::from("heater"));
+
+        // no safe state is declared for the light
+        let light = TimedOutput::new_without_scheduled(
+            Output::default(),
+            NaiveTime::from_hms_opt(5, 0, 0).unwrap(),
+            Duration::hours(8),
+        ).schedule_first(now);
+
+        let mut group = ControllerGroup::new()
+            .add_controller(heater)
+            .add_controller(light);
+
+        group.poll(now + Duration::minutes(1));
+        assert_eq!(group.status()[0].get_outputs(), &vec![Some(true)]);
+
+        let messages = group.shutdown(now + Duration::minutes(2));
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].get_controller_name(), "heater");
+        assert_eq!(messages[0].get_content(), "Safe State");
+        assert_eq!(group.status()[0].get_outputs(), &vec![Some(false)]);
+        assert_eq!(group.status()[1].get_outputs(), &vec![None]);
+    }
+}
@@ -0,0 +1,656 @@
+//! On-node history of readings and actuations
+//!
+//! A [`Historian`] records every [`Message`] returned by a [`ControllerGroup`](crate::ControllerGroup)
+//! to disk so that history is available for troubleshooting, or for controllers that need trends,
+//! even when there is no message broker available.
+//!
+//! Records are stored in one segment file per day. Each record is a single tab-separated line
+//! containing the timestamp (in milliseconds), controller name, message content and read value.
+//! Segments older than the retention period are deleted, and segments older than the downsampling
+//! threshold are compacted so that numeric readings are reduced to one record per bucket, which
+//! keeps their minimum, maximum and mean value. Records that arrive for a day which has already
+//! been compacted are merged into it at the next maintenance.
+//!
+//! # Example
+//! ```
+//! use chrono::{Duration, Utc};
+//! use equilibrium::historian::Historian;
+//! use equilibrium::types::Message;
+//!
+//! let dir = std::env::temp_dir().join("equilibrium-doc-historian");
+//! let mut historian = Historian::open(&dir).unwrap()
+//!     .set_retention(Duration::days(30));
+//!
+//! let now = Utc::now();
+//! historian.record(&[
+//!     Message::new("heater", "Below Threshold", now, "68.5".to_string()),
+//! ]).unwrap();
+//!
+//! let records = historian.query("heater", now - Duration::hours(1), now + Duration::hours(1)).unwrap();
+//! assert_eq!(records.len(), 1);
+//! # std::fs::remove_dir_all(dir).unwrap();
+//! ```
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use crate::types::Message;
+
+const SEGMENT_EXTENSION: &str = "tsv";
+const DOWNSAMPLED_SUFFIX: &str = ".downsampled";
+
+/// A single entry stored by the [`Historian`]
+#[derive(Debug, PartialEq, Clone)]
+pub struct Record {
+    timestamp: DateTime<Utc>,
+    name: String,
+    content: String,
+    value: Option<String>,
+    aggregate: Option<Aggregate>,
+}
+
+impl Record {
+    pub fn get_timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    pub fn get_controller_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_content(&self) -> &str {
+        &self.content
+    }
+
+    pub fn get_value(&self) -> Option<&str> {
+        self.value.as_deref()
+    }
+
+    /// Returns the read value as a number, if it is numeric
+    ///
+    /// Failed reads are recorded as "NaN", and are not treated as numeric so that they do not
+    /// poison summaries. The value of a downsampled record is the mean of the readings that it
+    /// replaces.
+    pub fn get_numeric_value(&self) -> Option<f64> {
+        self.value.as_ref()
+            .and_then(|v| v.trim().parse::<f64>().ok())
+            .filter(|v| v.is_finite())
+    }
+
+    /// Returns the summary of the readings that a downsampled record replaces
+    pub fn get_aggregate(&self) -> Option<&Aggregate> {
+        self.aggregate.as_ref()
+    }
+
+    /// Returns the readings of the record as an [`Aggregate`] starting at `start`
+    fn summarize(&self, start: DateTime<Utc>) -> Option<Aggregate> {
+        match &self.aggregate {
+            Some(aggregate) => Some(Aggregate { start, ..aggregate.clone() }),
+            None => self.get_numeric_value()
+                .map(|value| Aggregate { start, min: value, max: value, mean: value, count: 1 }),
+        }
+    }
+
+    fn encode(&self) -> String {
+        let aggregate = match &self.aggregate {
+            Some(aggregate) => format!("\t{},{},{}", aggregate.min, aggregate.max, aggregate.count),
+            None => String::new(),
+        };
+        format!(
+            "{}\t{}\t{}\t{}{}\n",
+            self.timestamp.timestamp_millis(),
+            escape(&self.name),
+            escape(&self.content),
+            self.value.as_deref().map(escape).unwrap_or_default(),
+            aggregate,
+        )
+    }
+
+    fn decode(line: &str) -> Option<Self> {
+        let mut fields = line.split('\t');
+        let timestamp = Utc.timestamp_millis_opt(fields.next()?.parse().ok()?).single()?;
+        let name = unescape(fields.next()?);
+        let content = unescape(fields.next()?);
+        let value = fields.next()?;
+        let value = if value.is_empty() { None } else { Some(unescape(value)) };
+
+        // downsampled records carry the minimum, maximum and count of their readings
+        let aggregate = match (fields.next(), value.as_ref().and_then(|v| v.parse().ok())) {
+            (Some(summary), Some(mean)) => {
+                let mut summary = summary.split(',');
+                let min = summary.next()?.parse().ok()?;
+                let max = summary.next()?.parse().ok()?;
+                let count = summary.next()?.parse().ok()?;
+                Some(Aggregate { start: timestamp, min, max, mean, count })
+            }
+            _ => None,
+        };
+
+        Some(Self { timestamp, name, content, value, aggregate })
+    }
+}
+
+impl From<&Message> for Record {
+    fn from(message: &Message) -> Self {
+        Self {
+            timestamp: message.get_timestamp(),
+            name: message.get_controller_name(),
+            content: message.get_content(),
+            value: message.get_read_state(),
+            aggregate: None,
+        }
+    }
+}
+
+/// Summary of numeric readings within a time bucket
+#[derive(Debug, PartialEq, Clone)]
+pub struct Aggregate {
+    start: DateTime<Utc>,
+    min: f64,
+    max: f64,
+    mean: f64,
+    count: usize,
+}
+
+impl Aggregate {
+    /// Combine the readings of another aggregate into this one
+    fn merge(&mut self, other: &Aggregate) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.mean += (other.mean - self.mean) * other.count as f64 / (self.count + other.count) as f64;
+        self.count += other.count;
+    }
+
+    pub fn get_start(&self) -> DateTime<Utc> {
+        self.start
+    }
+
+    pub fn get_min(&self) -> f64 {
+        self.min
+    }
+
+    pub fn get_max(&self) -> f64 {
+        self.max
+    }
+
+    pub fn get_mean(&self) -> f64 {
+        self.mean
+    }
+
+    pub fn get_count(&self) -> usize {
+        self.count
+    }
+}
+
+/// Time-series storage for [`Message`]s
+///
+/// See the [module documentation](self) for details about the storage format.
+#[derive(Debug)]
+pub struct Historian {
+    directory: PathBuf,
+    retention: Option<Duration>,
+    downsample_after: Option<Duration>,
+    downsample_bucket: Duration,
+    last_maintenance: Option<NaiveDate>,
+}
+
+impl Historian {
+    /// Open a historian, creating the directory if it does not exist
+    ///
+    /// By default, records are kept forever and are never downsampled.
+    pub fn open<P>(directory: P) -> io::Result<Self>
+        where P: AsRef<Path>
+    {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory)?;
+        Ok(Self {
+            directory,
+            retention: None,
+            downsample_after: None,
+            downsample_bucket: Duration::minutes(5),
+            last_maintenance: None,
+        })
+    }
+
+    /// Builder method to delete records once they are older than `retention`
+    pub fn set_retention(mut self, retention: Duration) -> Self {
+        self.retention = Some(retention);
+        self
+    }
+
+    /// Builder method to downsample records once they are older than `after`
+    ///
+    /// Numeric readings are replaced by a single record for each `bucket`, whose value is their
+    /// mean and which keeps their minimum and maximum (see [`Record::get_aggregate`]). Records
+    /// without a numeric value (such as actuations) are always kept.
+    pub fn set_downsampling(mut self, after: Duration, bucket: Duration) -> Self {
+        self.downsample_after = Some(after);
+        self.downsample_bucket = bucket;
+        self
+    }
+
+    fn segment_path(&self, date: NaiveDate, downsampled: bool) -> PathBuf {
+        let suffix = if downsampled { DOWNSAMPLED_SUFFIX } else { "" };
+        self.directory.join(format!("{}{}.{}", date.format("%Y-%m-%d"), suffix, SEGMENT_EXTENSION))
+    }
+
+    /// Returns every segment on disk along with its date and whether it has been downsampled
+    fn segments(&self) -> io::Result<Vec<(NaiveDate, bool, PathBuf)>> {
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(SEGMENT_EXTENSION) {
+                continue;
+            }
+            let stem = match path.file_stem().and_then(|s| s.to_str()) {
+                Some(stem) => stem,
+                None => continue,
+            };
+            let (date, downsampled) = match stem.strip_suffix(DOWNSAMPLED_SUFFIX) {
+                Some(date) => (date, true),
+                None => (stem, false),
+            };
+            if let Ok(date) = NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+                segments.push((date, downsampled, path));
+            }
+        }
+        segments.sort();
+        Ok(segments)
+    }
+
+    fn read_segment(path: &Path) -> io::Result<Vec<Record>> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut records = Vec::new();
+        for line in reader.lines() {
+            if let Some(record) = Record::decode(&line?) {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+
+    /// Append messages to storage
+    ///
+    /// Retention and downsampling are applied the first time that a message is recorded on a
+    /// new day.
+    pub fn record(&mut self, messages: &[Message]) -> io::Result<()> {
+        let mut latest = None;
+        for message in messages {
+            let record = Record::from(message);
+            let path = self.segment_path(record.timestamp.date_naive(), false);
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?
+                .write_all(record.encode().as_bytes())?;
+            latest = latest.max(Some(record.timestamp));
+        }
+
+        if let Some(latest) = latest {
+            if self.last_maintenance != Some(latest.date_naive()) {
+                self.maintain(latest)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply retention and downsampling relative to `now`
+    pub fn maintain(&mut self, now: DateTime<Utc>) -> io::Result<()> {
+        for (date, downsampled, path) in self.segments()? {
+            // the end of the day covered by the segment
+            let end = Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap()) + Duration::days(1);
+
+            if let Some(retention) = self.retention {
+                if end <= now - retention {
+                    fs::remove_file(path)?;
+                    continue;
+                }
+            }
+
+            if let Some(after) = self.downsample_after {
+                if !downsampled && end <= now - after {
+                    self.downsample_segment(date, &path)?;
+                }
+            }
+        }
+        self.last_maintenance = Some(now.date_naive());
+        Ok(())
+    }
+
+    /// Compact a segment, merging it into the downsampled segment of the same day if one exists
+    fn downsample_segment(&self, date: NaiveDate, path: &Path) -> io::Result<()> {
+        let bucket_millis = self.downsample_bucket.num_milliseconds().max(1);
+        let downsampled = self.segment_path(date, true);
+        let mut records = match downsampled.exists() {
+            true => Self::read_segment(&downsampled)?,
+            false => Vec::new(),
+        };
+        records.extend(Self::read_segment(path)?);
+
+        let mut kept = Vec::new();
+        let mut buckets: BTreeMap<(String, i64), Aggregate> = BTreeMap::new();
+        for record in records {
+            let millis = record.timestamp.timestamp_millis();
+            let start = millis - millis.rem_euclid(bucket_millis);
+            match record.summarize(Utc.timestamp_millis_opt(start).unwrap()) {
+                Some(aggregate) => match buckets.get_mut(&(record.name.clone(), start)) {
+                    Some(bucket) => bucket.merge(&aggregate),
+                    None => {
+                        buckets.insert((record.name, start), aggregate);
+                    }
+                },
+                None => kept.push(record),
+            }
+        }
+
+        for ((name, _), aggregate) in buckets {
+            kept.push(Record {
+                timestamp: aggregate.start,
+                name,
+                content: String::from("Downsampled"),
+                value: Some(aggregate.mean.to_string()),
+                aggregate: Some(aggregate),
+            });
+        }
+        kept.sort_by_key(|r| r.timestamp);
+
+        let mut contents = String::new();
+        for record in kept.iter() {
+            contents.push_str(&record.encode());
+        }
+        fs::write(downsampled, contents)?;
+        fs::remove_file(path)
+    }
+
+    /// Returns all records for a controller within `[from, to)`, ordered by time
+    pub fn query(&self, name: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> io::Result<Vec<Record>> {
+        self.query_all(from, to)
+            .map(|records| records.into_iter().filter(|r| r.name == name).collect())
+    }
+
+    /// Returns all records for all controllers within `[from, to)`, ordered by time
+    pub fn query_all(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> io::Result<Vec<Record>> {
+        let mut records = Vec::new();
+        for (date, _, path) in self.segments()? {
+            if date < from.date_naive() || date > to.date_naive() {
+                continue;
+            }
+            records.extend(
+                Self::read_segment(&path)?
+                    .into_iter()
+                    .filter(|r| r.timestamp >= from && r.timestamp < to)
+            );
+        }
+        records.sort_by_key(|r| r.timestamp);
+        Ok(records)
+    }
+
+    /// Summarize the numeric readings of a controller within `[from, to)`
+    ///
+    /// Buckets are aligned to `from`. Buckets without any numeric readings are omitted. Downsampled
+    /// records contribute the minimum, maximum and count of the readings that they replace.
+    pub fn aggregate(
+        &self,
+        name: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        bucket: Duration,
+    ) -> io::Result<Vec<Aggregate>> {
+        let bucket_millis = bucket.num_milliseconds().max(1);
+
+        let mut aggregates: Vec<Aggregate> = Vec::new();
+        for record in self.query(name, from, to)? {
+            let offset = (record.timestamp - from).num_milliseconds() / bucket_millis;
+            let start = from + Duration::milliseconds(offset * bucket_millis);
+            let summary = match record.summarize(start) {
+                Some(summary) => summary,
+                None => continue,
+            };
+
+            match aggregates.last_mut() {
+                Some(aggregate) if aggregate.start == start => aggregate.merge(&summary),
+                _ => aggregates.push(summary),
+            }
+        }
+        Ok(aggregates)
+    }
+
+    /// Write records within `[from, to)` as CSV
+    ///
+    /// If `name` is `None`, records for all controllers are exported.
+    pub fn export_csv<W>(
+        &self,
+        name: Option<&str>,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        mut writer: W,
+    ) -> io::Result<()>
+        where W: Write
+    {
+        let records = match name {
+            Some(name) => self.query(name, from, to)?,
+            None => self.query_all(from, to)?,
+        };
+
+        writeln!(writer, "timestamp,name,content,value")?;
+        for record in records {
+            writeln!(
+                writer,
+                "{},{},{},{}",
+                record.timestamp.to_rfc3339(),
+                csv_field(&record.name),
+                csv_field(&record.content),
+                record.value.as_deref().map(csv_field).unwrap_or_default(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn escape(field: &str) -> String {
+    field.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
+
+fn unescape(field: &str) -> String {
+    let mut result = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('t') => result.push('\t'),
+                Some('n') => result.push('\n'),
+                Some(other) => result.push(other),
+                None => {}
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::TempDir;
+    use super::*;
+
+    #[test]
+    fn test_record_and_query() {
+        let dir = TempDir::new("historian-query");
+        let mut historian = Historian::open(&dir).unwrap();
+
+        let time = Utc.with_ymd_and_hms(2023, 1, 1, 23, 59, 0).unwrap();
+        historian.record(&[
+            Message::new("heater", "Below Threshold", time, "68.5".to_string()),
+            Message::new("light", "Activated", time, None),
+            Message::new("heater", "Above\tThreshold", time + Duration::minutes(2), "71.0".to_string()),
+        ]).unwrap();
+
+        // records span two segments
+        let records = historian.query("heater", time, time + Duration::hours(1)).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].get_value(), Some("68.5"));
+        assert_eq!(records[1].get_content(), "Above\tThreshold");
+
+        let records = historian.query_all(time, time + Duration::minutes(1)).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].get_controller_name(), "light");
+        assert_eq!(records[1].get_value(), None);
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let dir = TempDir::new("historian-aggregate");
+        let mut historian = Historian::open(&dir).unwrap();
+
+        let time = Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap();
+        let messages: Vec<Message> = [1.0, 3.0, 5.0, 10.0]
+            .iter()
+            .enumerate()
+            .map(|(i, v)| Message::new(
+                "probe".to_string(),
+                "Read".to_string(),
+                time + Duration::minutes(i as i64 * 5),
+                v.to_string(),
+            ))
+            .collect();
+        historian.record(&messages).unwrap();
+
+        let aggregates = historian.aggregate("probe", time, time + Duration::hours(1), Duration::minutes(10)).unwrap();
+        assert_eq!(aggregates.len(), 2);
+
+        assert_eq!(aggregates[0].get_start(), time);
+        assert_eq!(aggregates[0].get_min(), 1.0);
+        assert_eq!(aggregates[0].get_max(), 3.0);
+        assert_eq!(aggregates[0].get_mean(), 2.0);
+        assert_eq!(aggregates[0].get_count(), 2);
+
+        assert_eq!(aggregates[1].get_start(), time + Duration::minutes(10));
+        assert_eq!(aggregates[1].get_mean(), 7.5);
+    }
+
+    #[test]
+    fn test_failed_reads() {
+        let dir = TempDir::new("historian-failed");
+        let mut historian = Historian::open(&dir).unwrap()
+            .set_downsampling(Duration::days(1), Duration::hours(1));
+
+        let time = Utc.with_ymd_and_hms(2023, 1, 10, 12, 0, 0).unwrap();
+        historian.record(&[
+            Message::new("probe", "Read", time, "1.0".to_string()),
+            Message::new("probe", "Read Failed: timed out", time + Duration::minutes(10), "NaN".to_string()),
+            Message::new("probe", "Read", time + Duration::minutes(20), "3.0".to_string()),
+        ]).unwrap();
+
+        // the failed read is left out of the bucket it falls in
+        let aggregates = historian.aggregate("probe", time, time + Duration::hours(1), Duration::hours(1)).unwrap();
+        assert_eq!(aggregates.len(), 1);
+        assert_eq!(aggregates[0].get_mean(), 2.0);
+        assert_eq!(aggregates[0].get_count(), 2);
+
+        // and is kept as it is when the bucket is downsampled
+        historian.maintain(time + Duration::days(2)).unwrap();
+        let records = historian.query("probe", time, time + Duration::hours(1)).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].get_content(), "Downsampled");
+        assert_eq!(records[0].get_numeric_value(), Some(2.0));
+        assert_eq!(records[1].get_content(), "Read Failed: timed out");
+        assert_eq!(records[1].get_numeric_value(), None);
+    }
+
+    #[test]
+    fn test_retention_and_downsampling() {
+        let dir = TempDir::new("historian-retention");
+        let mut historian = Historian::open(&dir).unwrap()
+            .set_retention(Duration::days(7))
+            .set_downsampling(Duration::days(1), Duration::hours(1));
+
+        let old = Utc.with_ymd_and_hms(2023, 1, 1, 12, 0, 0).unwrap();
+        let recent = Utc.with_ymd_and_hms(2023, 1, 10, 12, 0, 0).unwrap();
+        historian.record(&[
+            Message::new("probe", "Read", old, "1.0".to_string()),
+            Message::new("probe", "Read", recent, "1.0".to_string()),
+            Message::new("probe", "Read", recent + Duration::minutes(30), "2.0".to_string()),
+            Message::new("pump", "Activated", recent + Duration::minutes(45), None),
+        ]).unwrap();
+
+        let now = Utc.with_ymd_and_hms(2023, 1, 12, 0, 0, 0).unwrap();
+        historian.maintain(now).unwrap();
+
+        // the old segment has been removed by retention
+        let records = historian.query_all(old, old + Duration::days(1)).unwrap();
+        assert!(records.is_empty());
+
+        // readings are downsampled while actuations are kept
+        let records = historian.query_all(recent, recent + Duration::days(1)).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].get_content(), "Downsampled");
+        assert_eq!(records[0].get_numeric_value(), Some(1.5));
+        let aggregate = records[0].get_aggregate().unwrap();
+        assert_eq!((aggregate.get_min(), aggregate.get_max(), aggregate.get_count()), (1.0, 2.0, 2));
+        assert_eq!(records[1].get_content(), "Activated");
+    }
+
+    #[test]
+    fn test_downsampling_late_records() {
+        let dir = TempDir::new("historian-late");
+        let mut historian = Historian::open(&dir).unwrap()
+            .set_downsampling(Duration::days(1), Duration::hours(1));
+
+        let time = Utc.with_ymd_and_hms(2023, 1, 10, 12, 0, 0).unwrap();
+        historian.record(&[
+            Message::new("probe", "Read", time, "1.0".to_string()),
+            Message::new("probe", "Read", time + Duration::minutes(30), "2.0".to_string()),
+        ]).unwrap();
+        historian.maintain(time + Duration::days(2)).unwrap();
+
+        // a reading for the same day arrives after it has been downsampled
+        historian.record(&[
+            Message::new("probe", "Read", time + Duration::minutes(45), "6.0".to_string()),
+        ]).unwrap();
+        historian.maintain(time + Duration::days(3)).unwrap();
+
+        let records = historian.query_all(time, time + Duration::days(1)).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].get_numeric_value(), Some(3.0));
+        let aggregate = records[0].get_aggregate().unwrap();
+        assert_eq!((aggregate.get_min(), aggregate.get_max(), aggregate.get_count()), (1.0, 6.0, 3));
+
+        // aggregates over downsampled records keep the extremes of the original readings
+        let aggregates = historian.aggregate("probe", time, time + Duration::days(1), Duration::days(1)).unwrap();
+        assert_eq!(aggregates.len(), 1);
+        assert_eq!(aggregates[0].get_min(), 1.0);
+        assert_eq!(aggregates[0].get_max(), 6.0);
+        assert_eq!(aggregates[0].get_mean(), 3.0);
+        assert_eq!(aggregates[0].get_count(), 3);
+    }
+
+    #[test]
+    fn test_export_csv() {
+        let dir = TempDir::new("historian-csv");
+        let mut historian = Historian::open(&dir).unwrap();
+
+        let time = Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap();
+        historian.record(&[
+            Message::new("heater", "Below, Threshold", time, "68.5".to_string()),
+        ]).unwrap();
+
+        let mut buffer = Vec::new();
+        historian.export_csv(None, time, time + Duration::hours(1), &mut buffer).unwrap();
+
+        let csv = String::from_utf8(buffer).unwrap();
+        assert_eq!(
+            csv,
+            "timestamp,name,content,value\n2023-01-01T00:00:00+00:00,heater,\"Below, Threshold\",68.5\n",
+        );
+    }
+}
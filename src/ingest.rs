@@ -0,0 +1,495 @@
+//! Reference server for the messages posted by an [`Emitter`]
+//!
+//! An [`IngestServer`] accepts the batches that [`Emitter::emit`] posts, validates them against
+//! the [`Message`] schema, optionally appends them to a [`FileSink`], and keeps the latest message
+//! of every controller of every node. It is intended as a starting point for a collection
+//! endpoint, and as a stand-in for one in tests.
+//!
+//! Messages are grouped by the ID of their [`NodeIdentity`](crate::types::NodeIdentity), and
+//! messages without an identity are grouped under the node `default`. The latest messages are
+//! only kept in memory.
+//!
+//! # Endpoints
+//! Node and controller names are percent-encoded within the path, so that a controller named
+//! `grow light` is found at `/nodes/{node}/controllers/grow%20light`.
+//!
+//! * `POST /` or `POST /messages` - Accepts a JSON array of messages, of at most the maximum body
+//!   size
+//! * `GET /nodes` - Returns the ID of every node that has sent messages
+//! * `GET /nodes/{node}/controllers` - Returns the latest message of every controller of a node
+//! * `GET /nodes/{node}/controllers/{controller}` - Returns the latest message of a controller,
+//!   along with the latest message that carried a reading
+//! * `GET /nodes/{node}/controllers/{controller}/latest` - Returns the latest message that carried
+//!   a reading, or the latest message if there is none, as expected by a
+//!   [`RemoteInput`](crate::remote::RemoteInput)
+//!
+//! # Authentication
+//! With a bearer token, batches must carry it in the `Authorization` header. With a signing key,
+//! batches must be signed as described in [`Emitter`], and are rejected if their timestamp is
+//! further from the current time than the maximum skew. Both are only required for posting. The
+//! bearer token is checked before the body is read.
+//!
+//! # Example
+//! ```
+//! use chrono::{Duration, Utc};
+//! use equilibrium::Emitter;
+//! use equilibrium::ingest::IngestServer;
+//! use equilibrium::types::Message;
+//! use tokio_util::sync::CancellationToken;
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let server = IngestServer::new().set_signing_key("secret");
+//! let shutdown = CancellationToken::new();
+//! let addr = server.serve(([127, 0, 0, 1], 0).into(), shutdown.clone()).unwrap();
+//!
+//! let emitter = Emitter::new(format!("http://{}", addr)).set_signing_key("secret");
+//! emitter.emit(vec![
+//!     Message::new("heater", "Below Threshold", Utc::now(), "68.5".to_string()),
+//! ]).await.unwrap();
+//!
+//! let latest = server.get_latest("default", "heater").unwrap();
+//! assert_eq!(latest.get_read_state(), Some("68.5".to_string()));
+//! shutdown.cancel();
+//! # });
+//! ```
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Duration, Utc};
+use hyper::{Body, HeaderMap, Method, Request, Response, Server, StatusCode};
+use hyper::service::{make_service_fn, service_fn};
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+use crate::api::{error_response, json_response, read_body};
+use crate::Emitter;
+use crate::sinks::file::FileSink;
+use crate::types::Message;
+
+/// The node that messages without an identity are grouped under
+pub const DEFAULT_NODE: &str = "default";
+
+/// The latest messages of a single controller
+#[derive(Debug, Clone, Serialize)]
+pub struct Latest {
+    /// The latest message of any kind
+    pub message: Message,
+
+    /// The latest message that carried a reading
+    pub reading: Option<Message>,
+}
+
+impl Latest {
+    fn update(&mut self, message: Message) {
+        if message.get_read_state().is_some()
+            && self.reading.as_ref().is_none_or(|r| r.get_timestamp() <= message.get_timestamp())
+        {
+            self.reading = Some(message.clone());
+        }
+        if self.message.get_timestamp() <= message.get_timestamp() {
+            self.message = message;
+        }
+    }
+}
+
+/// Accepts batches of messages and keeps the latest state of every node
+///
+/// Clones share the same state, so a clone may be kept to inspect the messages that a running
+/// server has received.
+///
+/// See the [module documentation](self) for details.
+#[derive(Clone)]
+pub struct IngestServer {
+    nodes: Arc<Mutex<BTreeMap<String, BTreeMap<String, Latest>>>>,
+    log: Option<Arc<Mutex<FileSink>>>,
+    token: Option<String>,
+    signing_key: Option<Vec<u8>>,
+    max_skew: Duration,
+    max_body_size: usize,
+}
+
+impl IngestServer {
+    /// Create a server which accepts every batch, and does not keep a log
+    pub fn new() -> Self {
+        Self {
+            nodes: Arc::default(),
+            log: None,
+            token: None,
+            signing_key: None,
+            max_skew: Duration::minutes(5),
+            max_body_size: 1024 * 1024,
+        }
+    }
+
+    /// Builder method to append every accepted message to a log
+    pub fn set_log(mut self, log: FileSink) -> Self {
+        self.log = Some(Arc::new(Mutex::new(log)));
+        self
+    }
+
+    /// Builder method to require a bearer token
+    pub fn set_bearer_token<S>(mut self, token: S) -> Self
+        where S: Into<String>
+    {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Builder method to require batches to be signed with an HMAC-SHA256 key
+    pub fn set_signing_key<K>(mut self, key: K) -> Self
+        where K: Into<Vec<u8>>
+    {
+        self.signing_key = Some(key.into());
+        self
+    }
+
+    /// Builder method to set how far the timestamp of a signed batch may be from the current time
+    ///
+    /// Defaults to 5 minutes.
+    pub fn set_max_skew(mut self, skew: Duration) -> Self {
+        self.max_skew = skew;
+        self
+    }
+
+    /// Builder method to set the size in bytes of the largest batch that is accepted
+    ///
+    /// Larger batches are rejected with `413 Payload Too Large`. Defaults to 1 MiB.
+    pub fn set_max_body_size(mut self, size: usize) -> Self {
+        self.max_body_size = size;
+        self
+    }
+
+    /// Store a batch of messages and update the latest state
+    ///
+    /// Messages which are older than the latest message of their controller are logged, but do
+    /// not replace it.
+    pub fn ingest(&self, messages: Vec<Message>) -> io::Result<()> {
+        if let Some(log) = &self.log {
+            log.lock().unwrap().write(&messages)?;
+        }
+
+        let mut nodes = self.nodes.lock().unwrap();
+        for message in messages {
+            let node = message.get_node().map_or(DEFAULT_NODE, |node| node.get_id()).to_string();
+            let controllers = nodes.entry(node).or_default();
+            match controllers.get_mut(&message.get_controller_name()) {
+                Some(latest) => latest.update(message),
+                None => {
+                    let reading = message.get_read_state().map(|_| message.clone());
+                    controllers.insert(message.get_controller_name(), Latest { message, reading });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the ID of every node that has sent messages
+    pub fn get_nodes(&self) -> Vec<String> {
+        self.nodes.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Get the latest messages of every controller of a node
+    pub fn get_controllers(&self, node: &str) -> Option<BTreeMap<String, Latest>> {
+        self.nodes.lock().unwrap().get(node).cloned()
+    }
+
+    /// Get the latest message that carried a reading, or the latest message if there is none
+    pub fn get_latest(&self, node: &str, controller: &str) -> Option<Message> {
+        let nodes = self.nodes.lock().unwrap();
+        let latest = nodes.get(node)?.get(controller)?;
+        Some(latest.reading.as_ref().unwrap_or(&latest.message).clone())
+    }
+
+    /// Check the bearer token of a request
+    fn authorize(&self, headers: &HeaderMap) -> Result<(), String> {
+        if let Some(token) = &self.token {
+            let expected = format!("Bearer {}", token);
+            let given = headers.get("authorization").map(|value| value.as_bytes()).unwrap_or_default();
+            if !constant_time_eq(given, expected.as_bytes()) {
+                return Err(String::from("invalid bearer token"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the signature of a batch
+    fn verify(&self, headers: &HeaderMap, body: &[u8], time: DateTime<Utc>) -> Result<(), String> {
+        if let Some(key) = &self.signing_key {
+            let timestamp = headers.get(Emitter::TIMESTAMP_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<i64>().ok())
+                .ok_or_else(|| String::from("missing timestamp"))?;
+            if (time.timestamp() - timestamp).abs() > self.max_skew.num_seconds() {
+                return Err(String::from("timestamp is outside of the allowed skew"));
+            }
+            let given = headers.get(Emitter::SIGNATURE_HEADER)
+                .map(|value| value.as_bytes())
+                .unwrap_or_default();
+            if !constant_time_eq(given, Emitter::sign(key, timestamp, body).as_bytes()) {
+                return Err(String::from("invalid signature"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Bind the server and spawn it onto the tokio runtime
+    ///
+    /// Returns the address that the server is bound to.
+    /// The server stops once the shutdown token is cancelled.
+    pub fn serve(&self, addr: SocketAddr, shutdown: CancellationToken) -> Result<SocketAddr, hyper::Error> {
+        let server = self.clone();
+        let make_service = make_service_fn(move |_| {
+            let server = server.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| route(req, server.clone())))
+            }
+        });
+
+        let server = Server::try_bind(&addr)?
+            .serve(make_service);
+        let addr = server.local_addr();
+        let server = server.with_graceful_shutdown(async move { shutdown.cancelled().await });
+        tokio::spawn(async move {
+            if let Err(e) = server.await {
+                eprintln!("Ingest server error: {}", e);
+            }
+        });
+        Ok(addr)
+    }
+}
+
+impl Default for IngestServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+async fn route(req: Request<Body>, server: IngestServer) -> Result<Response<Body>, Infallible> {
+    let path = req.uri().path()
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| percent_decode_str(s).decode_utf8().map(String::from))
+        .collect::<Result<Vec<String>, _>>();
+    let path = match path {
+        Ok(path) => path,
+        Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, format!("invalid path: {}", e))),
+    };
+    let path: Vec<&str> = path.iter().map(String::as_str).collect();
+
+    let response = match (req.method(), path.as_slice()) {
+        (&Method::POST, [] | ["messages"]) => {
+            let headers = req.headers().clone();
+            if let Err(e) = server.authorize(&headers) {
+                return Ok(error_response(StatusCode::UNAUTHORIZED, e));
+            }
+            let body = match read_body(req.into_body(), server.max_body_size).await {
+                Ok(body) => body,
+                Err((status, e)) => return Ok(error_response(status, e)),
+            };
+            if let Err(e) = server.verify(&headers, &body, Utc::now()) {
+                return Ok(error_response(StatusCode::UNAUTHORIZED, e));
+            }
+            match serde_json::from_slice::<Vec<Message>>(&body) {
+                Ok(messages) => match server.ingest(messages) {
+                    Ok(()) => json_response(StatusCode::OK, &serde_json::json!({ "result": "ok" })),
+                    Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+                },
+                Err(e) => error_response(StatusCode::BAD_REQUEST, e.to_string()),
+            }
+        }
+        (&Method::GET, ["nodes"]) => json_response(StatusCode::OK, &server.get_nodes()),
+        (&Method::GET, ["nodes", node, "controllers"]) => match server.get_controllers(node) {
+            Some(controllers) => {
+                let messages: BTreeMap<_, _> = controllers.into_iter()
+                    .map(|(name, latest)| (name, latest.message))
+                    .collect();
+                json_response(StatusCode::OK, &messages)
+            }
+            None => error_response(StatusCode::NOT_FOUND, format!("unknown node: {}", node)),
+        },
+        (&Method::GET, ["nodes", node, "controllers", controller]) => {
+            match server.get_controllers(node).and_then(|mut controllers| controllers.remove(*controller)) {
+                Some(latest) => json_response(StatusCode::OK, &latest),
+                None => error_response(StatusCode::NOT_FOUND, format!("unknown controller: {}/{}", node, controller)),
+            }
+        }
+        (&Method::GET, ["nodes", node, "controllers", controller, "latest"]) => {
+            match server.get_latest(node, controller) {
+                Some(message) => json_response(StatusCode::OK, &message),
+                None => error_response(StatusCode::NOT_FOUND, format!("unknown controller: {}/{}", node, controller)),
+            }
+        }
+        _ => error_response(StatusCode::NOT_FOUND, String::from("not found")),
+    };
+    Ok(response)
+}
+
+/// Compare two byte strings in time that only depends on their lengths
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use crate::remote::RemoteInput;
+    use crate::test_util::TempDir;
+    use crate::types::NodeIdentity;
+    use super::*;
+
+    fn serve(server: &IngestServer) -> String {
+        let addr = server.serve(SocketAddr::from(([127, 0, 0, 1], 0)), CancellationToken::new()).unwrap();
+        format!("http://{}", addr)
+    }
+
+    #[test]
+    fn test_ingest() {
+        let server = IngestServer::new();
+        let node = NodeIdentity::new("greenhouse-1");
+        let now = Utc::now();
+        server.ingest(vec![
+            Message::new("heater", "Below Threshold", now, String::from("68.5")).set_node(node.clone()),
+            Message::new("heater", "Mode Changed: Auto -> Disabled", now + Duration::seconds(1), None)
+                .set_node(node.clone()),
+            // an older message does not replace the latest one
+            Message::new("heater", "Above Threshold", now - Duration::seconds(1), String::from("71"))
+                .set_node(node),
+            Message::new("pump", "Activated", now, None),
+        ]).unwrap();
+
+        assert_eq!(server.get_nodes(), vec!["default", "greenhouse-1"]);
+        let controllers = server.get_controllers("greenhouse-1").unwrap();
+        assert_eq!(controllers["heater"].message.get_content(), "Mode Changed: Auto -> Disabled");
+        assert_eq!(server.get_latest("greenhouse-1", "heater").unwrap().get_read_state(), Some(String::from("68.5")));
+        assert_eq!(server.get_latest("default", "pump").unwrap().get_content(), "Activated");
+        assert!(server.get_latest("default", "heater").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_post() {
+        let server = IngestServer::new();
+        let url = serve(&server);
+        let client = reqwest::Client::new();
+
+        let emitter = Emitter::new(format!("{}/messages", url));
+        emitter.emit(vec![Message::new("heater", "Below Threshold", Utc::now(), String::from("68.5"))])
+            .await.unwrap();
+        assert!(server.get_latest(DEFAULT_NODE, "heater").is_some());
+
+        // batches which are not valid messages are rejected
+        let response = client.post(&url).body(r#"[{"name": "heater"}]"#).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        let response = client.get(format!("{}/nodes", url)).send().await.unwrap();
+        assert_eq!(response.json::<Vec<String>>().await.unwrap(), vec![DEFAULT_NODE]);
+        let response = client.get(format!("{}/nodes/default/controllers/heater", url)).send().await.unwrap();
+        let latest: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(latest["reading"]["content"], "Below Threshold");
+        let response = client.get(format!("{}/nodes/other/controllers", url)).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_authentication() {
+        let server = IngestServer::new()
+            .set_bearer_token("token")
+            .set_signing_key("key");
+        let url = serve(&server);
+        let messages = vec![Message::new("heater", "Below Threshold", Utc::now(), None)];
+
+        let unsigned = Emitter::new(url.as_str()).set_bearer_token("token");
+        assert!(unsigned.emit(messages.clone()).await.is_err());
+        let wrong_key = Emitter::new(url.as_str()).set_bearer_token("token").set_signing_key("other");
+        assert!(wrong_key.emit(messages.clone()).await.is_err());
+        let no_token = Emitter::new(url.as_str()).set_signing_key("key");
+        assert!(no_token.emit(messages.clone()).await.is_err());
+        assert!(server.get_nodes().is_empty());
+
+        let emitter = Emitter::new(url.as_str()).set_bearer_token("token").set_signing_key("key");
+        emitter.emit(messages).await.unwrap();
+        assert_eq!(server.get_nodes(), vec![DEFAULT_NODE]);
+    }
+
+    #[test]
+    fn test_replay() {
+        let server = IngestServer::new().set_signing_key("key");
+        let body = b"[]";
+        let sent = Utc::now() - Duration::minutes(10);
+        let mut headers = HeaderMap::new();
+        headers.insert(Emitter::TIMESTAMP_HEADER, sent.timestamp().into());
+        headers.insert(Emitter::SIGNATURE_HEADER, Emitter::sign(b"key", sent.timestamp(), body).parse().unwrap());
+
+        assert!(server.verify(&headers, body, sent + Duration::seconds(30)).is_ok());
+        assert!(server.verify(&headers, body, Utc::now()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_log_and_remote_input() {
+        let dir = TempDir::new("ingest");
+        let server = IngestServer::new().set_log(FileSink::open(&dir).unwrap());
+        let url = serve(&server);
+
+        let node = NodeIdentity::new("greenhouse-1");
+        Emitter::new(url.as_str()).emit(vec![
+            Message::new("heater", "Below Threshold", Utc::now(), String::from("68.5")).set_node(node),
+        ]).await.unwrap();
+        assert_eq!(fs::read_to_string(dir.join("messages.jsonl")).unwrap().lines().count(), 1);
+
+        let remote = RemoteInput::new(url.as_str(), "greenhouse-1", "heater");
+        remote.fetch(&reqwest::Client::new()).await.unwrap();
+        assert_eq!(remote.read_at(Utc::now()).unwrap(), "68.5");
+    }
+
+    #[tokio::test]
+    async fn test_encoded_names() {
+        let server = IngestServer::new();
+        let url = serve(&server);
+
+        let node = NodeIdentity::new("north house/1");
+        Emitter::new(url.as_str()).emit(vec![
+            Message::new("grow light", "Activated", Utc::now(), String::from("1")).set_node(node),
+        ]).await.unwrap();
+
+        let response = reqwest::get(format!("{}/nodes/north%20house%2F1/controllers", url)).await.unwrap();
+        let controllers: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(controllers["grow light"]["content"], "Activated");
+
+        let remote = RemoteInput::new(url.as_str(), "north house/1", "grow light");
+        remote.fetch(&reqwest::Client::new()).await.unwrap();
+        assert_eq!(remote.read_at(Utc::now()).unwrap(), "1");
+    }
+
+    #[tokio::test]
+    async fn test_max_body_size() {
+        let server = IngestServer::new().set_bearer_token("token").set_max_body_size(64);
+        let url = serve(&server);
+        let client = reqwest::Client::new();
+        let body = serde_json::to_vec(&vec![Message::new("heater", "Below Threshold", Utc::now(), None); 2]).unwrap();
+
+        // the token is checked before the body is read
+        let response = client.post(&url).body(body.clone()).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+        let response = client.post(&url).bearer_auth("token").body(body).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+
+        // a body without a length is limited while it is read
+        let mut stream = tokio::net::TcpStream::connect(url.trim_start_matches("http://")).await.unwrap();
+        let chunk = format!("30\r\n{}\r\n", " ".repeat(48));
+        let request = format!(
+            "POST / HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer token\r\n\
+            Transfer-Encoding: chunked\r\n\r\n{}{}0\r\n\r\n",
+            chunk,
+            chunk,
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = [0; 12];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(&response, b"HTTP/1.1 413");
+        assert!(server.get_nodes().is_empty());
+    }
+
+}
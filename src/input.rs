@@ -52,12 +52,22 @@ where F: Fn() -> String {
     pub fn get_state(&self) -> &Option<String> {
         &self.state
     }
+
+    /// Restore a previously cached state without reading the input
+    pub fn restore(&mut self, state: Option<String>) {
+        self.state = state;
+    }
+
+    /// Consume the input and return the underlying callback
+    pub fn into_callback(self) -> F {
+        self.callback
+    }
 }
 
 impl Default for Input<fn() -> String> {
     /// The default callback function returns an empty `String`
     fn default() -> Self {
-        Self::new(|| String::new())
+        Self::new(String::new)
     }
 }
 
@@ -59,15 +59,36 @@ pub mod types;
 mod scheduler;
 mod input;
 mod output;
+mod async_input;
+mod async_output;
 pub mod controllers;
 mod group;
 mod emitter;
+mod subscriber;
+pub mod remote;
+pub mod node;
+pub mod failover;
 mod runtime;
+pub mod store;
+pub mod historian;
+pub mod api;
+pub mod metrics;
+pub mod ingest;
+pub mod worker;
+pub mod sensors;
+pub mod arbiter;
+pub mod drivers;
+pub mod sinks;
+#[cfg(test)]
+mod test_util;
 
 // re-export types
 pub use input::Input;
 pub use output::Output;
+pub use async_input::AsyncInput;
+pub use async_output::{AsyncOutput, SafeStateAction};
 
 pub use group::ControllerGroup;
 pub use emitter::Emitter;
+pub use subscriber::Subscriber;
 pub use runtime::Runtime;
\ No newline at end of file
@@ -0,0 +1,281 @@
+//! Prometheus metrics for monitoring a running [`Runtime`](crate::Runtime)
+//!
+//! The endpoint is served by [`Runtime::serve_metrics`](crate::Runtime::serve_metrics) at
+//! `GET /metrics`, in the Prometheus text exposition format. Every metric is labelled with the ID
+//! of the node, and, where applicable, with the name of the controller.
+//!
+//! # Metrics
+//! * `equilibrium_input_reading` - Latest reading of the input of each controller
+//! * `equilibrium_output_state` - Cached state of each output, labelled by its index
+//! * `equilibrium_poll_duration_seconds` - How long the last poll of each controller took
+//! * `equilibrium_scheduler_queue_depth` - Number of events scheduled by each controller
+//! * `equilibrium_alarms_total` - Number of alarm messages of each controller (see [`ALARMS`])
+//! * `equilibrium_sink_failures_total` - Number of batches that were dropped or could not be
+//!   written, labelled by the name of the [`Route`](crate::sinks::Route)
+//! * `equilibrium_sink_retries_total` - Number of times a batch was retried by each route
+//!
+//! Readings of controllers outside of the [`ControllerGroup`](crate::ControllerGroup), such as
+//! isolated and async controllers, are taken from their messages. Outputs, poll durations and
+//! queue depths are only available for the controllers of the group.
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::service::{make_service_fn, service_fn};
+use tokio_util::sync::CancellationToken;
+use crate::types::{ControllerStatus, Message};
+
+/// Prefixes of the message contents that are counted as alarms
+pub const ALARMS: [&str; 5] = [
+    "Read Failed",
+    "Write Failed",
+    "Sensor Disagreement",
+    "Controller Restarted",
+    "Controller Quarantined",
+];
+
+#[derive(Debug, Default)]
+struct Inner {
+    node: String,
+    readings: BTreeMap<String, f64>,
+    statuses: Vec<ControllerStatus>,
+    alarms: BTreeMap<String, u64>,
+    sinks: BTreeMap<String, (u64, u64)>,
+}
+
+/// The latest values of every metric
+///
+/// Clones share the same values, so the runtime updates the values that the server renders.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the ID of the node that every metric is labelled with
+    pub fn set_node<S>(&self, node: S)
+        where S: Into<String>
+    {
+        self.inner.lock().unwrap().node = node.into();
+    }
+
+    /// Replace the status of the controllers in the group
+    pub fn update_status(&self, statuses: Vec<ControllerStatus>) {
+        let mut inner = self.inner.lock().unwrap();
+        for status in statuses.iter() {
+            if let Some(value) = status.get_last_reading().as_ref().and_then(|r| r.parse().ok()) {
+                inner.readings.insert(status.get_name().to_string(), value);
+            }
+        }
+        inner.statuses = statuses;
+    }
+
+    /// Count alarms and record the readings carried by messages
+    pub fn record(&self, messages: &[Message]) {
+        let mut inner = self.inner.lock().unwrap();
+        for message in messages {
+            let name = message.get_controller_name();
+            if let Some(value) = message.get_read_state().and_then(|r| r.parse().ok()) {
+                inner.readings.insert(name.clone(), value);
+            }
+            let content = message.get_content();
+            if ALARMS.iter().any(|alarm| content.starts_with(alarm)) {
+                *inner.alarms.entry(name).or_default() += 1;
+            }
+        }
+    }
+
+    /// Set the totals reported by the named route
+    pub fn update_sink(&self, name: &str, failures: u64, retries: u64) {
+        self.inner.lock().unwrap().sinks.insert(name.to_string(), (failures, retries));
+    }
+
+    /// Render every metric in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let inner = self.inner.lock().unwrap();
+        let node = escape(&inner.node);
+        let mut out = String::new();
+
+        header(&mut out, "equilibrium_input_reading", "gauge", "Latest reading of the input of each controller");
+        for (controller, value) in inner.readings.iter() {
+            sample(&mut out, "equilibrium_input_reading", &node, Some(controller), None, *value);
+        }
+
+        header(&mut out, "equilibrium_output_state", "gauge", "Cached state of each output");
+        for status in inner.statuses.iter() {
+            for (index, state) in status.get_outputs().iter().enumerate() {
+                if let Some(state) = state {
+                    let value = if *state { 1.0 } else { 0.0 };
+                    sample(&mut out, "equilibrium_output_state", &node, Some(status.get_name()), Some(index), value);
+                }
+            }
+        }
+
+        header(&mut out, "equilibrium_poll_duration_seconds", "gauge", "How long the last poll of each controller took");
+        for status in inner.statuses.iter() {
+            if let Some(duration) = status.get_poll_duration() {
+                sample(&mut out, "equilibrium_poll_duration_seconds", &node, Some(status.get_name()), None, duration);
+            }
+        }
+
+        header(&mut out, "equilibrium_scheduler_queue_depth", "gauge", "Number of events scheduled by each controller");
+        for status in inner.statuses.iter() {
+            let depth = status.get_pending_events() as f64;
+            sample(&mut out, "equilibrium_scheduler_queue_depth", &node, Some(status.get_name()), None, depth);
+        }
+
+        header(&mut out, "equilibrium_alarms_total", "counter", "Number of alarm messages of each controller");
+        for (controller, count) in inner.alarms.iter() {
+            sample(&mut out, "equilibrium_alarms_total", &node, Some(controller), None, *count as f64);
+        }
+
+        header(&mut out, "equilibrium_sink_failures_total", "counter", "Number of batches that were dropped or could not be written");
+        for (sink, (failures, _)) in inner.sinks.iter() {
+            let _ = writeln!(out, "equilibrium_sink_failures_total{{node=\"{}\",sink=\"{}\"}} {}", node, escape(sink), failures);
+        }
+
+        header(&mut out, "equilibrium_sink_retries_total", "counter", "Number of times a batch was retried");
+        for (sink, (_, retries)) in inner.sinks.iter() {
+            let _ = writeln!(out, "equilibrium_sink_retries_total{{node=\"{}\",sink=\"{}\"}} {}", node, escape(sink), retries);
+        }
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Write a single sample, where `node` has already been escaped
+fn sample(out: &mut String, name: &str, node: &str, controller: Option<&str>, output: Option<usize>, value: f64) {
+    let _ = write!(out, "{}{{node=\"{}\"", name, node);
+    if let Some(controller) = controller {
+        let _ = write!(out, ",controller=\"{}\"", escape(controller));
+    }
+    if let Some(output) = output {
+        let _ = write!(out, ",output=\"{}\"", output);
+    }
+    let value = match value {
+        v if v.is_nan() => String::from("NaN"),
+        v if v == f64::INFINITY => String::from("+Inf"),
+        v if v == f64::NEG_INFINITY => String::from("-Inf"),
+        v => v.to_string(),
+    };
+    let _ = writeln!(out, "}} {}", value);
+}
+
+/// Escape a label value
+fn escape(value: &str) -> String {
+    value.replace('\\', r"\\").replace('"', "\\\"").replace('\n', r"\n")
+}
+
+/// Bind the metrics server and spawn it onto the tokio runtime
+///
+/// Returns the address that the server is bound to.
+/// The server stops once the shutdown token is cancelled.
+pub(crate) fn spawn(
+    addr: SocketAddr,
+    metrics: Metrics,
+    shutdown: CancellationToken,
+) -> Result<SocketAddr, hyper::Error> {
+    let make_service = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| route(req, metrics.clone())))
+        }
+    });
+
+    let server = Server::try_bind(&addr)?
+        .serve(make_service);
+    let addr = server.local_addr();
+    let server = server.with_graceful_shutdown(async move { shutdown.cancelled().await });
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            eprintln!("Metrics server error: {}", e);
+        }
+    });
+    Ok(addr)
+}
+
+async fn route(req: Request<Body>, metrics: Metrics) -> Result<Response<Body>, Infallible> {
+    let response = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/metrics") => Response::builder()
+            .header("content-type", "text/plain; version=0.0.4")
+            .body(Body::from(metrics.render())),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+    };
+    Ok(response.unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+    use crate::controllers::{Controller, Threshold};
+    use crate::{ControllerGroup, Input, Output};
+    use super::*;
+
+    fn build_metrics() -> Metrics {
+        let mut controller = Threshold::new(
+            70.0,
+            Input::new(|| "69.5".to_string()),
+            Output::default(),
+            Duration::minutes(5),
+        ).set_inverted();
+        controller.set_name(String::from("heater"));
+        let mut group = ControllerGroup::new().add_controller(controller);
+        group.poll(Utc::now() + Duration::minutes(5));
+
+        let metrics = Metrics::new();
+        metrics.set_node("greenhouse-1");
+        metrics.update_status(group.status());
+        metrics.record(&[
+            Message::new("probe", "Read Failed: timed out", Utc::now(), None),
+            Message::new("probe", "Read Failed: timed out", Utc::now(), None),
+            Message::new("pump \"2\"", "Above Threshold", Utc::now(), String::from("81")),
+        ]);
+        metrics.update_sink("emitter", 3, 7);
+        metrics
+    }
+
+    #[test]
+    fn test_render() {
+        let rendered = build_metrics().render();
+        let lines: Vec<&str> = rendered.lines().filter(|line| !line.starts_with('#')).collect();
+        assert!(lines.contains(&r#"equilibrium_input_reading{node="greenhouse-1",controller="heater"} 69.5"#));
+        assert!(lines.contains(&r#"equilibrium_input_reading{node="greenhouse-1",controller="pump \"2\""} 81"#));
+        assert!(lines.contains(&r#"equilibrium_output_state{node="greenhouse-1",controller="heater",output="0"} 1"#));
+        assert!(lines.contains(&r#"equilibrium_scheduler_queue_depth{node="greenhouse-1",controller="heater"} 1"#));
+        assert!(lines.contains(&r#"equilibrium_alarms_total{node="greenhouse-1",controller="probe"} 2"#));
+        assert!(lines.contains(&r#"equilibrium_sink_failures_total{node="greenhouse-1",sink="emitter"} 3"#));
+        assert!(lines.contains(&r#"equilibrium_sink_retries_total{node="greenhouse-1",sink="emitter"} 7"#));
+        assert!(lines.iter().any(|line| line.starts_with(
+            r#"equilibrium_poll_duration_seconds{node="greenhouse-1",controller="heater"} "#
+        )));
+        assert!(rendered.contains("# TYPE equilibrium_alarms_total counter\n"));
+    }
+
+    #[tokio::test]
+    async fn test_spawn() {
+        let token = CancellationToken::new();
+        let addr = spawn(SocketAddr::from(([127, 0, 0, 1], 0)), build_metrics(), token.clone()).unwrap();
+
+        let client = reqwest::Client::new();
+        let response = client.get(format!("http://{}/metrics", addr)).send().await.unwrap();
+        assert!(response.status().is_success());
+        assert!(response.text().await.unwrap().contains("equilibrium_alarms_total"));
+
+        let response = client.get(format!("http://{}/other", addr)).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+        token.cancel();
+    }
+}
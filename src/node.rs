@@ -0,0 +1,342 @@
+//! Heartbeats and discovery of nodes on the local network
+//!
+//! A [`Runtime`](crate::Runtime) with a heartbeat interval periodically emits a [`Heartbeat`],
+//! which carries the [`NodeIdentity`], uptime, number of controllers and [`Health`] of the node.
+//! It is emitted as a "Heartbeat" [`Message`] like any other, so a broker can tell when a node has
+//! gone silent.
+//!
+//! Nodes can also find each other without a broker using [`Discovery`]. Each heartbeat is
+//! broadcast as JSON over UDP, and the heartbeats received from other nodes are kept as a table
+//! of peers. By default, heartbeats are broadcast to `255.255.255.255` on the discovery port, so
+//! only nodes on the same subnet are found.
+//!
+//! # Example
+//! ```no_run
+//! use chrono::Duration;
+//! use equilibrium::{ControllerGroup, Runtime};
+//! use equilibrium::node::Discovery;
+//! use equilibrium::types::NodeIdentity;
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let discovery = Discovery::new(47800);
+//! let peers = discovery.clone();
+//! tokio::spawn(async move {
+//!     loop {
+//!         tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+//!         for peer in peers.get_peers() {
+//!             println!("{} was last seen at {}", peer.get_node().get_id(), peer.get_timestamp());
+//!         }
+//!     }
+//! });
+//!
+//! let mut runtime = Runtime::new(ControllerGroup::new(), Duration::seconds(1))
+//!     .set_identity(NodeIdentity::new("greenhouse-1").set_site("north-farm"))
+//!     .set_heartbeat(Duration::seconds(10))
+//!     .set_discovery(discovery);
+//! runtime.run().await;
+//! # });
+//! ```
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::{Arc, Mutex, OnceLock};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use crate::failover::Leadership;
+use crate::types::{Message, NodeIdentity};
+
+/// Overall health of a node
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Health {
+    /// Every controller is running and messages are being emitted
+    Healthy,
+
+    /// The node is running, but some of it is not working
+    Degraded { reasons: Vec<String> },
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Health::Healthy => write!(f, "Healthy"),
+            Health::Degraded { reasons } => write!(f, "Degraded ({})", reasons.join(", ")),
+        }
+    }
+}
+
+/// A periodic report that a node is alive
+///
+/// # Fields
+/// * `node` - The identity of the node
+/// * `timestamp` - When the heartbeat was sent
+/// * `uptime` - How long the runtime has been running, in seconds
+/// * `controllers` - How many controllers the node runs
+/// * `health` - Overall health of the node
+/// * `leadership` - Role of the node, if it is part of a [`Failover`](crate::failover::Failover)
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Heartbeat {
+    node: NodeIdentity,
+    timestamp: DateTime<Utc>,
+    uptime: i64,
+    controllers: usize,
+    health: Health,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    leadership: Option<Leadership>,
+}
+
+impl Heartbeat {
+    pub fn new(node: NodeIdentity, timestamp: DateTime<Utc>, uptime: Duration, controllers: usize, health: Health) -> Self {
+        Self {
+            node,
+            timestamp,
+            uptime: uptime.num_seconds(),
+            controllers,
+            health,
+            leadership: None,
+        }
+    }
+
+    /// Builder method to set the role of the node
+    pub fn set_leadership(mut self, leadership: Leadership) -> Self {
+        self.leadership = Some(leadership);
+        self
+    }
+
+    pub fn get_node(&self) -> &NodeIdentity {
+        &self.node
+    }
+
+    pub fn get_timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    pub fn get_uptime(&self) -> Duration {
+        Duration::seconds(self.uptime)
+    }
+
+    pub fn get_controllers(&self) -> usize {
+        self.controllers
+    }
+
+    pub fn get_health(&self) -> &Health {
+        &self.health
+    }
+
+    pub fn get_leadership(&self) -> Option<Leadership> {
+        self.leadership
+    }
+
+    /// Convert to a message from "runtime", for example
+    /// "Heartbeat: 3 controllers, up 3600s, Healthy"
+    pub fn to_message(&self) -> Message {
+        let content = format!(
+            "Heartbeat: {} controllers, up {}s, {}",
+            self.controllers,
+            self.uptime,
+            self.health,
+        );
+        Message::new(String::from("runtime"), content, self.timestamp, None)
+            .set_node(self.node.clone())
+    }
+}
+
+/// Announces heartbeats over UDP, and keeps track of the nodes that announce theirs
+///
+/// Clones share the same socket and table of peers, so a clone may be kept to inspect the peers
+/// after the original has been attached to a [`Runtime`](crate::Runtime).
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug, Clone)]
+pub struct Discovery {
+    bind_addr: SocketAddr,
+    targets: Vec<SocketAddr>,
+    socket: Arc<OnceLock<UdpSocket>>,
+    own_id: Arc<Mutex<Option<String>>>,
+    peers: Arc<Mutex<HashMap<String, Heartbeat>>>,
+}
+
+impl Discovery {
+    /// Create a discovery which listens on, and broadcasts to, the given port
+    pub fn new(port: u16) -> Self {
+        Self {
+            bind_addr: SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)),
+            targets: vec![SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::BROADCAST, port))],
+            socket: Arc::new(OnceLock::new()),
+            own_id: Arc::new(Mutex::new(None)),
+            peers: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Builder method to set the address that is listened on
+    pub fn set_bind_addr<A>(mut self, addr: A) -> Self
+        where A: Into<SocketAddr>
+    {
+        self.bind_addr = addr.into();
+        self
+    }
+
+    /// Builder method to send heartbeats to the given addresses instead of broadcasting them
+    pub fn set_targets(mut self, targets: Vec<SocketAddr>) -> Self {
+        self.targets = targets;
+        self
+    }
+
+    /// Bind the socket and spawn a task which records the heartbeats of other nodes
+    ///
+    /// Must be called from a tokio runtime. Returns an error if the socket is already bound.
+    pub async fn start(&self) -> io::Result<JoinHandle<()>> {
+        let socket = UdpSocket::bind(self.bind_addr).await?;
+        socket.set_broadcast(true)?;
+        self.socket.set(socket)
+            .map_err(|_| io::Error::new(io::ErrorKind::AddrInUse, "discovery has already started"))?;
+
+        let discovery = self.clone();
+        Ok(tokio::spawn(async move {
+            let socket = discovery.socket.get().unwrap();
+            let mut buffer = vec![0; 4096];
+            loop {
+                let (length, addr) = match socket.recv_from(&mut buffer).await {
+                    Ok(received) => received,
+                    Err(e) => {
+                        eprintln!("Failed to receive heartbeat: {}", e);
+                        continue;
+                    }
+                };
+                match serde_json::from_slice::<Heartbeat>(&buffer[..length]) {
+                    Ok(heartbeat) => discovery.record(heartbeat),
+                    Err(e) => eprintln!("Invalid heartbeat from {}: {}", addr, e),
+                }
+            }
+        }))
+    }
+
+    /// Returns the address that the socket is bound to, once started
+    pub fn get_local_addr(&self) -> Option<SocketAddr> {
+        self.socket.get().and_then(|socket| socket.local_addr().ok())
+    }
+
+    /// Send a heartbeat to every target
+    ///
+    /// Heartbeats with the same ID as the announced one are no longer recorded as peers.
+    pub async fn announce(&self, heartbeat: &Heartbeat) -> io::Result<()> {
+        *self.own_id.lock().unwrap() = Some(heartbeat.get_node().get_id().to_string());
+
+        let socket = self.socket.get()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "discovery has not started"))?;
+        let payload = serde_json::to_vec(heartbeat)?;
+        for target in &self.targets {
+            socket.send_to(&payload, target).await?;
+        }
+        Ok(())
+    }
+
+    /// Keep the heartbeat unless it is our own, or older than the one already recorded
+    fn record(&self, heartbeat: Heartbeat) {
+        let id = heartbeat.get_node().get_id().to_string();
+        if self.own_id.lock().unwrap().as_ref() == Some(&id) {
+            return;
+        }
+        let mut peers = self.peers.lock().unwrap();
+        if peers.get(&id).is_none_or(|last| last.get_timestamp() <= heartbeat.get_timestamp()) {
+            peers.insert(id, heartbeat);
+        }
+    }
+
+    /// Get the latest heartbeat of a peer
+    pub fn get_peer(&self, id: &str) -> Option<Heartbeat> {
+        self.peers.lock().unwrap().get(id).cloned()
+    }
+
+    /// Get the latest heartbeat of every peer, ordered by ID
+    ///
+    /// Peers are never removed, so the timestamp should be checked to find nodes that have gone
+    /// silent.
+    pub fn get_peers(&self) -> Vec<Heartbeat> {
+        let mut peers: Vec<_> = self.peers.lock().unwrap().values().cloned().collect();
+        peers.sort_by(|a, b| a.get_node().get_id().cmp(b.get_node().get_id()));
+        peers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heartbeat(id: &str, timestamp: DateTime<Utc>) -> Heartbeat {
+        Heartbeat::new(
+            NodeIdentity::new(id).set_hostname("pi"),
+            timestamp,
+            Duration::hours(1),
+            3,
+            Health::Healthy,
+        )
+    }
+
+    #[test]
+    fn test_heartbeat_message() {
+        let message = heartbeat("greenhouse-1", Utc::now()).to_message();
+        assert_eq!(message.get_controller_name(), "runtime");
+        assert_eq!(message.get_content(), "Heartbeat: 3 controllers, up 3600s, Healthy");
+        assert_eq!(message.get_node().unwrap().get_id(), "greenhouse-1");
+
+        let health = Health::Degraded { reasons: vec![String::from("1 controller quarantined")] };
+        assert_eq!(health.to_string(), "Degraded (1 controller quarantined)");
+    }
+
+    #[test]
+    fn test_serialize() {
+        let heartbeat = heartbeat("greenhouse-1", Utc::now());
+        let json = serde_json::to_value(&heartbeat).unwrap();
+        assert_eq!(json["node"]["id"], "greenhouse-1");
+        assert_eq!(json["health"]["status"], "healthy");
+        assert_eq!(serde_json::from_value::<Heartbeat>(json).unwrap(), heartbeat);
+    }
+
+    #[test]
+    fn test_record() {
+        let discovery = Discovery::new(0);
+        let now = Utc::now();
+        discovery.record(heartbeat("b", now));
+        discovery.record(heartbeat("a", now));
+        // older heartbeats are ignored
+        discovery.record(heartbeat("a", now - Duration::seconds(10)));
+
+        let peers = discovery.get_peers();
+        assert_eq!(peers.len(), 2);
+        assert_eq!(peers[0].get_node().get_id(), "a");
+        assert_eq!(peers[0].get_timestamp(), now);
+        assert!(discovery.get_peer("c").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_discovery() {
+        let localhost = SocketAddr::from(([127, 0, 0, 1], 0));
+        let first = Discovery::new(0).set_bind_addr(localhost);
+        let second = Discovery::new(0).set_bind_addr(localhost);
+        first.start().await.unwrap();
+        second.start().await.unwrap();
+        assert!(first.start().await.is_err());
+
+        let (first_addr, second_addr) = (first.get_local_addr().unwrap(), second.get_local_addr().unwrap());
+        let first = first.set_targets(vec![second_addr]);
+        let second = second.set_targets(vec![first_addr, second_addr]);
+        first.announce(&heartbeat("first", Utc::now())).await.unwrap();
+        // our own heartbeats are not peers
+        second.announce(&heartbeat("second", Utc::now())).await.unwrap();
+
+        for _ in 0..50 {
+            if first.get_peer("second").is_some() && second.get_peer("first").is_some() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(first.get_peers().len(), 1);
+        assert_eq!(first.get_peers()[0].get_node().get_id(), "second");
+        assert_eq!(second.get_peers().len(), 1);
+        assert_eq!(second.get_peers()[0].get_node().get_id(), "first");
+    }
+}
@@ -8,6 +8,9 @@
 /// The `Output` struct also maintains the state of the output device, which is updated every time
 /// the output is activated or deactivated.
 ///
+/// An output may declare a safe state, which it is driven to when the [`Runtime`](crate::Runtime)
+/// shuts down. For example, a heater should be turned off while an aerator should be left on.
+///
 /// # Example
 /// ```
 /// use equilibrium::Output;
@@ -22,6 +25,7 @@ pub struct Output<F>
 where F: FnMut(bool) {
     callback: F,
     state: Option<bool>,
+    safe_state: Option<bool>,
 }
 
 impl<F> Output<F>
@@ -34,7 +38,46 @@ where F: FnMut(bool) {
         Output {
             callback,
             state: None,
+            safe_state: None,
+        }
+    }
+
+    /// Builder method to declare the state that the output should be driven to on shutdown
+    ///
+    /// By default, no safe state is declared and the output is left untouched on shutdown.
+    ///
+    /// # Example
+    /// ```
+    /// use equilibrium::Output;
+    ///
+    /// let heater = Output::new(|state| {
+    ///     // low-level code would go here
+    /// }).set_safe_state(false);
+    /// ```
+    pub fn set_safe_state(mut self, state: bool) -> Self {
+        self.safe_state = Some(state);
+        self
+    }
+
+    pub fn get_safe_state(&self) -> Option<bool> {
+        self.safe_state
+    }
+
+    /// Consume the output and return the underlying callback
+    pub fn into_callback(self) -> F {
+        self.callback
+    }
+
+    /// Drive the output to its safe state, if one has been declared
+    ///
+    /// Returns the state that was applied.
+    pub fn apply_safe_state(&mut self) -> Option<bool> {
+        match self.safe_state {
+            Some(true) => self.activate(),
+            Some(false) => self.deactivate(),
+            None => {},
         }
+        self.safe_state
     }
 
     /// Activate the output
@@ -56,6 +99,18 @@ where F: FnMut(bool) {
     pub fn get_state(&self) -> Option<bool> {
         self.state
     }
+
+    /// Restore a previously cached state
+    ///
+    /// If the state is known, the output is driven to that state so that the physical device
+    /// matches the cached state. Otherwise, the output is left untouched.
+    pub fn restore(&mut self, state: Option<bool>) {
+        match state {
+            Some(true) => self.activate(),
+            Some(false) => self.deactivate(),
+            None => self.state = None,
+        }
+    }
 }
 
 impl Default for Output<fn(bool)> {
@@ -66,6 +121,7 @@ impl Default for Output<fn(bool)> {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::clone_on_copy)]
 mod tests {
     use std::sync::{Arc, Mutex};
 
@@ -109,4 +165,41 @@ mod tests {
         assert_eq!(external_state.lock().unwrap().clone(), false);
         assert_eq!(output.get_state().unwrap(), false);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_restore() {
+        let external_state = Arc::new(Mutex::new(false));
+        let mut output = super::Output::new(|state| {
+            let mut external_state = external_state.lock().unwrap();
+            *external_state = state;
+        });
+
+        output.restore(None);
+        assert_eq!(output.get_state(), None);
+        assert!(!*external_state.lock().unwrap());
+
+        output.restore(Some(true));
+        assert!(output.get_state().unwrap());
+        assert!(*external_state.lock().unwrap());
+    }
+
+    #[test]
+    fn test_apply_safe_state() {
+        let external_state = Arc::new(Mutex::new(true));
+        let mut output = super::Output::new(|state| {
+            let mut external_state = external_state.lock().unwrap();
+            *external_state = state;
+        });
+
+        // no safe state has been declared
+        assert_eq!(output.apply_safe_state(), None);
+        assert_eq!(output.get_state(), None);
+
+        let mut output = output.set_safe_state(false);
+        output.activate();
+
+        assert_eq!(output.apply_safe_state(), Some(false));
+        assert!(!output.get_state().unwrap());
+        assert!(!*external_state.lock().unwrap());
+    }
+}
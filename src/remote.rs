@@ -0,0 +1,322 @@
+//! Inputs which act on measurements taken by other nodes
+//!
+//! Every node publishes its messages to a broker through an [`Emitter`](crate::Emitter). A
+//! [`RemoteInput`] follows the latest message of a controller on another node, so that, for
+//! example, a pump controller on one node can act on a level sensor which is attached to another.
+//!
+//! The broker is polled with `GET {url}/nodes/{node}/controllers/{controller}/latest`, where the
+//! node and controller names are percent-encoded. It should answer with the latest [`Message`] as
+//! JSON, or `404 Not Found` if there is none.
+//!
+//! A reading is stale once its timestamp is older than the maximum age. Stale readings are
+//! replaced by the fallback value if one is set. Otherwise, [`RemoteInput::async_input`] fails,
+//! and [`RemoteInput::input`] returns the cause in place of a reading, which the controllers
+//! report as a "Read Failed" message without driving their outputs.
+//! Timestamps are taken by the remote node, so the clocks of both nodes should be synchronized.
+//!
+//! # Example
+//! ```no_run
+//! use chrono::Duration;
+//! use equilibrium::controllers::Threshold;
+//! use equilibrium::remote::RemoteInput;
+//! use equilibrium::Output;
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let sump_level = RemoteInput::new("http://broker:8000", "pump-house", "sump")
+//!     .set_max_age(Duration::minutes(2));
+//! sump_level.start();
+//!
+//! let pump = Threshold::new(
+//!     80.0,
+//!     sump_level.input(),
+//!     Output::default(),
+//!     Duration::seconds(30),
+//! );
+//! # });
+//! ```
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Duration, Utc};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use reqwest::{Client, StatusCode};
+use tokio::task::JoinHandle;
+use crate::{AsyncInput, Input};
+use crate::types::{DriverError, Message};
+
+/// Characters which are encoded within a path segment
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.').remove(b'~');
+
+/// Follows the readings of a controller on another node
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug, Clone)]
+pub struct RemoteInput {
+    url: String,
+    node: String,
+    controller: String,
+    max_age: Duration,
+    poll_interval: Duration,
+    fallback: Option<String>,
+    latest: Arc<Mutex<Option<Message>>>,
+}
+
+impl RemoteInput {
+    /// Create a remote input with a maximum age of 5 minutes, which polls the broker every 10
+    /// seconds
+    ///
+    /// # Arguments
+    /// * `url` - Base url of the broker
+    /// * `node` - Name of the node that takes the measurement
+    /// * `controller` - Name of the controller on that node
+    pub fn new<U, N, C>(url: U, node: N, controller: C) -> Self
+        where
+            U: Into<String>,
+            N: Into<String>,
+            C: Into<String>,
+    {
+        Self {
+            url: url.into(),
+            node: node.into(),
+            controller: controller.into(),
+            max_age: Duration::minutes(5),
+            poll_interval: Duration::seconds(10),
+            fallback: None,
+            latest: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Builder method to set how old a reading may be before it is stale
+    pub fn set_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Builder method to set how often the broker is polled
+    pub fn set_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Builder method to set the value that is used when the reading is stale
+    pub fn set_fallback<S>(mut self, value: S) -> Self
+        where S: Into<String>
+    {
+        self.fallback = Some(value.into());
+        self
+    }
+
+    /// Replace the latest message
+    ///
+    /// Messages without a reading, or which are older than the current one, are ignored.
+    pub fn update(&self, message: Message) {
+        update(&self.latest, message);
+    }
+
+    /// Get the latest message with a reading, regardless of its age
+    pub fn get_latest(&self) -> Option<Message> {
+        self.latest.lock().unwrap().clone()
+    }
+
+    /// Returns true if there is no reading that is younger than the maximum age
+    pub fn is_stale(&self, time: DateTime<Utc>) -> bool {
+        self.latest.lock().unwrap().as_ref()
+            .is_none_or(|latest| time - latest.get_timestamp() > self.max_age)
+    }
+
+    /// Get the reading at the given time, falling back if it is stale
+    pub fn read_at(&self, time: DateTime<Utc>) -> Result<String, DriverError> {
+        if !self.is_stale(time) {
+            if let Some(reading) = self.get_latest().and_then(|latest| latest.get_read_state()) {
+                return Ok(reading);
+            }
+        }
+        self.fallback.clone().ok_or_else(|| DriverError::Failed(format!(
+            "no recent reading from {}/{}",
+            self.node,
+            self.controller,
+        )))
+    }
+
+    fn latest_url(&self) -> String {
+        format!(
+            "{}/nodes/{}/controllers/{}/latest",
+            self.url.trim_end_matches('/'),
+            utf8_percent_encode(&self.node, SEGMENT),
+            utf8_percent_encode(&self.controller, SEGMENT),
+        )
+    }
+
+    /// Fetch the latest message from the broker
+    pub async fn fetch(&self, client: &Client) -> Result<(), reqwest::Error> {
+        if let Some(message) = fetch(client, &self.latest_url()).await? {
+            self.update(message);
+        }
+        Ok(())
+    }
+
+    /// Spawn a task which polls the broker
+    ///
+    /// Must be called from a tokio runtime. The task stops once every clone of the remote input,
+    /// and every input created from it, has been dropped.
+    pub fn start(&self) -> JoinHandle<()> {
+        let url = self.latest_url();
+        let latest = Arc::downgrade(&self.latest);
+        let interval = self.poll_interval.to_std().unwrap_or_default();
+        tokio::spawn(async move {
+            let client = Client::new();
+            loop {
+                let result = fetch(&client, &url).await;
+                let Some(latest) = latest.upgrade() else {
+                    return;
+                };
+                match result {
+                    Ok(Some(message)) => update(&latest, message),
+                    Ok(None) => {},
+                    Err(e) => eprintln!("Failed to fetch {}: {}", url, e),
+                }
+                drop(latest);
+                tokio::time::sleep(interval).await;
+            }
+        })
+    }
+
+    /// Create an [`Input`] which returns the latest reading
+    ///
+    /// If the reading is stale and there is no fallback, the cause is logged and returned
+    /// instead, such as `"no recent reading from pump-house/sump"`.
+    pub fn input(&self) -> Input<impl Fn() -> String + Send + Sync> {
+        let remote = self.clone();
+        Input::new(move || remote.read_at(Utc::now()).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            e.to_string()
+        }))
+    }
+
+    /// Create an [`AsyncInput`] which returns the latest reading, and fails if it is stale
+    pub fn async_input(&self) -> AsyncInput {
+        let remote = self.clone();
+        AsyncInput::new(move || {
+            let result = remote.read_at(Utc::now());
+            async move { result }
+        })
+    }
+}
+
+/// Replace the latest message, unless it is newer or the message has no reading
+fn update(latest: &Mutex<Option<Message>>, message: Message) {
+    if message.get_read_state().is_none() {
+        return;
+    }
+    let mut latest = latest.lock().unwrap();
+    if latest.as_ref().is_none_or(|l| l.get_timestamp() <= message.get_timestamp()) {
+        *latest = Some(message);
+    }
+}
+
+/// Request the latest message, returning `None` if the broker has none
+async fn fetch(client: &Client, url: &str) -> Result<Option<Message>, reqwest::Error> {
+    let response = client.get(url).send().await?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    response.error_for_status()?.json().await.map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use hyper::{Body, Request, Response, Server};
+    use hyper::service::{make_service_fn, service_fn};
+    use super::*;
+
+    #[test]
+    fn test_staleness() {
+        let remote = RemoteInput::new("http://localhost", "pump-house", "sump")
+            .set_max_age(Duration::minutes(2));
+        let now = Utc::now();
+        assert!(remote.is_stale(now));
+        assert!(remote.read_at(now).is_err());
+
+        remote.update(Message::new("sump", "Above Threshold", now, String::from("81.0")));
+        assert!(!remote.is_stale(now + Duration::minutes(2)));
+        assert_eq!(remote.read_at(now + Duration::minutes(2)), Ok(String::from("81.0")));
+
+        assert!(remote.is_stale(now + Duration::minutes(3)));
+        assert_eq!(
+            remote.read_at(now + Duration::minutes(3)),
+            Err(DriverError::Failed(String::from("no recent reading from pump-house/sump"))),
+        );
+
+        // older messages and messages without readings are ignored
+        remote.update(Message::new("sump", "Below Threshold", now - Duration::minutes(1), String::from("10.0")));
+        remote.update(Message::new("sump", "Mode Changed: Auto -> Disabled", now + Duration::minutes(1), None));
+        assert_eq!(remote.get_latest().unwrap().get_read_state(), Some(String::from("81.0")));
+    }
+
+    #[test]
+    fn test_fallback() {
+        let remote = RemoteInput::new("http://localhost", "pump-house", "sump")
+            .set_fallback("0.0");
+        assert_eq!(remote.input().read(), "0.0");
+
+        let remote = RemoteInput::new("http://localhost", "pump-house", "sump");
+        assert_eq!(remote.input().read(), "no recent reading from pump-house/sump");
+    }
+
+    #[test]
+    fn test_stale_read_failed() {
+        use crate::controllers::{Controller, Threshold};
+        use crate::Output;
+
+        let remote = RemoteInput::new("http://localhost", "pump-house", "sump");
+        let writes = Arc::new(Mutex::new(Vec::new()));
+        let log = writes.clone();
+        let time = Utc::now();
+        let mut refill = Threshold::new_without_scheduled(
+            20.0,
+            remote.input(),
+            Output::new(move |state| log.lock().unwrap().push(state)),
+            Duration::seconds(1),
+        ).set_inverted().schedule_next(time);
+
+        // the refill pump is not switched on because the level is unknown
+        let message = refill.poll(time + Duration::seconds(1)).unwrap();
+        assert_eq!(message.get_content(), "Read Failed: invalid reading \"no recent reading from pump-house/sump\"");
+        assert!(writes.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_start() {
+        let make_service = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+                let response = match req.uri().path() {
+                    "/nodes/pump-house/controllers/sump/latest" => {
+                        let message = Message::new("sump", "Above Threshold", Utc::now(), String::from("81.0"));
+                        Response::new(Body::from(serde_json::to_vec(&message).unwrap()))
+                    }
+                    _ => Response::builder().status(404).body(Body::empty()).unwrap(),
+                };
+                Ok::<_, Infallible>(response)
+            }))
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let remote = RemoteInput::new(format!("http://{}/", addr), "pump-house", "sump");
+        remote.start();
+
+        let mut input = remote.async_input();
+        let mut attempts = 0;
+        while input.read().await.is_err() && attempts < 50 {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            attempts += 1;
+        }
+        assert_eq!(input.read().await, Ok(String::from("81.0")));
+
+        // missing controllers are not an error
+        let missing = RemoteInput::new(format!("http://{}", addr), "pump-house", "well");
+        assert!(missing.fetch(&Client::new()).await.is_ok());
+        assert!(missing.get_latest().is_none());
+    }
+}
@@ -1,21 +1,97 @@
-use chrono::{Duration, Utc};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use chrono::{DateTime, Duration, Utc};
+use tokio::sync::mpsc;
 use tokio::time::sleep;
-use crate::{ControllerGroup, Emitter};
+use tokio_util::sync::CancellationToken;
+use crate::{ControllerGroup, Emitter, Subscriber};
+use crate::api::{self, ApiRequest};
+use crate::failover::Failover;
+use crate::historian::Historian;
+use crate::metrics::{self, Metrics};
+use crate::node::{Discovery, Health, Heartbeat};
+use crate::sinks::{Route, Sink};
+use crate::controllers::{AsyncController, Controller};
+use crate::store::{MemoryStateStore, StateStore};
+use crate::types::{Command, CommandError, CommandRequest, Message, NodeIdentity};
+use crate::worker::{panic_reason, FailurePolicy, Supervisor, WorkerSpec};
+
+/// Name of the route that [`Runtime::build_emitter`] attaches
+const EMITTER: &str = "emitter";
 
 /// A wrapper around a [`ControllerGroup`] that runs the group at a specified interval
 ///
-/// It has a loop that runs forever and polls the controllers. Any messages that are returned
-/// are sent to an optional [`Emitter`] for logging.
+/// It has a loop that runs until shutdown and polls the controllers. Any messages that are returned
+/// are sent to an optional [`Emitter`] for logging, and to any number of other [`Sink`]s, each with
+/// its own [`Filter`](crate::sinks::Filter) and rate limit (see [`Route`]). Heartbeats report the
+/// node as degraded while the last write to any of them failed.
 ///
 /// An `interval` defines how often the group is polled. This must be low enough to ensure that
 /// the controllers are polled often enough to meet their requirements. The loop will sleep for
 /// 100ms between polls to avoid busy-looping, however, the [`Runtime::run`] method is very
 /// greedy and will consume a substantial amount of CPU to ensure that the controllers are polled
 /// as accurately as possible.
+///
+/// An optional [`StateStore`] may be attached so that controller state survives a restart. The
+/// group is restored from the store when [`Runtime::run`] is called, and a snapshot is saved
+/// after every poll that produces messages.
+///
+/// An optional [`Historian`] may be attached to keep a local record of every message, regardless
+/// of whether an emitter is attached.
+///
+/// An optional HTTP API may be served for inspecting the controllers and sending them commands.
+/// See the [`api`](crate::api) module for the available endpoints.
+///
+/// Optional Prometheus metrics may be served for monitoring the node. See the
+/// [`metrics`](crate::metrics) module for the available metrics.
+///
+/// Controllers which are slow or unreliable may be run on a dedicated thread with their own
+/// interval by using [`Runtime::add_isolated_controller`]. See the [`worker`](crate::worker)
+/// module for details on supervision and message ordering.
+///
+/// An optional [`Subscriber`] may be attached to receive [`Command`]s from a remote broker. Each
+/// command is routed to the named controller in the group, and is acknowledged with a
+/// "Command Acknowledged" or "Command Rejected" [`Message`] which is emitted like any other.
+///
+/// Controllers which implement [`AsyncController`] are added with
+/// [`Runtime::add_async_controller`] and run as separate tokio tasks with their own interval.
+///
+/// An optional [`NodeIdentity`] is attached to every message that the runtime emits, so that
+/// messages from different nodes can be told apart. With a heartbeat interval, the runtime also
+/// emits a periodic [`Heartbeat`], and announces it to other nodes if a [`Discovery`] is attached.
+/// See the [`node`](crate::node) module for details.
+///
+/// Two runtimes may run the same controllers in hot-standby with [`Runtime::set_failover`]. See
+/// the [`failover`](crate::failover) module for details.
+///
+/// # Shutdown
+/// The runtime is shut down by cancelling the token returned by [`Runtime::shutdown_token`], by a
+/// `POST /shutdown` request to the HTTP API, or by SIGTERM/SIGINT if [`Runtime::handle_signals`]
+/// is used. On shutdown, polling stops, every output is driven to its declared safe state (see
+/// [`Output::set_safe_state`](crate::Output::set_safe_state)), and the resulting messages are
+/// flushed before [`Runtime::run`] returns.
 pub struct Runtime {
-    emitter: Option<Emitter>,
+    routes: Vec<Route>,
     group: ControllerGroup,
     interval: Duration,
+    store: Option<Box<dyn StateStore>>,
+    historian: Option<Historian>,
+    api_addr: Option<SocketAddr>,
+    metrics: Option<(SocketAddr, Metrics)>,
+    shutdown: CancellationToken,
+    handle_signals: bool,
+    workers: Vec<WorkerSpec>,
+    failure_policy: FailurePolicy,
+    poll_timeout: std::time::Duration,
+    async_controllers: Vec<(Box<dyn AsyncController + Send>, Duration)>,
+    subscriber: Option<Subscriber>,
+    reload: Option<Box<dyn FnMut() -> Result<ControllerGroup, String>>>,
+    identity: Option<NodeIdentity>,
+    heartbeat: Option<Duration>,
+    discovery: Option<Discovery>,
+    failover: Option<Failover>,
 }
 
 impl Runtime {
@@ -53,14 +129,33 @@ impl Runtime {
     /// ```
     pub fn new(group: ControllerGroup, interval: Duration) -> Self {
         Self {
-            emitter: None,
+            routes: Vec::new(),
             group,
             interval,
+            store: None,
+            historian: None,
+            api_addr: None,
+            metrics: None,
+            shutdown: CancellationToken::new(),
+            handle_signals: false,
+            workers: Vec::new(),
+            failure_policy: FailurePolicy::default(),
+            poll_timeout: std::time::Duration::from_secs(30),
+            async_controllers: Vec::new(),
+            subscriber: None,
+            reload: None,
+            identity: None,
+            heartbeat: None,
+            discovery: None,
+            failover: None,
         }
     }
 
     /// Builder method to add an emitter to the runtime
     ///
+    /// The emitter is attached as a [`Route`] named "emitter", so that it is written to, and
+    /// retries, without holding up the runtime.
+    ///
     /// # Arguments
     /// * `url` - The url to build the emitter with
     ///
@@ -81,39 +176,1277 @@ impl Runtime {
     {
         let emitter = Emitter::new(url);
 
-        self.emitter = Some(emitter);
+        self.routes.push(Route::new(EMITTER, emitter));
         self
     }
 
     /// Returns true if an emitter has been built
     pub fn has_emitter(&self) -> bool {
-        self.emitter.is_some()
+        self.routes.iter().any(|route| route.get_name() == EMITTER)
+    }
+
+    /// Builder method to write every message to another [`Sink`]
+    ///
+    /// See the [`sinks`](crate::sinks) module for the available sinks.
+    pub fn add_sink<S>(mut self, sink: S) -> Self
+        where S: Sink + 'static
+    {
+        let name = format!("sink {}", self.routes.len() + 1);
+        self.routes.push(Route::new(name, sink));
+        self
+    }
+
+    /// Builder method to write the messages selected by a [`Route`] to its sink
+    pub fn add_route(mut self, route: Route) -> Self {
+        self.routes.push(route);
+        self
+    }
+
+    /// Builder method to receive commands from a broker
+    ///
+    /// # Arguments
+    /// * `url` - The url that is long-polled for commands. See [`Subscriber`] for the protocol.
+    ///
+    /// # Example
+    /// ```
+    /// use equilibrium::{Runtime, ControllerGroup};
+    ///
+    /// let runtime = Runtime::new(
+    ///     ControllerGroup::new(),
+    ///     chrono::Duration::seconds(1)
+    /// )
+    ///     .build_emitter("http://localhost:8000/messages")
+    ///     .build_subscriber("http://localhost:8000/commands");
+    /// ```
+    pub fn build_subscriber<S>(mut self, url: S) -> Self
+        where S: Into<String>
+    {
+        self.subscriber = Some(Subscriber::new(url));
+        self
+    }
+
+    /// Returns true if a subscriber has been built
+    pub fn has_subscriber(&self) -> bool {
+        self.subscriber.is_some()
+    }
+
+    /// Builder method to set how the group is rebuilt when a [`Command::Reload`] is received
+    ///
+    /// The factory typically reads a configuration file and builds a new [`ControllerGroup`]. The
+    /// state and [`Mode`](crate::types::Mode) of each controller are carried over to the
+    /// controller with the same name in the new group. If the factory fails, the current group is
+    /// kept and the command is rejected.
+    pub fn set_reload<F>(mut self, factory: F) -> Self
+        where F: FnMut() -> Result<ControllerGroup, String> + 'static
+    {
+        self.reload = Some(Box::new(factory));
+        self
+    }
+
+    /// Builder method to set the identity that is attached to every emitted message
+    ///
+    /// # Example
+    /// ```
+    /// use equilibrium::{Runtime, ControllerGroup};
+    /// use equilibrium::types::NodeIdentity;
+    ///
+    /// let runtime = Runtime::new(
+    ///     ControllerGroup::new(),
+    ///     chrono::Duration::seconds(1)
+    /// ).set_identity(NodeIdentity::new("greenhouse-1").add_tag("zone", "seedlings"));
+    /// ```
+    pub fn set_identity(mut self, identity: NodeIdentity) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// Get the identity of the node, if one has been set
+    pub fn get_identity(&self) -> Option<&NodeIdentity> {
+        self.identity.as_ref()
+    }
+
+    /// Builder method to emit a [`Heartbeat`] at the given interval
+    ///
+    /// If no identity has been set, heartbeats are sent with [`NodeIdentity::default`].
+    pub fn set_heartbeat(mut self, interval: Duration) -> Self {
+        self.heartbeat = Some(interval);
+        self
+    }
+
+    /// Builder method to announce heartbeats to other nodes and discover theirs
+    ///
+    /// The discovery is started when [`Runtime::run`] is called, unless it has already been
+    /// started. Heartbeats are announced every 30 seconds unless a heartbeat interval has been set.
+    pub fn set_discovery(mut self, discovery: Discovery) -> Self {
+        self.discovery = Some(discovery);
+        self
+    }
+
+    /// Builder method to run in hot-standby with another node
+    ///
+    /// The role of the node is decided from the heartbeats of the peer, which are received by the
+    /// [`Discovery`]. Without a discovery, the node takes over once the timeout has passed. When
+    /// the node becomes active, the outputs of the named controllers in the group are driven to
+    /// their cached states. If a [`StateStore`] is attached, the epoch is persisted so that the
+    /// fencing token keeps increasing when both nodes restart.
+    ///
+    /// # Example
+    /// ```
+    /// use chrono::Duration;
+    /// use equilibrium::{Runtime, ControllerGroup};
+    /// use equilibrium::failover::Failover;
+    /// use equilibrium::node::Discovery;
+    /// use equilibrium::types::NodeIdentity;
+    ///
+    /// let runtime = Runtime::new(ControllerGroup::new(), Duration::seconds(1))
+    ///     .set_identity(NodeIdentity::new("aeration-a"))
+    ///     .set_heartbeat(Duration::seconds(2))
+    ///     .set_discovery(Discovery::new(47800))
+    ///     .set_failover(Failover::new("aeration-b", Duration::seconds(10)).set_primary());
+    /// ```
+    pub fn set_failover(mut self, failover: Failover) -> Self {
+        self.failover = Some(failover);
+        self
+    }
+
+    /// Builder method to attach a [`StateStore`] to the runtime
+    ///
+    /// # Example
+    /// ```
+    /// use equilibrium::{Runtime, ControllerGroup};
+    /// use equilibrium::store::MemoryStateStore;
+    ///
+    /// let runtime = Runtime::new(
+    ///     ControllerGroup::new(),
+    ///     chrono::Duration::seconds(1)
+    /// ).set_state_store(MemoryStateStore::new());
+    /// ```
+    pub fn set_state_store<S>(mut self, store: S) -> Self
+        where S: StateStore + 'static
+    {
+        self.store = Some(Box::new(store));
+        self
+    }
+
+    /// Returns true if a state store has been attached
+    pub fn has_state_store(&self) -> bool {
+        self.store.is_some()
+    }
+
+    /// Builder method to attach a [`Historian`] to the runtime
+    ///
+    /// Every message that is sent to the emitter is also recorded by the historian.
+    pub fn set_historian(mut self, historian: Historian) -> Self {
+        self.historian = Some(historian);
+        self
+    }
+
+    /// Returns true if a historian has been attached
+    pub fn has_historian(&self) -> bool {
+        self.historian.is_some()
+    }
+
+    /// Builder method to serve the HTTP API on the given address
+    ///
+    /// The server is started when [`Runtime::run`] is called.
+    ///
+    /// # Example
+    /// ```
+    /// use equilibrium::{Runtime, ControllerGroup};
+    ///
+    /// let runtime = Runtime::new(
+    ///     ControllerGroup::new(),
+    ///     chrono::Duration::seconds(1)
+    /// ).serve_api(([127, 0, 0, 1], 8080));
+    /// ```
+    pub fn serve_api<A>(mut self, addr: A) -> Self
+        where A: Into<SocketAddr>
+    {
+        self.api_addr = Some(addr.into());
+        self
+    }
+
+    /// Builder method to serve Prometheus metrics on the given address
+    ///
+    /// The server is started when [`Runtime::run`] is called.
+    ///
+    /// # Example
+    /// ```
+    /// use equilibrium::{Runtime, ControllerGroup};
+    ///
+    /// let runtime = Runtime::new(
+    ///     ControllerGroup::new(),
+    ///     chrono::Duration::seconds(1)
+    /// ).serve_metrics(([0, 0, 0, 0], 9100));
+    /// ```
+    pub fn serve_metrics<A>(mut self, addr: A) -> Self
+        where A: Into<SocketAddr>
+    {
+        self.metrics = Some((addr.into(), Metrics::new()));
+        self
+    }
+
+    /// Builder method to run a controller on a dedicated thread with its own polling interval
+    ///
+    /// The controller is created by `factory` when the runtime starts, and again whenever the
+    /// controller is restarted after a failure. Isolated controllers are not part of the
+    /// [`ControllerGroup`], and are therefore not persisted or available via the HTTP API.
+    ///
+    /// # Arguments
+    /// * `factory` - Creates a new instance of the controller
+    /// * `interval` - How often the controller is polled
+    ///
+    /// # Example
+    /// ```
+    /// use chrono::Duration;
+    /// use equilibrium::{Runtime, ControllerGroup, Input, Output};
+    /// use equilibrium::controllers::Threshold;
+    ///
+    /// let runtime = Runtime::new(
+    ///     ControllerGroup::new(),
+    ///     Duration::seconds(1),
+    /// ).add_isolated_controller(|| Threshold::new(
+    ///     70.0,
+    ///     Input::new(|| {
+    ///         // a slow 1-Wire conversion would go here
+    ///         String::from("69.0")
+    ///     }),
+    ///     Output::default(),
+    ///     Duration::seconds(5),
+    /// ), Duration::seconds(1));
+    /// ```
+    pub fn add_isolated_controller<F, C>(mut self, factory: F, interval: Duration) -> Self
+        where
+            F: Fn() -> C + Send + Sync + 'static,
+            C: Controller + Send + 'static,
+    {
+        self.workers.push(WorkerSpec::new(factory, interval));
+        self
+    }
+
+    /// Builder method to run an [`AsyncController`] as a tokio task with its own polling interval
+    ///
+    /// Async controllers are not part of the [`ControllerGroup`], and are therefore not persisted
+    /// or available via the HTTP API. Their messages are emitted alongside those of the group.
+    ///
+    /// If a poll panics, the controller is lost along with its task, so it is quarantined and a
+    /// "Controller Quarantined" message is emitted. Its outputs are first driven to their safe
+    /// states using the [`AsyncController::safe_state_actions`] that were taken before the task
+    /// started. Controllers which should be restarted instead are added with
+    /// [`Runtime::add_isolated_controller`].
+    ///
+    /// # Example
+    /// ```
+    /// use chrono::Duration;
+    /// use equilibrium::{AsyncInput, AsyncOutput, Runtime, ControllerGroup};
+    /// use equilibrium::controllers::AsyncThreshold;
+    ///
+    /// let runtime = Runtime::new(
+    ///     ControllerGroup::new(),
+    ///     Duration::seconds(1),
+    /// ).add_async_controller(AsyncThreshold::new(
+    ///     70.0,
+    ///     AsyncInput::new(|| async {
+    ///         // a network request would go here
+    ///         Ok(String::from("69.0"))
+    ///     }),
+    ///     AsyncOutput::new(|_| async { Ok(()) }),
+    ///     Duration::seconds(5),
+    /// ), Duration::seconds(1));
+    /// ```
+    pub fn add_async_controller<C>(mut self, controller: C, interval: Duration) -> Self
+        where C: AsyncController + Send + 'static
+    {
+        self.async_controllers.push((Box::new(controller), interval));
+        self
+    }
+
+    /// Builder method to set how isolated controllers are handled when a poll panics or times out
+    pub fn set_failure_policy(mut self, policy: FailurePolicy) -> Self {
+        self.failure_policy = policy;
+        self
+    }
+
+    /// Builder method to set how long a poll of an isolated controller may take
+    ///
+    /// Defaults to 30 seconds.
+    pub fn set_poll_timeout(mut self, timeout: Duration) -> Self {
+        self.poll_timeout = timeout.to_std().unwrap_or_default();
+        self
+    }
+
+    /// Builder method to shut down the runtime when SIGTERM or SIGINT is received
+    pub fn handle_signals(mut self) -> Self {
+        self.handle_signals = true;
+        self
+    }
+
+    /// Returns a token that shuts down the runtime when cancelled
+    ///
+    /// # Example
+    /// ```
+    /// use equilibrium::{Runtime, ControllerGroup};
+    ///
+    /// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+    /// let mut runtime = Runtime::new(
+    ///     ControllerGroup::new(),
+    ///     chrono::Duration::seconds(1)
+    /// );
+    ///
+    /// let token = runtime.shutdown_token();
+    /// token.cancel();
+    ///
+    /// // returns immediately since the token has been cancelled
+    /// runtime.run().await;
+    /// # });
+    /// ```
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
+
+    /// Persist, record and emit messages
+    async fn process(&mut self, messages: Vec<Message>) {
+        if messages.is_empty() {
+            return;
+        }
+        self.save_state();
+        self.publish(messages).await;
+    }
+
+    /// Save a snapshot of the group to the state store, if one is attached
+    fn save_state(&mut self) {
+        if let Some(store) = &mut self.store {
+            if let Err(e) = self.group.save_state(store.as_mut()) {
+                eprintln!("Failed to save controller state: {}", e);
+            }
+        }
+    }
+
+    /// Record and emit messages without persisting the group
+    async fn publish(&mut self, mut messages: Vec<Message>) {
+        if messages.is_empty() {
+            return;
+        }
+
+        if let Some(identity) = &self.identity {
+            messages = messages.into_iter()
+                .map(|message| match message.get_node() {
+                    Some(_) => message,
+                    None => message.set_node(identity.clone()),
+                })
+                .collect();
+        }
+
+        if let Some((_, metrics)) = &self.metrics {
+            metrics.record(&messages);
+        }
+
+        if let Some(historian) = &mut self.historian {
+            if let Err(e) = historian.record(&messages) {
+                eprintln!("Failed to record messages: {}", e);
+            }
+        }
+
+        // routes only queue messages, so the runtime is not held up by a slow or failing sink
+        for route in self.routes.iter_mut() {
+            route.send(&messages);
+            if let Some((_, metrics)) = &self.metrics {
+                metrics.update_sink(route.get_name(), route.get_failures(), route.get_retries());
+            }
+        }
+    }
+
+    /// Report the health of the node
+    ///
+    /// # Arguments
+    /// * `time` - Timestamp of the heartbeat
+    /// * `uptime` - How long the runtime has been running
+    /// * `controllers` - How many controllers run outside of the group
+    /// * `quarantined` - How many of those have been quarantined
+    fn heartbeat(&self, time: DateTime<Utc>, uptime: Duration, controllers: usize, quarantined: usize) -> Heartbeat {
+        let mut reasons = Vec::new();
+        if quarantined > 0 {
+            reasons.push(format!("{} controllers quarantined", quarantined));
+        }
+        for route in self.routes.iter().filter(|route| route.is_failing()) {
+            reasons.push(format!("{} failing", route.get_name()));
+        }
+        let health = match reasons.is_empty() {
+            true => Health::Healthy,
+            false => Health::Degraded { reasons },
+        };
+
+        let heartbeat = Heartbeat::new(
+            self.identity.clone().unwrap_or_default(),
+            time,
+            uptime,
+            self.group.get_controllers().len() + controllers,
+            health,
+        );
+        match &self.failover {
+            Some(failover) => heartbeat.set_leadership(failover.get_leadership()),
+            None => heartbeat,
+        }
+    }
+
+    /// Decide the role of the node, driving the outputs if it has become active
+    fn observe_failover(&mut self, id: &str, time: DateTime<Utc>) -> Option<Message> {
+        let failover = self.failover.as_ref()?;
+        let peer = self.discovery.as_ref().and_then(|discovery| discovery.get_peer(failover.get_peer()));
+        let message = failover.observe(id, peer.as_ref(), time)?;
+
+        // the epoch is saved before any output is driven in it
+        if let Some(store) = &mut self.store {
+            if let Err(e) = store.save(Failover::STATE_NAME, &failover.snapshot()) {
+                eprintln!("Failed to save failover epoch: {}", e);
+            }
+        }
+        if failover.is_active() {
+            let mut snapshot = MemoryStateStore::new();
+            let result = self.group.save_state(&mut snapshot)
+                .and_then(|_| self.group.restore_state(&snapshot));
+            if let Err(e) = result {
+                eprintln!("Failed to drive outputs after takeover: {}", e);
+            }
+        }
+        Some(message)
+    }
+
+    /// Rebuild the group using the reload factory, carrying over controller state
+    fn reload(&mut self) -> Result<(), CommandError> {
+        let factory = self.reload.as_mut().ok_or(CommandError::Unsupported)?;
+        let mut group = factory().map_err(CommandError::Failed)?;
+
+        let mut snapshot = MemoryStateStore::new();
+        self.group.save_state(&mut snapshot)
+            .and_then(|_| group.restore_state(&snapshot))
+            .map_err(|e| CommandError::Failed(e.to_string()))?;
+        self.group = group;
+        Ok(())
+    }
+
+    /// Route a command to the group and return the message that acknowledges it
+    fn handle_command(&mut self, request: CommandRequest) -> Message {
+        let time = Utc::now();
+        let result = match (request.get_command(), request.get_controller()) {
+            (Command::Reload, _) => self.reload(),
+            (command, Some(name)) => self.group.execute(name, command.clone(), time),
+            (_, None) => Err(CommandError::Failed(String::from("no controller was named"))),
+        };
+
+        let id = request.get_id().map(|id| format!(" ({})", id)).unwrap_or_default();
+        let content = match result {
+            Ok(()) => format!("Command Acknowledged{}", id),
+            Err(e) => format!("Command Rejected{}: {}", id, e),
+        };
+        Message::new(request.get_controller().unwrap_or("runtime").to_string(), content, time, None)
     }
 
     /// Execute the runtime
     ///
-    /// This method will run forever and should be called from a tokio runtime
+    /// This method runs until the runtime is shut down and should be called from a tokio runtime.
     pub async fn run(&mut self) {
+        if let Some(store) = &self.store {
+            if let Err(e) = self.group.restore_state(store.as_ref()) {
+                eprintln!("Failed to restore controller state: {}", e);
+            }
+            if let Some(failover) = &self.failover {
+                match store.load(Failover::STATE_NAME) {
+                    Ok(Some(state)) => failover.restore(&state),
+                    Ok(None) => {},
+                    Err(e) => eprintln!("Failed to restore failover epoch: {}", e),
+                }
+            }
+        }
+
+        let started = Instant::now();
+        let listener = match &self.discovery {
+            Some(discovery) if discovery.get_local_addr().is_none() => match discovery.start().await {
+                Ok(listener) => Some(listener),
+                Err(e) => {
+                    eprintln!("Failed to start discovery: {}", e);
+                    None
+                }
+            },
+            _ => None,
+        };
+
+        if self.handle_signals {
+            let token = self.shutdown.clone();
+            tokio::spawn(async move {
+                wait_for_signal().await;
+                token.cancel();
+            });
+        }
+
+        let mut api_requests = self.api_addr.and_then(|addr| {
+            let (tx, rx) = mpsc::channel::<ApiRequest>(16);
+            match api::spawn(addr, tx, self.shutdown.clone()) {
+                Ok(_) => Some(rx),
+                Err(e) => {
+                    eprintln!("Failed to start API server: {}", e);
+                    None
+                }
+            }
+        });
+
+        if let Some((addr, metrics)) = &self.metrics {
+            metrics.set_node(self.identity.clone().unwrap_or_default().get_id());
+            if let Err(e) = metrics::spawn(*addr, metrics.clone(), self.shutdown.clone()) {
+                eprintln!("Failed to start metrics server: {}", e);
+            }
+        }
+
+        let mut commands = self.subscriber.take().map(|subscriber| {
+            let (tx, rx) = mpsc::channel::<CommandRequest>(16);
+            tokio::spawn(subscribe(subscriber, tx, self.shutdown.clone()));
+            rx
+        });
+
+        let mut supervisor = Supervisor::new(
+            std::mem::take(&mut self.workers),
+            self.failure_policy,
+            self.poll_timeout,
+            self.shutdown.clone(),
+        );
+        supervisor.start();
+
+        let (async_tx, mut async_rx) = mpsc::unbounded_channel();
+        let async_quarantined = Arc::new(AtomicUsize::new(0));
+        let tasks: Vec<_> = std::mem::take(&mut self.async_controllers)
+            .into_iter()
+            .map(|(controller, interval)| spawn_async(
+                controller,
+                interval,
+                async_tx.clone(),
+                self.shutdown.clone(),
+                async_quarantined.clone(),
+            ))
+            .collect();
+        drop(async_tx);
+
+        let shutdown = self.shutdown.clone();
         let mut next_execution_time = Utc::now() + self.interval;
-        loop {
+        let heartbeat_interval = self.heartbeat.unwrap_or(Duration::seconds(30));
+        let mut next_heartbeat = Utc::now();
+        let id = self.identity.clone().unwrap_or_default().get_id().to_string();
+        while !shutdown.is_cancelled() {
             let now = Utc::now();
 
-            if now >= next_execution_time {
-                // poll the group and get messages
-                let messages = self.group.poll(now);
+            let mut messages = supervisor.collect();
+            if let Some(message) = self.observe_failover(&id, now) {
+                messages.push(message);
+                // let the peer know about the new role straight away
+                next_heartbeat = now;
+            }
 
-                if !messages.is_empty() {
-                    if let Some(emitter) = &self.emitter {
-                        emitter.emit(messages).await.unwrap();
+            if now >= next_heartbeat && (self.heartbeat.is_some() || self.discovery.is_some()) {
+                let uptime = Duration::from_std(started.elapsed()).unwrap_or(Duration::zero());
+                let controllers = supervisor.count() + tasks.len();
+                let quarantined = supervisor.quarantined() + async_quarantined.load(Ordering::Relaxed);
+                let heartbeat = self.heartbeat(now, uptime, controllers, quarantined);
+                if let Some(discovery) = &self.discovery {
+                    if let Err(e) = discovery.announce(&heartbeat).await {
+                        eprintln!("Failed to announce heartbeat: {}", e);
                     }
                 }
+                if self.heartbeat.is_some() {
+                    self.process(vec![heartbeat.to_message()]).await;
+                }
+                next_heartbeat = now + heartbeat_interval;
+            }
+
+            while let Ok(message) = async_rx.try_recv() {
+                messages.push(message);
+            }
+            if now >= next_execution_time {
+                // poll the group and get messages
+                messages.extend(self.group.poll(now));
+                if let Some((_, metrics)) = &self.metrics {
+                    metrics.update_status(self.group.status());
+                }
 
                 // update the next execution time
                 next_execution_time = now + self.interval;
             }
+            self.process(messages).await;
+
+            // sleep for 100ms to avoid busy-looping, answering API requests in the meantime
+            tokio::select! {
+                _ = sleep(std::time::Duration::from_millis(100)) => {},
+                _ = shutdown.cancelled() => {},
+                Some(request) = recv(&mut api_requests) => request.handle(&mut self.group),
+                Some(request) = recv(&mut commands) => {
+                    let message = self.handle_command(request);
+                    self.process(vec![message]).await;
+                },
+            }
+        }
+
+        // drive outputs to their safe states and flush the final messages
+        let mut messages = supervisor.shutdown().await;
+        for task in tasks {
+            if tokio::time::timeout(self.poll_timeout, task).await.is_err() {
+                eprintln!("Async controller did not shut down in time");
+            }
+        }
+        while let Ok(message) = async_rx.try_recv() {
+            messages.push(message);
+        }
+        // the snapshot is taken before the safe states are applied, so that the outputs are
+        // restored to the state that the controllers computed rather than to their safe states
+        self.save_state();
+        messages.extend(self.group.shutdown(Utc::now()));
+        self.publish(messages).await;
+        for route in self.routes.iter_mut() {
+            route.close(self.poll_timeout).await;
+        }
+
+        if let Some(listener) = listener {
+            listener.abort();
+        }
+    }
+}
+
+/// Spawn a task that polls an async controller until shutdown, then drives it to its safe state
+///
+/// The controller runs on a task of its own, which is watched by the returned task. If the
+/// controller panics, its outputs are driven to their safe states and it is quarantined: the
+/// counter is incremented and a message is sent.
+fn spawn_async(
+    mut controller: Box<dyn AsyncController + Send>,
+    interval: Duration,
+    tx: mpsc::UnboundedSender<Message>,
+    shutdown: CancellationToken,
+    quarantined: Arc<AtomicUsize>,
+) -> tokio::task::JoinHandle<()> {
+    let name = controller.get_name().unwrap_or_default();
+    let actions = controller.safe_state_actions();
+    let events = tx.clone();
+    let interval = interval.to_std().unwrap_or_default();
+    let task = tokio::spawn(async move {
+        while !shutdown.is_cancelled() {
+            if let Some(message) = controller.poll(Utc::now()).await {
+                let _ = tx.send(message);
+            }
+            tokio::select! {
+                _ = sleep(interval) => {},
+                _ = shutdown.cancelled() => {},
+            }
+        }
+        if let Some(message) = controller.shutdown(Utc::now()).await {
+            let _ = tx.send(message);
+        }
+    });
+
+    tokio::spawn(async move {
+        match task.await {
+            Err(e) if e.is_panic() => {
+                quarantined.fetch_add(1, Ordering::Relaxed);
+                for action in actions.iter() {
+                    let (content, state) = match action.apply().await {
+                        Ok(state) => (String::from("Safe State"), Some(state)),
+                        Err(e) => (format!("Write Failed: {}", e), None),
+                    };
+                    let message = Message::new(name.clone(), content, Utc::now(), None).set_output_state(state);
+                    let _ = events.send(message);
+                }
+                let content = format!("Controller Quarantined: panicked: {}", panic_reason(e.into_panic()));
+                let _ = events.send(Message::new(name, content, Utc::now(), None));
+            }
+            _ => {}
+        }
+    })
+}
+
+/// Forward commands from the subscriber to the runtime loop until shutdown
+async fn subscribe(subscriber: Subscriber, tx: mpsc::Sender<CommandRequest>, shutdown: CancellationToken) {
+    loop {
+        let result = tokio::select! {
+            result = subscriber.receive() => result,
+            _ = shutdown.cancelled() => return,
+        };
+        match result {
+            Ok(requests) => for request in requests {
+                if tx.send(request).await.is_err() {
+                    return;
+                }
+            },
+            Err(e) => {
+                eprintln!("Failed to receive commands: {}", e);
+                // back off before reconnecting
+                tokio::select! {
+                    _ = sleep(std::time::Duration::from_secs(5)) => {},
+                    _ = shutdown.cancelled() => return,
+                }
+            }
+        }
+    }
+}
+
+/// Receive the next request, or wait forever if there is no channel
+async fn recv<T>(rx: &mut Option<mpsc::Receiver<T>>) -> Option<T> {
+    match rx {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Wait for SIGTERM or SIGINT
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut sigterm) => tokio::select! {
+            _ = sigterm.recv() => {},
+            _ = tokio::signal::ctrl_c() => {},
+        },
+        Err(_) => {
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+}
+
+/// Wait for Ctrl-C
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use crate::controllers::{Controller, Threshold};
+    use crate::{AsyncOutput, Input, Output, SafeStateAction};
+    use super::*;
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let external_state = Arc::new(Mutex::new(None));
+        let state = external_state.clone();
+        let output = Output::new(move |value| {
+            *state.lock().unwrap() = Some(value);
+        }).set_safe_state(false);
+
+        let mut controller = Threshold::new(
+            70.0,
+            Input::new(|| "69.0".to_string()),
+            output,
+            Duration::milliseconds(100),
+        ).set_inverted();
+        controller.set_name(String::from("heater"));
+
+        let mut runtime = Runtime::new(
+            ControllerGroup::new().add_controller(controller),
+            Duration::milliseconds(100),
+        );
+
+        let token = runtime.shutdown_token();
+        let state = external_state.clone();
+        tokio::spawn(async move {
+            // wait for the heater to be activated before shutting down
+            while *state.lock().unwrap() != Some(true) {
+                sleep(std::time::Duration::from_millis(10)).await;
+            }
+            token.cancel();
+        });
+
+        tokio::time::timeout(std::time::Duration::from_secs(5), runtime.run())
+            .await
+            .expect("runtime did not shut down");
+
+        assert_eq!(*external_state.lock().unwrap(), Some(false));
+    }
+
+    #[tokio::test]
+    async fn test_restart_during_window() {
+        use chrono::{NaiveTime, Timelike};
+        use crate::controllers::{CatchUpPolicy, TimedOutput};
+        use crate::store::{FileStateStore, StateStore};
+        use crate::test_util::TempDir;
+
+        let dir = TempDir::new("runtime-restart");
+
+        /// Run a grow light, whose window started an hour ago, until it has been switched on
+        async fn run_light(dir: &TempDir) -> Vec<bool> {
+            let writes = Arc::new(Mutex::new(Vec::new()));
+            let log = writes.clone();
+            let output = Output::new(move |value| log.lock().unwrap().push(value))
+                .set_safe_state(false);
+            let start = (Utc::now() - Duration::hours(1)).time();
+            let start = NaiveTime::from_hms_opt(start.hour(), start.minute(), start.second()).unwrap();
+            let mut light = TimedOutput::new(output, start, Duration::hours(4))
+                .set_catch_up(CatchUpPolicy::Reconstruct);
+            light.set_name(String::from("light"));
+
+            let mut runtime = Runtime::new(
+                ControllerGroup::new().add_controller(light),
+                Duration::milliseconds(100),
+            ).set_state_store(FileStateStore::new(dir).unwrap());
+
+            let token = runtime.shutdown_token();
+            let state = writes.clone();
+            tokio::spawn(async move {
+                while !state.lock().unwrap().contains(&true) {
+                    sleep(std::time::Duration::from_millis(10)).await;
+                }
+                token.cancel();
+            });
+            tokio::time::timeout(std::time::Duration::from_secs(5), runtime.run())
+                .await
+                .expect("runtime did not shut down");
+
+            let writes = writes.lock().unwrap().clone();
+            writes
+        }
+
+        // the light is switched off on shutdown, but the snapshot holds the computed state
+        assert_eq!(run_light(&dir).await, vec![true, false]);
+        let state = FileStateStore::new(&dir).unwrap().load("light").unwrap().unwrap();
+        assert_eq!(state.get_outputs(), &vec![Some(true)]);
+
+        // after the restart, the light is switched on again
+        assert_eq!(run_light(&dir).await, vec![true, false]);
+    }
+
+    #[tokio::test]
+    async fn test_async_controller() {
+        use crate::{AsyncInput, AsyncOutput};
+        use crate::controllers::AsyncThreshold;
+
+        let external_state = Arc::new(Mutex::new(None));
+        let state = external_state.clone();
+        let output = AsyncOutput::new(move |value| {
+            let state = state.clone();
+            async move {
+                *state.lock().unwrap() = Some(value);
+                Ok(())
+            }
+        }).set_safe_state(false);
+
+        let controller = AsyncThreshold::new(
+            70.0,
+            AsyncInput::new(|| async { Ok(String::from("69.0")) }),
+            output,
+            Duration::milliseconds(50),
+        ).set_inverted();
+
+        let mut runtime = Runtime::new(ControllerGroup::new(), Duration::milliseconds(100))
+            .add_async_controller(controller, Duration::milliseconds(10));
+
+        let token = runtime.shutdown_token();
+        let state = external_state.clone();
+        tokio::spawn(async move {
+            while *state.lock().unwrap() != Some(true) {
+                sleep(std::time::Duration::from_millis(10)).await;
+            }
+            token.cancel();
+        });
+
+        tokio::time::timeout(std::time::Duration::from_secs(5), runtime.run())
+            .await
+            .expect("runtime did not shut down");
 
-            // sleep for 100ms to avoid busy-looping
-            sleep(std::time::Duration::milliseconds(100)).await
+        assert_eq!(*external_state.lock().unwrap(), Some(false));
+    }
+
+    #[test]
+    fn test_handle_command() {
+        let mut controller = Threshold::new(
+            70.0,
+            Input::new(|| "69.0".to_string()),
+            Output::default(),
+            Duration::minutes(5),
+        );
+        controller.set_name(String::from("heater"));
+        let mut runtime = Runtime::new(
+            ControllerGroup::new().add_controller(controller),
+            Duration::seconds(1),
+        );
+
+        let request = CommandRequest::new(Some(String::from("1")), Some("heater"), Command::SetSetpoint { value: 72.0 });
+        let message = runtime.handle_command(request);
+        assert_eq!(message.get_controller_name(), "heater");
+        assert_eq!(message.get_content(), "Command Acknowledged (1)");
+        assert_eq!(runtime.group.status()[0].get_setpoint(), Some(72.0));
+
+        let request = CommandRequest::new(None, Some("cooler"), Command::Release);
+        let message = runtime.handle_command(request);
+        assert_eq!(message.get_content(), "Command Rejected: unknown controller: cooler");
+
+        // reloading requires a factory
+        let request = CommandRequest::new(None, None::<String>, Command::Reload);
+        let message = runtime.handle_command(request);
+        assert_eq!(message.get_controller_name(), "runtime");
+        assert_eq!(message.get_content(), "Command Rejected: command is not supported by controller");
+    }
+
+    #[test]
+    fn test_reload() {
+        fn build_group() -> ControllerGroup {
+            let mut controller = Threshold::new(
+                70.0,
+                Input::new(|| "69.0".to_string()),
+                Output::default(),
+                Duration::minutes(5),
+            );
+            controller.set_name(String::from("heater"));
+            ControllerGroup::new().add_controller(controller)
         }
+
+        let mut runtime = Runtime::new(build_group(), Duration::seconds(1))
+            .set_reload(|| Ok(build_group()));
+
+        runtime.group.execute("heater", Command::SetSetpoint { value: 65.0 }, Utc::now()).unwrap();
+        let message = runtime.handle_command(CommandRequest::new(None, None::<String>, Command::Reload));
+        assert_eq!(message.get_content(), "Command Acknowledged");
+        // the setpoint is carried over to the new group
+        assert_eq!(runtime.group.status()[0].get_setpoint(), Some(65.0));
+
+        let mut runtime = runtime.set_reload(|| Err(String::from("invalid configuration")));
+        let message = runtime.handle_command(CommandRequest::new(None, None::<String>, Command::Reload));
+        assert_eq!(message.get_content(), "Command Rejected: invalid configuration");
+    }
+
+    #[tokio::test]
+    async fn test_subscriber() {
+        use std::convert::Infallible;
+        use std::net::SocketAddr;
+        use hyper::{Body, Response, Server};
+        use hyper::service::{make_service_fn, service_fn};
+
+        // the broker answers the first request with a command, and holds later requests open
+        let served = Arc::new(Mutex::new(false));
+        let make_service = make_service_fn(move |_| {
+            let served = served.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |_| {
+                    let served = served.clone();
+                    async move {
+                        let first = !std::mem::replace(&mut *served.lock().unwrap(), true);
+                        let body = match first {
+                            true => r#"[{"controller": "heater", "command": {"command": "set_setpoint", "value": 72.0}}]"#,
+                            false => {
+                                sleep(std::time::Duration::from_secs(1)).await;
+                                "[]"
+                            }
+                        };
+                        Ok::<_, Infallible>(Response::new(Body::from(body)))
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let mut controller = Threshold::new(
+            70.0,
+            Input::new(|| "69.0".to_string()),
+            Output::default(),
+            Duration::minutes(5),
+        );
+        controller.set_name(String::from("heater"));
+        let mut runtime = Runtime::new(
+            ControllerGroup::new().add_controller(controller),
+            Duration::seconds(1),
+        ).build_subscriber(format!("http://{}/commands", addr));
+
+        let token = runtime.shutdown_token();
+        tokio::spawn(async move {
+            sleep(std::time::Duration::from_millis(500)).await;
+            token.cancel();
+        });
+        tokio::time::timeout(std::time::Duration::from_secs(5), runtime.run())
+            .await
+            .expect("runtime did not shut down");
+
+        assert_eq!(runtime.group.status()[0].get_setpoint(), Some(72.0));
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_heartbeat() {
+        let mut controller = Threshold::new(
+            70.0,
+            Input::new(|| "69.0".to_string()),
+            Output::default(),
+            Duration::minutes(5),
+        );
+        controller.set_name(String::from("heater"));
+
+        // another node which listens for heartbeats
+        let localhost = SocketAddr::from(([127, 0, 0, 1], 0));
+        let peer = Discovery::new(0).set_bind_addr(localhost);
+        peer.start().await.unwrap();
+        let discovery = Discovery::new(0)
+            .set_bind_addr(localhost)
+            .set_targets(vec![peer.get_local_addr().unwrap()]);
+
+        let mut runtime = Runtime::new(
+            ControllerGroup::new().add_controller(controller),
+            Duration::seconds(1),
+        )
+            .set_identity(NodeIdentity::new("greenhouse-1").set_site("north-farm"))
+            .set_heartbeat(Duration::milliseconds(50))
+            .set_discovery(discovery);
+
+        let token = runtime.shutdown_token();
+        let listener = peer.clone();
+        tokio::spawn(async move {
+            while listener.get_peer("greenhouse-1").is_none() {
+                sleep(std::time::Duration::from_millis(10)).await;
+            }
+            token.cancel();
+        });
+        tokio::time::timeout(std::time::Duration::from_secs(5), runtime.run())
+            .await
+            .expect("runtime did not shut down");
+
+        let heartbeat = peer.get_peer("greenhouse-1").unwrap();
+        assert_eq!(heartbeat.get_node().get_site(), Some("north-farm"));
+        assert_eq!(heartbeat.get_controllers(), 1);
+        assert_eq!(heartbeat.get_health(), &Health::Healthy);
+
+        // failures degrade the health of the node
+        let heartbeat = runtime.heartbeat(Utc::now(), Duration::hours(1), 2, 1);
+        assert_eq!(heartbeat.get_controllers(), 3);
+        assert_eq!(
+            heartbeat.get_health().to_string(),
+            "Degraded (1 controllers quarantined)",
+        );
+    }
+
+    #[tokio::test]
+    async fn test_async_panic() {
+        use crate::controllers::PollFuture;
+
+        /// Holds the heater on when it panics
+        struct Faulty {
+            output: AsyncOutput,
+        }
+
+        fn fault() -> Option<Message> {
+            panic!("driver fault")
+        }
+
+        impl AsyncController for Faulty {
+            fn set_name(&mut self, _name: String) {}
+
+            fn get_name(&self) -> Option<String> {
+                Some(String::from("faulty"))
+            }
+
+            fn poll(&mut self, _time: DateTime<Utc>) -> PollFuture<'_> {
+                Box::pin(async { fault() })
+            }
+
+            fn safe_state_actions(&self) -> Vec<SafeStateAction> {
+                self.output.safe_state_action().into_iter().collect()
+            }
+        }
+
+        let heater = Arc::new(Mutex::new(true));
+        let state = heater.clone();
+        let output = AsyncOutput::from_blocking(move |value| *state.lock().unwrap() = value)
+            .set_safe_state(false);
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let quarantined = Arc::new(AtomicUsize::new(0));
+        let task = spawn_async(
+            Box::new(Faulty { output }),
+            Duration::milliseconds(10),
+            tx,
+            CancellationToken::new(),
+            quarantined.clone(),
+        );
+        tokio::time::timeout(std::time::Duration::from_secs(5), task)
+            .await
+            .expect("panic was not observed")
+            .unwrap();
+
+        // the output is driven to its safe state even though the controller is gone
+        let message = rx.recv().await.unwrap();
+        assert_eq!(message.get_controller_name(), "faulty");
+        assert_eq!(message.get_content(), "Safe State");
+        assert_eq!(message.get_output_state(), Some(false));
+        assert!(!*heater.lock().unwrap());
+
+        let message = rx.recv().await.unwrap();
+        assert_eq!(message.get_controller_name(), "faulty");
+        assert_eq!(message.get_content(), "Controller Quarantined: panicked: driver fault");
+        assert_eq!(quarantined.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failing_sink() {
+        use std::convert::Infallible;
+        use hyper::{Body, Response, Server};
+        use hyper::service::{make_service_fn, service_fn};
+
+        // the endpoint rejects every batch
+        let make_service = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|_| async {
+                Ok::<_, Infallible>(Response::builder().status(500).body(Body::empty()).unwrap())
+            }))
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let metrics = Metrics::new();
+        let mut runtime = Runtime::new(ControllerGroup::new(), Duration::seconds(1))
+            .add_route(Route::new("cloud", Emitter::new(format!("http://{}", addr)).set_retries(1)));
+        runtime.metrics = Some((SocketAddr::from(([127, 0, 0, 1], 0)), metrics.clone()));
+        let message = Message::new("heater", "Above Threshold", Utc::now(), None);
+
+        // the batch is retried by the route rather than by the runtime loop
+        let started = Instant::now();
+        runtime.process(vec![message.clone()]).await;
+        assert!(started.elapsed() < std::time::Duration::from_millis(200));
+        assert_eq!(runtime.heartbeat(Utc::now(), Duration::hours(1), 0, 0).get_health(), &Health::Healthy);
+
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while !runtime.routes[0].is_failing() {
+                sleep(std::time::Duration::from_millis(10)).await;
+            }
+        }).await.expect("route did not fail");
+        assert_eq!(
+            runtime.heartbeat(Utc::now(), Duration::hours(1), 0, 0).get_health().to_string(),
+            "Degraded (cloud failing)",
+        );
+
+        // the totals are reported with the next batch
+        runtime.process(vec![message]).await;
+        let rendered = metrics.render();
+        assert!(rendered.contains(r#"equilibrium_sink_failures_total{node="",sink="cloud"} 1"#));
+        assert!(rendered.contains(r#"equilibrium_sink_retries_total{node="",sink="cloud"} 1"#));
+    }
+
+    #[tokio::test]
+    async fn test_failover() {
+        use crate::failover::{Failover, Fence};
+        use crate::store::FileStateStore;
+        use crate::test_util::TempDir;
+
+        /// A runtime which drives a shared aerator through the fence
+        fn node(
+            id: &'static str,
+            failover: Failover,
+            discovery: &Discovery,
+            fence: &Fence,
+            writes: &Arc<Mutex<Vec<(&'static str, u64)>>>,
+            store: &TempDir,
+        ) -> Runtime {
+            let (fence, writes) = (fence.clone(), writes.clone());
+            let output = failover.output(move |_, epoch| {
+                if fence.admit(epoch) {
+                    writes.lock().unwrap().push((id, epoch));
+                }
+            });
+            let mut controller = Threshold::new(
+                5.0,
+                Input::new(|| "4.0".to_string()),
+                output,
+                Duration::milliseconds(20),
+            ).set_inverted();
+            controller.set_name(String::from("aerator"));
+
+            Runtime::new(ControllerGroup::new().add_controller(controller), Duration::milliseconds(20))
+                .set_identity(NodeIdentity::new(id))
+                .set_heartbeat(Duration::milliseconds(50))
+                .set_discovery(discovery.clone())
+                .set_failover(failover)
+                .set_state_store(FileStateStore::new(store).unwrap())
+        }
+
+        /// Run until the token is cancelled
+        async fn run(runtime: &mut Runtime) {
+            tokio::time::timeout(std::time::Duration::from_secs(5), runtime.run())
+                .await
+                .expect("runtime did not shut down");
+        }
+
+        /// Cancel the token after a delay
+        async fn stop_after(token: CancellationToken, millis: u64) {
+            sleep(std::time::Duration::from_millis(millis)).await;
+            token.cancel();
+        }
+
+        let localhost = SocketAddr::from(([127, 0, 0, 1], 0));
+        let (first, returning, second) = (
+            Discovery::new(0).set_bind_addr(localhost),
+            Discovery::new(0).set_bind_addr(localhost),
+            Discovery::new(0).set_bind_addr(localhost),
+        );
+        for discovery in [&first, &returning, &second] {
+            discovery.start().await.unwrap();
+        }
+        let first = first.set_targets(vec![second.get_local_addr().unwrap()]);
+        let returning = returning.set_targets(vec![second.get_local_addr().unwrap()]);
+        let second = second.set_targets(vec![first.get_local_addr().unwrap(), returning.get_local_addr().unwrap()]);
+
+        let fence = Fence::new();
+        let writes = Arc::new(Mutex::new(Vec::new()));
+        let (store_a, store_b) = (TempDir::new("failover-a"), TempDir::new("failover-b"));
+        let timeout = Duration::milliseconds(300);
+        let primary = Failover::new("b", timeout).set_primary();
+        let standby = Failover::new("a", timeout);
+        let mut a = node("a", primary.clone(), &first, &fence, &writes, &store_a);
+        let mut b = node("b", standby.clone(), &second, &fence, &writes, &store_b);
+
+        // both nodes wait for the timeout, then only the primary drives the aerator
+        let (token_a, token_b) = (a.shutdown_token(), b.shutdown_token());
+        tokio::join!(run(&mut a), stop_after(token_a, 700), async {
+            sleep(std::time::Duration::from_millis(150)).await;
+            assert!(!primary.is_active());
+            assert!(writes.lock().unwrap().is_empty());
+
+            sleep(std::time::Duration::from_millis(350)).await;
+            assert!(primary.is_active());
+            assert!(!standby.is_active());
+            assert!(!writes.lock().unwrap().is_empty());
+            assert!(writes.lock().unwrap().iter().all(|write| *write == ("a", 1)));
+        }, async {
+            // the standby takes over once the primary stops
+            run(&mut b).await;
+        }, async {
+            sleep(std::time::Duration::from_millis(1400)).await;
+            assert!(standby.is_active());
+            assert_eq!(standby.get_leadership().epoch, 2);
+            assert_eq!(writes.lock().unwrap().last(), Some(&("b", 2)));
+
+            // the former primary returns, and steps down instead of fighting the new leader
+            let returned = Failover::new("b", timeout).set_primary();
+            let mut a = node("a", returned.clone(), &returning, &fence, &writes, &store_a);
+            let token = a.shutdown_token();
+            tokio::join!(run(&mut a), stop_after(token, 500));
+            assert!(!returned.is_active());
+            assert!(standby.is_active());
+            assert_eq!(fence.get_epoch(), 2);
+            token_b.cancel();
+        });
+        assert_eq!(writes.lock().unwrap().last(), Some(&("b", 2)));
+
+        // both nodes restart, and the new leader continues from the persisted epoch, so that its
+        // writes are admitted by the fence
+        let (first, second) = (
+            Discovery::new(0).set_bind_addr(localhost),
+            Discovery::new(0).set_bind_addr(localhost),
+        );
+        first.start().await.unwrap();
+        second.start().await.unwrap();
+        let first = first.set_targets(vec![second.get_local_addr().unwrap()]);
+        let second = second.set_targets(vec![first.get_local_addr().unwrap()]);
+
+        let primary = Failover::new("b", timeout).set_primary();
+        let standby = Failover::new("a", timeout);
+        let mut a = node("a", primary.clone(), &first, &fence, &writes, &store_a);
+        let mut b = node("b", standby.clone(), &second, &fence, &writes, &store_b);
+        let (token_a, token_b) = (a.shutdown_token(), b.shutdown_token());
+        tokio::join!(run(&mut a), run(&mut b), stop_after(token_a, 700), stop_after(token_b, 700));
+        assert_eq!(primary.get_leadership().epoch, 3);
+        assert_eq!(fence.get_epoch(), 3);
+        assert_eq!(writes.lock().unwrap().last(), Some(&("a", 3)));
+    }
+}
@@ -26,6 +26,7 @@ impl Scheduler {
     /// Returns true if there are any future events
     ///
     /// This is used in testing.
+    #[allow(dead_code)]
     pub fn has_future_events(&self) -> bool {
         !self.future_events.is_empty()
     }
@@ -72,6 +73,21 @@ impl Scheduler {
         }
     }
 
+    /// Discard all future events
+    ///
+    /// This is used when a controller needs to rebuild its schedule from scratch, such as when
+    /// recovering from missed events.
+    pub fn clear_future_events(&mut self) {
+        self.future_events.clear();
+    }
+
+    /// Replace all future events with the given events
+    ///
+    /// This is used when restoring a controller from a snapshot.
+    pub fn restore(&mut self, events: Vec<Event>) {
+        self.future_events = events;
+    }
+
     /// Returns a reference of future events
     pub fn get_future_events(&self) -> &Vec<Event> {
         &self.future_events
@@ -80,6 +96,7 @@ impl Scheduler {
 
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::clone_on_copy)]
 mod tests {
     use chrono::TimeZone;
     use super::*;
@@ -0,0 +1,643 @@
+//! Inputs that are read once and shared by many controllers
+//!
+//! Normally each controller owns its [`Input`], so two controllers which watch the same probe read
+//! the device twice, and the two readings may disagree. A [`SensorRegistry`] owns the low-level
+//! read callback instead, and hands out any number of [`Input`]s which read through a shared cache.
+//!
+//! When the registry is attached to a [`ControllerGroup`](crate::ControllerGroup) with
+//! [`ControllerGroup::set_sensors`](crate::ControllerGroup::set_sensors), the group starts a new
+//! tick every time it is polled. A sensor is sampled at most once per tick, and the cached
+//! [`Reading`] is reused until it is older than the maximum age of the sensor. If the registry is
+//! not attached to a group, the current time is used instead and only the maximum age applies.
+//!
+//! Sensors may also be computed from other sensors with [`SensorRegistry::add_computed`], such as
+//! the difference between inlet and outlet temperatures or a unit conversion. Computed sensors
+//! are consumed by controllers like any other [`Input`]. A computed sensor fails when any of its
+//! sources fails, and the cause is passed on to the controllers instead of a number, so that they
+//! report a "Read Failed" message. Use [`SensorRegistry::async_input`] to receive the failure as a
+//! [`DriverError`].
+//!
+//! Redundant probes are combined with [`SensorRegistry::add_voted`]. A probe which diverges from
+//! the others by more than a tolerance is excluded, and the voted value is derived from the
+//! healthy probes. Every change of the excluded probes is reported as a [`Message`], which is
+//! returned by [`ControllerGroup::poll`](crate::ControllerGroup::poll) when the registry is
+//! attached.
+//!
+//! # Example
+//! ```
+//! use chrono::{Duration, Utc};
+//! use equilibrium::controllers::Threshold;
+//! use equilibrium::sensors::SensorRegistry;
+//! use equilibrium::{ControllerGroup, Output};
+//!
+//! let sensors = SensorRegistry::new()
+//!     .add_sensor("probe", || {
+//!         // low-level code would go here
+//!         String::from("71.0")
+//!     }, Duration::zero());
+//!
+//! let heater = Threshold::new(
+//!     70.0,
+//!     sensors.input("probe").unwrap(),
+//!     Output::default(),
+//!     Duration::minutes(5),
+//! ).set_inverted();
+//! let alarm = Threshold::new(
+//!     90.0,
+//!     sensors.input("probe").unwrap(),
+//!     Output::default(),
+//!     Duration::minutes(1),
+//! );
+//!
+//! let mut group = ControllerGroup::new()
+//!     .add_controller(heater)
+//!     .add_controller(alarm)
+//!     .set_sensors(sensors);
+//!
+//! group.poll(Utc::now());
+//! ```
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Duration, Utc};
+use crate::{AsyncInput, Input};
+use crate::types::{DriverError, Message};
+
+/// A cached value of a sensor and the time at which it was sampled
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+    value: String,
+    timestamp: DateTime<Utc>,
+}
+
+impl Reading {
+    pub fn get_value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn get_timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+}
+
+/// Strategy used to combine redundant sensors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Vote {
+    /// Probes which diverge from the median by more than the tolerance are excluded. The median
+    /// of the remaining probes is used.
+    Median,
+
+    /// A probe is healthy when it agrees within the tolerance with a majority of all probes. With
+    /// three probes, this is 2-out-of-3 selection. The mean of the healthy probes is used.
+    TwoOutOfThree,
+}
+
+fn median(values: &[f32]) -> Option<f32> {
+    let mut values = values.to_vec();
+    values.sort_by(f32::total_cmp);
+    match values.len() {
+        0 => None,
+        n if n % 2 == 0 => Some((values[n / 2 - 1] + values[n / 2]) / 2.0),
+        n => Some(values[n / 2]),
+    }
+}
+
+struct Voter {
+    name: String,
+    sources: Vec<(String, Arc<Mutex<Sensor>>)>,
+    vote: Vote,
+    tolerance: f32,
+    excluded: Vec<String>,
+    faults: Arc<Mutex<Vec<Message>>>,
+}
+
+impl Voter {
+    /// Combine the readings of the sources, reporting any change of the excluded sources
+    ///
+    /// Fails if no source is healthy. The failure is reported as a "Sensor Disagreement" along
+    /// with the excluded sources.
+    fn read(&mut self, time: DateTime<Utc>) -> Result<f32, String> {
+        // sources which fail are always excluded
+        let readings: Vec<Option<f32>> = self.sources.iter()
+            .map(|(_, source)| source.lock().unwrap().value(time).ok())
+            .collect();
+        let valid: Vec<f32> = readings.iter().flatten().copied().collect();
+
+        let healthy: Vec<bool> = match self.vote {
+            Vote::Median => {
+                let median = median(&valid);
+                readings.iter()
+                    .map(|v| v.zip(median).is_some_and(|(v, median)| (v - median).abs() <= self.tolerance))
+                    .collect()
+            }
+            Vote::TwoOutOfThree => readings.iter()
+                .map(|v| v.is_some_and(|v| {
+                    let agreeing = valid.iter().filter(|w| (v - **w).abs() <= self.tolerance).count();
+                    agreeing * 2 > readings.len()
+                }))
+                .collect(),
+        };
+
+        let values: Vec<f32> = readings.iter().zip(healthy.iter())
+            .filter_map(|(v, healthy)| v.filter(|_| *healthy))
+            .collect();
+        let value = match self.vote {
+            Vote::Median => median(&values),
+            Vote::TwoOutOfThree if values.is_empty() => None,
+            Vote::TwoOutOfThree => Some(values.iter().sum::<f32>() / values.len() as f32),
+        };
+
+        let excluded: Vec<String> = self.sources.iter().zip(healthy.iter()).zip(readings.iter())
+            .filter(|((_, healthy), _)| !**healthy)
+            .map(|(((name, _), _), reading)| match reading {
+                Some(reading) => format!("{} ({})", name, reading),
+                None => format!("{} (invalid)", name),
+            })
+            .collect();
+        let names: Vec<String> = self.sources.iter().zip(healthy.iter())
+            .filter(|(_, healthy)| !**healthy)
+            .map(|((name, _), _)| name.clone())
+            .collect();
+
+        if names != self.excluded {
+            let content = match names.is_empty() {
+                true => String::from("Sensor Agreement Restored"),
+                false => format!("Sensor Disagreement: excluded {}", excluded.join(", ")),
+            };
+            self.faults.lock().unwrap().push(Message::new(
+                self.name.clone(),
+                content,
+                time,
+                value.map(|value| value.to_string()),
+            ));
+            self.excluded = names;
+        }
+        value.ok_or_else(|| String::from("no healthy source"))
+    }
+}
+
+type Function = Box<dyn Fn(&[f32]) -> f32 + Send>;
+
+enum Source {
+    Device(Box<dyn Fn() -> String + Send>),
+    Computed(Vec<(String, Arc<Mutex<Sensor>>)>, Function),
+    Voted(Voter),
+}
+
+struct Sensor {
+    source: Source,
+    max_age: Duration,
+    cached: Option<Reading>,
+}
+
+impl Sensor {
+    /// Return the cached value if it is still fresh at `time`, otherwise sample the source
+    ///
+    /// Failures are not cached, so that the source is sampled again by the next read.
+    fn read(&mut self, time: DateTime<Utc>) -> Result<String, String> {
+        if let Some(reading) = &self.cached {
+            if time - reading.timestamp <= self.max_age {
+                return Ok(reading.value.clone());
+            }
+        }
+
+        let value = match &mut self.source {
+            Source::Device(callback) => callback(),
+            Source::Voted(voter) => voter.read(time)?.to_string(),
+            Source::Computed(sources, function) => {
+                let values = sources.iter()
+                    .map(|(name, source)| source.lock().unwrap().value(time)
+                        .map_err(|e| format!("source {} failed: {}", name, e)))
+                    .collect::<Result<Vec<f32>, String>>()?;
+                match function(&values) {
+                    value if value.is_finite() => value.to_string(),
+                    value => return Err(format!("computed {}", value)),
+                }
+            }
+        };
+        self.cached = Some(Reading { value: value.clone(), timestamp: time });
+        Ok(value)
+    }
+
+    /// Read the sensor and parse the reading as a finite `f32`
+    fn value(&mut self, time: DateTime<Utc>) -> Result<f32, String> {
+        let value = self.read(time)?;
+        match value.trim().parse::<f32>() {
+            Ok(value) if value.is_finite() => Ok(value),
+            _ => Err(format!("invalid reading {:?}", value)),
+        }
+    }
+}
+
+/// A registry of named sensors whose readings are shared between controllers
+///
+/// See the [module documentation](self) for details.
+#[derive(Default)]
+pub struct SensorRegistry {
+    sensors: HashMap<String, Arc<Mutex<Sensor>>>,
+    tick: Arc<Mutex<Option<DateTime<Utc>>>>,
+    faults: Arc<Mutex<Vec<Message>>>,
+}
+
+impl SensorRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builder method to register a sensor
+    ///
+    /// # Arguments
+    /// * `name` - Name used to retrieve inputs for the sensor
+    /// * `callback` - Low-level code that returns the reading as a `String`
+    /// * `max_age` - How long a reading may be reused across ticks. A zero duration samples the
+    ///   sensor on every tick.
+    pub fn add_sensor<S, F>(mut self, name: S, callback: F, max_age: Duration) -> Self
+        where
+            S: Into<String>,
+            F: Fn() -> String + Send + 'static,
+    {
+        let sensor = Sensor {
+            source: Source::Device(Box::new(callback)),
+            max_age,
+            cached: None,
+        };
+        self.sensors.insert(name.into(), Arc::new(Mutex::new(sensor)));
+        self
+    }
+
+    /// Builder method to register a sensor that is computed from other sensors
+    ///
+    /// The function receives the readings of `sources` in the given order, parsed as `f32`.
+    /// Since the sources are read through the shared cache, a computed sensor does not cause its
+    /// sources to be sampled again. The computed value is cached for the current tick.
+    ///
+    /// The function is not called if any source fails or returns a reading which is not a finite
+    /// number, and the computed sensor fails instead. It also fails if the function returns a
+    /// value which is not finite.
+    ///
+    /// # Panics
+    /// If any of the sources has not been registered.
+    ///
+    /// # Example
+    /// ```
+    /// use chrono::Duration;
+    /// use equilibrium::sensors::SensorRegistry;
+    ///
+    /// let sensors = SensorRegistry::new()
+    ///     .add_sensor("inlet", || String::from("24.5"), Duration::zero())
+    ///     .add_sensor("outlet", || String::from("21.0"), Duration::zero())
+    ///     .add_computed("delta", &["inlet", "outlet"], |t| t[0] - t[1])
+    ///     .add_computed("inlet_f", &["inlet"], |t| t[0] * 9.0 / 5.0 + 32.0);
+    ///
+    /// let mut delta = sensors.input("delta").unwrap();
+    /// assert_eq!(delta.read(), "3.5");
+    /// ```
+    pub fn add_computed<S, F>(mut self, name: S, sources: &[&str], function: F) -> Self
+        where
+            S: Into<String>,
+            F: Fn(&[f32]) -> f32 + Send + 'static,
+    {
+        let sources = sources.iter()
+            .map(|source| match self.sensors.get(*source) {
+                Some(sensor) => (source.to_string(), sensor.clone()),
+                None => panic!("unknown source sensor: {}", source),
+            })
+            .collect();
+        let sensor = Sensor {
+            source: Source::Computed(sources, Box::new(function)),
+            max_age: Duration::zero(),
+            cached: None,
+        };
+        self.sensors.insert(name.into(), Arc::new(Mutex::new(sensor)));
+        self
+    }
+
+    /// Builder method to register a sensor that votes between redundant sensors
+    ///
+    /// Sources which fail or cannot be parsed as `f32` are always excluded. If no source is
+    /// healthy, the voted sensor fails, and its controllers report a "Read Failed" message. A
+    /// "Sensor Disagreement" message is reported whenever the excluded sources change, and
+    /// "Sensor Agreement Restored" once every source is healthy again.
+    ///
+    /// # Panics
+    /// If any of the sources has not been registered.
+    ///
+    /// # Example
+    /// ```
+    /// use chrono::Duration;
+    /// use equilibrium::sensors::{SensorRegistry, Vote};
+    ///
+    /// let sensors = SensorRegistry::new()
+    ///     .add_sensor("ph_a", || String::from("7.02"), Duration::zero())
+    ///     .add_sensor("ph_b", || String::from("6.98"), Duration::zero())
+    ///     .add_sensor("ph_c", || String::from("8.40"), Duration::zero())
+    ///     .add_voted("ph", &["ph_a", "ph_b", "ph_c"], Vote::TwoOutOfThree, 0.1);
+    ///
+    /// // ph_c has drifted and is excluded
+    /// let mut ph = sensors.input("ph").unwrap();
+    /// assert_eq!(ph.read(), "7");
+    /// ```
+    pub fn add_voted<S>(mut self, name: S, sources: &[&str], vote: Vote, tolerance: f32) -> Self
+        where S: Into<String>
+    {
+        let name = name.into();
+        let sources = sources.iter()
+            .map(|source| match self.sensors.get(*source) {
+                Some(sensor) => (source.to_string(), sensor.clone()),
+                None => panic!("unknown source sensor: {}", source),
+            })
+            .collect();
+        let voter = Voter {
+            name: name.clone(),
+            sources,
+            vote,
+            tolerance,
+            excluded: Vec::new(),
+            faults: self.faults.clone(),
+        };
+        let sensor = Sensor {
+            source: Source::Voted(voter),
+            max_age: Duration::zero(),
+            cached: None,
+        };
+        self.sensors.insert(name, Arc::new(Mutex::new(sensor)));
+        self
+    }
+
+    /// Create an [`Input`] which reads the named sensor through the shared cache
+    ///
+    /// If the sensor fails, the cause is logged and returned in place of a reading, such as
+    /// `"delta: source inlet failed: invalid reading \"error\""`, which the controllers report as
+    /// a "Read Failed" message.
+    ///
+    /// Returns `None` if no sensor has been registered with the given name.
+    pub fn input(&self, name: &str) -> Option<Input<impl Fn() -> String + Send + Sync>> {
+        let read = self.reader(name)?;
+        let name = name.to_string();
+        Some(Input::new(move || read().unwrap_or_else(|e| {
+            eprintln!("Failed to read sensor {}: {}", name, e);
+            format!("{}: {}", name, e)
+        })))
+    }
+
+    /// Create an [`AsyncInput`] which reads the named sensor through the shared cache
+    ///
+    /// The sensor is read on a blocking task, and failures of the sensor are returned as
+    /// [`DriverError::Failed`].
+    ///
+    /// Returns `None` if no sensor has been registered with the given name.
+    pub fn async_input(&self, name: &str) -> Option<AsyncInput> {
+        let read = Arc::new(self.reader(name)?);
+        Some(AsyncInput::new(move || {
+            let read = read.clone();
+            async move {
+                tokio::task::spawn_blocking(move || read())
+                    .await
+                    .map_err(|e| DriverError::Failed(e.to_string()))?
+                    .map_err(DriverError::Failed)
+            }
+        }))
+    }
+
+    /// Create a callback which reads the named sensor at the current tick
+    fn reader(&self, name: &str) -> Option<impl Fn() -> Result<String, String> + Send + Sync> {
+        let sensor = self.sensors.get(name)?.clone();
+        let tick = self.tick.clone();
+        Some(move || {
+            let time = tick.lock().unwrap().unwrap_or_else(Utc::now);
+            sensor.lock().unwrap().read(time)
+        })
+    }
+
+    /// Begin a new tick
+    ///
+    /// Every sensor is sampled at most once for a given tick. This is called by
+    /// [`ControllerGroup::poll`](crate::ControllerGroup::poll) when the registry is attached.
+    pub fn tick(&self, time: DateTime<Utc>) {
+        *self.tick.lock().unwrap() = Some(time);
+    }
+
+    /// Return every fault that has been reported since the last call
+    pub fn drain_messages(&self) -> Vec<Message> {
+        self.faults.lock().unwrap().drain(..).collect()
+    }
+
+    /// Get the last cached reading of the named sensor
+    pub fn get_reading(&self, name: &str) -> Option<Reading> {
+        self.sensors.get(name)?.lock().unwrap().cached.clone()
+    }
+}
+
+impl std::fmt::Debug for SensorRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut names: Vec<_> = self.sensors.keys().collect();
+        names.sort();
+        f.debug_struct("SensorRegistry")
+            .field("sensors", &names)
+            .field("tick", &self.tick.lock().unwrap())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use super::*;
+
+    /// Build a registry with a sensor that counts how often it has been sampled
+    fn counting_registry(max_age: Duration) -> (SensorRegistry, Arc<Mutex<u32>>) {
+        let count = Arc::new(Mutex::new(0));
+        let samples = count.clone();
+        let registry = SensorRegistry::new()
+            .add_sensor("probe", move || {
+                let mut samples = samples.lock().unwrap();
+                *samples += 1;
+                samples.to_string()
+            }, max_age);
+        (registry, count)
+    }
+
+    #[test]
+    fn test_sampled_once_per_tick() {
+        let (registry, count) = counting_registry(Duration::zero());
+        let mut first = registry.input("probe").unwrap();
+        let mut second = registry.input("probe").unwrap();
+
+        let time = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        registry.tick(time);
+        assert_eq!(first.read(), "1");
+        assert_eq!(second.read(), "1");
+        assert_eq!(*count.lock().unwrap(), 1);
+
+        registry.tick(time + Duration::seconds(1));
+        assert_eq!(second.read(), "2");
+        assert_eq!(first.read(), "2");
+        assert_eq!(*count.lock().unwrap(), 2);
+
+        let reading = registry.get_reading("probe").unwrap();
+        assert_eq!(reading.get_value(), "2");
+        assert_eq!(reading.get_timestamp(), time + Duration::seconds(1));
+    }
+
+    #[test]
+    fn test_max_age() {
+        let (registry, count) = counting_registry(Duration::seconds(5));
+        let mut input = registry.input("probe").unwrap();
+
+        let time = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        registry.tick(time);
+        assert_eq!(input.read(), "1");
+
+        // the cached reading is reused while it is fresh
+        registry.tick(time + Duration::seconds(5));
+        assert_eq!(input.read(), "1");
+
+        registry.tick(time + Duration::seconds(6));
+        assert_eq!(input.read(), "2");
+        assert_eq!(*count.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_computed() {
+        let (registry, count) = counting_registry(Duration::zero());
+        let registry = registry
+            .add_sensor("humidity", || String::from("50"), Duration::zero())
+            .add_sensor("broken", || String::from("error"), Duration::zero())
+            .add_computed("average", &["probe", "humidity"], |v| v.iter().sum::<f32>() / v.len() as f32)
+            .add_computed("invalid", &["broken"], |v| v[0]);
+        let mut probe = registry.input("probe").unwrap();
+        let mut average = registry.input("average").unwrap();
+
+        let time = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        registry.tick(time);
+        assert_eq!(average.read(), "25.5");
+        assert_eq!(probe.read(), "1");
+        assert_eq!(*count.lock().unwrap(), 1);
+
+        registry.tick(time + Duration::seconds(1));
+        assert_eq!(average.read(), "26");
+        assert_eq!(registry.get_reading("average").unwrap().get_value(), "26");
+
+        assert_eq!(
+            registry.input("invalid").unwrap().read(),
+            "invalid: source broken failed: invalid reading \"error\"",
+        );
+    }
+
+    #[tokio::test]
+    async fn test_computed_failed() {
+        let registry = SensorRegistry::new()
+            .add_sensor("inlet", || f32::NAN.to_string(), Duration::zero())
+            .add_sensor("outlet", || String::from("21.0"), Duration::zero())
+            .add_computed("hottest", &["inlet", "outlet"], |t| t[0].max(t[1]))
+            .add_computed("ratio", &["outlet", "outlet"], |t| t[0] / (t[1] - t[1]));
+
+        // the failed source is not hidden by the function
+        let mut hottest = registry.async_input("hottest").unwrap();
+        assert_eq!(
+            hottest.read().await,
+            Err(DriverError::Failed(String::from("source inlet failed: invalid reading \"NaN\""))),
+        );
+        assert!(registry.get_reading("hottest").is_none());
+
+        let mut ratio = registry.async_input("ratio").unwrap();
+        assert_eq!(ratio.read().await, Err(DriverError::Failed(String::from("computed inf"))));
+        assert!(registry.async_input("missing").is_none());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_computed_unknown_source() {
+        let _ = SensorRegistry::new()
+            .add_computed("delta", &["inlet", "outlet"], |t| t[0] - t[1]);
+    }
+
+    /// Build a registry with three pH probes whose readings are set by the test
+    fn probe_registry(vote: Vote) -> (SensorRegistry, Arc<Mutex<[&'static str; 3]>>) {
+        let readings = Arc::new(Mutex::new(["7.0", "7.0", "7.0"]));
+        let mut registry = SensorRegistry::new();
+        for (index, name) in ["a", "b", "c"].into_iter().enumerate() {
+            let readings = readings.clone();
+            registry = registry.add_sensor(name, move || {
+                readings.lock().unwrap()[index].to_string()
+            }, Duration::zero());
+        }
+        (registry.add_voted("ph", &["a", "b", "c"], vote, 0.2), readings)
+    }
+
+    #[test]
+    fn test_voted_median() {
+        let (registry, readings) = probe_registry(Vote::Median);
+        let mut ph = registry.input("ph").unwrap();
+        let time = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+
+        *readings.lock().unwrap() = ["7.0", "7.2", "7.1"];
+        registry.tick(time);
+        assert_eq!(ph.read(), "7.1");
+        assert!(registry.drain_messages().is_empty());
+
+        *readings.lock().unwrap() = ["7.0", "9.5", "7.1"];
+        registry.tick(time + Duration::seconds(1));
+        assert_eq!(ph.read(), "7.05");
+
+        let messages = registry.drain_messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].get_controller_name(), "ph");
+        assert_eq!(messages[0].get_content(), "Sensor Disagreement: excluded b (9.5)");
+
+        // the fault is only reported when it changes
+        registry.tick(time + Duration::seconds(2));
+        ph.read();
+        assert!(registry.drain_messages().is_empty());
+
+        *readings.lock().unwrap() = ["7.0", "7.0", "7.0"];
+        registry.tick(time + Duration::seconds(3));
+        assert_eq!(ph.read(), "7");
+        assert_eq!(registry.drain_messages()[0].get_content(), "Sensor Agreement Restored");
+    }
+
+    #[test]
+    fn test_voted_two_out_of_three() {
+        let (registry, readings) = probe_registry(Vote::TwoOutOfThree);
+        let mut ph = registry.input("ph").unwrap();
+        let time = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+
+        *readings.lock().unwrap() = ["7.0", "error", "7.2"];
+        registry.tick(time);
+        assert_eq!(ph.read(), "7.1");
+        assert_eq!(registry.drain_messages()[0].get_content(), "Sensor Disagreement: excluded b (invalid)");
+
+        // no two probes agree
+        *readings.lock().unwrap() = ["7.0", "8.0", "9.0"];
+        registry.tick(time + Duration::seconds(1));
+        assert_eq!(ph.read(), "ph: no healthy source");
+        let messages = registry.drain_messages();
+        assert_eq!(messages[0].get_content(), "Sensor Disagreement: excluded a (7), b (8), c (9)");
+        assert_eq!(messages[0].get_read_state(), None);
+    }
+
+    #[tokio::test]
+    async fn test_voted_no_healthy_source() {
+        let (registry, readings) = probe_registry(Vote::Median);
+        let mut ph = registry.async_input("ph").unwrap();
+
+        *readings.lock().unwrap() = ["error", "NaN", ""];
+        assert_eq!(ph.read().await, Err(DriverError::Failed(String::from("no healthy source"))));
+        assert_eq!(
+            registry.drain_messages()[0].get_content(),
+            "Sensor Disagreement: excluded a (invalid), b (invalid), c (invalid)",
+        );
+
+        // the vote recovers once the probes agree again
+        *readings.lock().unwrap() = ["7.0", "7.1", "7.2"];
+        assert_eq!(ph.read().await, Ok(String::from("7.1")));
+    }
+
+    #[test]
+    fn test_unknown_sensor() {
+        let (registry, _) = counting_registry(Duration::zero());
+
+        assert!(registry.input("missing").is_none());
+        assert!(registry.get_reading("probe").is_none());
+    }
+}
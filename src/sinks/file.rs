@@ -0,0 +1,464 @@
+//! Local log files of every message
+//!
+//! A [`FileSink`] appends every [`Message`] to a log file as JSON Lines or CSV, so that a record
+//! is kept on sites without a broker. It may be attached to a [`Runtime`](crate::Runtime) on its
+//! own, or alongside an [`Emitter`](crate::Emitter).
+//!
+//! Messages are written to `{prefix}.jsonl` (or `{prefix}.csv`) in the log directory. The file is
+//! rotated when the first message of a new day is written, and optionally once it would exceed a
+//! maximum size. Rotated files are renamed to `{prefix}-{date}-{sequence}.jsonl`, where the date is
+//! the day that the file was started, and are optionally gzip-compressed. Once there are more
+//! rotated files than the retention count, the oldest are deleted.
+//!
+//! When the sink is used through [`Sink::emit`], the compression and deletion of rotated files run
+//! on a blocking task, so that a large file does not stall the tokio runtime.
+//!
+//! # Example
+//! ```
+//! use chrono::{Duration, Utc};
+//! use equilibrium::sinks::file::{FileSink, Format};
+//! use equilibrium::types::Message;
+//!
+//! let dir = std::env::temp_dir().join("equilibrium-doc-file-sink");
+//! let mut log = FileSink::open(&dir).unwrap()
+//!     .set_format(Format::Csv)
+//!     .set_max_size(10 * 1024 * 1024)
+//!     .set_compression()
+//!     .set_retention(30);
+//!
+//! log.write(&[
+//!     Message::new("heater", "Below Threshold", Utc::now(), "68.5".to_string()),
+//! ]).unwrap();
+//! # std::fs::remove_dir_all(dir).unwrap();
+//! ```
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, NaiveDate, Utc};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use crate::sinks::{EmitFuture, Sink};
+use crate::types::Message;
+
+const CSV_HEADER: &str = "timestamp,node,controller,content,reading,output\n";
+
+/// How each message is encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// One JSON encoded [`Message`] per line
+    #[default]
+    JsonLines,
+
+    /// One row per message, with the columns `timestamp,node,controller,content,reading,output`
+    Csv,
+}
+
+impl Format {
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::JsonLines => "jsonl",
+            Format::Csv => "csv",
+        }
+    }
+
+    fn header(&self) -> &'static str {
+        match self {
+            Format::JsonLines => "",
+            Format::Csv => CSV_HEADER,
+        }
+    }
+
+    /// Encode a message as a single line, including the newline
+    fn encode(&self, message: &Message) -> io::Result<String> {
+        match self {
+            Format::JsonLines => Ok(serde_json::to_string(message)? + "\n"),
+            Format::Csv => {
+                let columns = [
+                    message.get_timestamp().to_rfc3339(),
+                    message.get_node().map(|node| node.get_id().to_string()).unwrap_or_default(),
+                    message.get_controller_name(),
+                    message.get_content(),
+                    message.get_read_state().unwrap_or_default(),
+                    message.get_output_state().map(|state| state.to_string()).unwrap_or_default(),
+                ];
+                let columns: Vec<String> = columns.iter().map(|column| csv_escape(column)).collect();
+                Ok(columns.join(",") + "\n")
+            }
+        }
+    }
+}
+
+/// The file that is currently written to
+#[derive(Debug)]
+struct Current {
+    file: File,
+    date: NaiveDate,
+    size: u64,
+}
+
+/// A file which has been rotated, but is yet to be compressed and pruned
+#[derive(Debug)]
+struct Rotated {
+    path: PathBuf,
+    directory: PathBuf,
+    prefix: String,
+    extension: &'static str,
+    compress: bool,
+    retention: Option<usize>,
+}
+
+impl Rotated {
+    /// Compress the rotated file, and delete the oldest rotated files
+    fn finish(self) -> io::Result<()> {
+        if self.compress {
+            let mut encoder = GzEncoder::new(
+                File::create(self.path.with_extension(format!("{}.gz", self.extension)))?,
+                Compression::default(),
+            );
+            encoder.write_all(&fs::read(&self.path)?)?;
+            encoder.finish()?;
+            fs::remove_file(&self.path)?;
+        }
+
+        if let Some(retention) = self.retention {
+            let files = rotated_files(&self.directory, &self.prefix, self.extension)?;
+            for path in files.iter().take(files.len().saturating_sub(retention)) {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns every rotated file in `directory`, oldest first
+fn rotated_files(directory: &Path, prefix: &str, extension: &str) -> io::Result<Vec<PathBuf>> {
+    let start = format!("{}-", prefix);
+    let extension = format!(".{}", extension);
+    let mut files = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name,
+            None => continue,
+        };
+        let name = name.strip_suffix(".gz").unwrap_or(name);
+        if name.starts_with(&start) && name.ends_with(&extension) {
+            files.push(path);
+        }
+    }
+    // dates and sequence numbers are zero-padded, so names sort chronologically
+    files.sort();
+    Ok(files)
+}
+
+/// Appends messages to rotating log files
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug)]
+pub struct FileSink {
+    directory: PathBuf,
+    prefix: String,
+    format: Format,
+    max_size: Option<u64>,
+    compress: bool,
+    retention: Option<usize>,
+    current: Option<Current>,
+    rotated: Vec<Rotated>,
+}
+
+impl FileSink {
+    /// Open a log directory, creating it if it does not exist
+    ///
+    /// By default, messages are written as JSON Lines to `messages.jsonl`, files are rotated
+    /// daily, and rotated files are kept forever without compression.
+    pub fn open<P>(directory: P) -> io::Result<Self>
+        where P: AsRef<Path>
+    {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory)?;
+        Ok(Self {
+            directory,
+            prefix: String::from("messages"),
+            format: Format::default(),
+            max_size: None,
+            compress: false,
+            retention: None,
+            current: None,
+            rotated: Vec::new(),
+        })
+    }
+
+    /// Builder method to set the name of the log files
+    pub fn set_prefix<S>(mut self, prefix: S) -> Self
+        where S: Into<String>
+    {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Builder method to set the format of the log files
+    pub fn set_format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Builder method to rotate a file before it would exceed `bytes`
+    ///
+    /// A message is always written to an empty file, even if it is larger.
+    pub fn set_max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Builder method to gzip-compress rotated files
+    pub fn set_compression(mut self) -> Self {
+        self.compress = true;
+        self
+    }
+
+    /// Builder method to keep at most `count` rotated files
+    pub fn set_retention(mut self, count: usize) -> Self {
+        self.retention = Some(count);
+        self
+    }
+
+    fn active_path(&self) -> PathBuf {
+        self.directory.join(format!("{}.{}", self.prefix, self.format.extension()))
+    }
+
+    /// Returns every rotated file, oldest first
+    pub fn rotated_files(&self) -> io::Result<Vec<PathBuf>> {
+        rotated_files(&self.directory, &self.prefix, self.format.extension())
+    }
+
+    /// Open the active file, writing the header if it is new
+    fn open_current(&self, date: NaiveDate) -> io::Result<Current> {
+        let path = self.active_path();
+        let existing = fs::metadata(&path).ok().filter(|metadata| metadata.len() > 0);
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+
+        match existing {
+            Some(metadata) => {
+                let modified = metadata.modified().map(|time| DateTime::<Utc>::from(time).date_naive());
+                Ok(Current { file, date: modified.unwrap_or(date), size: metadata.len() })
+            }
+            None => {
+                let header = self.format.header();
+                file.write_all(header.as_bytes())?;
+                Ok(Current { file, date, size: header.len() as u64 })
+            }
+        }
+    }
+
+    /// Close and rename the active file, leaving its compression and pruning for later
+    fn rotate(&mut self) -> io::Result<()> {
+        let current = match self.current.take() {
+            Some(current) => current,
+            None => return Ok(()),
+        };
+        drop(current.file);
+
+        let extension = self.format.extension();
+        let mut sequence = 1;
+        let rotated = loop {
+            let path = self.directory.join(format!(
+                "{}-{}-{:03}.{}",
+                self.prefix,
+                current.date.format("%Y-%m-%d"),
+                sequence,
+                extension,
+            ));
+            if !path.exists() && !path.with_extension(format!("{}.gz", extension)).exists() {
+                break path;
+            }
+            sequence += 1;
+        };
+        fs::rename(self.active_path(), &rotated)?;
+
+        self.rotated.push(Rotated {
+            path: rotated,
+            directory: self.directory.clone(),
+            prefix: self.prefix.clone(),
+            extension,
+            compress: self.compress,
+            retention: self.retention,
+        });
+        Ok(())
+    }
+
+    /// Append messages, rotating, compressing and pruning files when necessary
+    pub fn write(&mut self, messages: &[Message]) -> io::Result<()> {
+        self.append(messages)?;
+        for rotated in std::mem::take(&mut self.rotated) {
+            rotated.finish()?;
+        }
+        Ok(())
+    }
+
+    /// Append messages, rotating the file when necessary
+    fn append(&mut self, messages: &[Message]) -> io::Result<()> {
+        let header = self.format.header().len() as u64;
+        for message in messages {
+            let line = self.format.encode(message)?;
+            let date = message.get_timestamp().date_naive();
+
+            if let Some(current) = &self.current {
+                let new_day = date > current.date;
+                let full = self.max_size.is_some_and(|max| {
+                    current.size > header && current.size + line.len() as u64 > max
+                });
+                if new_day || full {
+                    self.rotate()?;
+                }
+            }
+            if self.current.is_none() {
+                self.current = Some(self.open_current(date)?);
+            }
+
+            let current = self.current.as_mut().unwrap();
+            current.file.write_all(line.as_bytes())?;
+            current.size += line.len() as u64;
+        }
+        Ok(())
+    }
+}
+
+impl Sink for FileSink {
+    fn emit<'a>(&'a mut self, messages: &'a [Message]) -> EmitFuture<'a> {
+        Box::pin(async move {
+            self.append(messages)?;
+            let rotated = std::mem::take(&mut self.rotated);
+            if !rotated.is_empty() {
+                tokio::task::spawn_blocking(move || rotated.into_iter().try_for_each(Rotated::finish))
+                    .await
+                    .map_err(io::Error::other)??;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Quote a column if it contains a comma, quote or newline
+fn csv_escape(value: &str) -> String {
+    match value.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::TempDir;
+    use std::io::Read;
+    use chrono::{Duration, TimeZone};
+    use flate2::read::GzDecoder;
+    use crate::types::NodeIdentity;
+    use super::*;
+
+    fn time() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let dir = TempDir::new("file-sink-jsonl");
+        let mut sink = FileSink::open(&dir).unwrap();
+        let messages = vec![
+            Message::new("heater", "Below Threshold", time(), String::from("68.5")).set_output_state(true),
+            Message::new("heater", "Mode Changed: Auto -> Disabled", time(), None),
+        ];
+        sink.write(&messages).unwrap();
+
+        let contents = fs::read_to_string(dir.join("messages.jsonl")).unwrap();
+        let decoded: Vec<Message> = contents.lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(decoded, messages);
+    }
+
+    #[test]
+    fn test_csv() {
+        let dir = TempDir::new("file-sink-csv");
+        let mut sink = FileSink::open(&dir).unwrap().set_format(Format::Csv);
+        sink.write(&[
+            Message::new("heater", "Below Threshold", time(), String::from("68.5"))
+                .set_output_state(true)
+                .set_node(NodeIdentity::new("greenhouse-1")),
+            Message::new("probe", "Read Failed: \"bus\", retrying", time(), None),
+        ]).unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("messages.csv")).unwrap(), concat!(
+            "timestamp,node,controller,content,reading,output\n",
+            "2024-03-01T12:00:00+00:00,greenhouse-1,heater,Below Threshold,68.5,true\n",
+            "2024-03-01T12:00:00+00:00,,probe,\"Read Failed: \"\"bus\"\", retrying\",,\n",
+        ));
+    }
+
+    #[test]
+    fn test_daily_rotation() {
+        let dir = TempDir::new("file-sink-daily");
+        let mut sink = FileSink::open(&dir).unwrap();
+        sink.write(&[Message::new("heater", "Below Threshold", time(), None)]).unwrap();
+        sink.write(&[Message::new("heater", "Above Threshold", time() + Duration::hours(1), None)]).unwrap();
+        assert!(sink.rotated_files().unwrap().is_empty());
+
+        sink.write(&[Message::new("heater", "Below Threshold", time() + Duration::days(1), None)]).unwrap();
+        let rotated = sink.rotated_files().unwrap();
+        assert_eq!(rotated, vec![dir.join("messages-2024-03-01-001.jsonl")]);
+        assert_eq!(fs::read_to_string(&rotated[0]).unwrap().lines().count(), 2);
+        assert_eq!(fs::read_to_string(dir.join("messages.jsonl")).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn test_size_rotation() {
+        let dir = TempDir::new("file-sink-size");
+        let message = Message::new("heater", "Below Threshold", time(), String::from("68.5"));
+        let length = Format::JsonLines.encode(&message).unwrap().len() as u64;
+
+        // two messages fit in each file
+        let mut sink = FileSink::open(&dir).unwrap()
+            .set_max_size(length * 2)
+            .set_compression()
+            .set_retention(2);
+        for _ in 0..7 {
+            sink.write(std::slice::from_ref(&message)).unwrap();
+        }
+
+        // three files were rotated, of which the oldest was deleted
+        let rotated = sink.rotated_files().unwrap();
+        assert_eq!(rotated, vec![
+            dir.join("messages-2024-03-01-002.jsonl.gz"),
+            dir.join("messages-2024-03-01-003.jsonl.gz"),
+        ]);
+        let mut contents = String::new();
+        GzDecoder::new(File::open(&rotated[0]).unwrap()).read_to_string(&mut contents).unwrap();
+        assert_eq!(contents.lines().count(), 2);
+        assert_eq!(fs::read_to_string(dir.join("messages.jsonl")).unwrap().lines().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sink() {
+        let dir = TempDir::new("file-sink-emit");
+        let mut sink = FileSink::open(&dir).unwrap().set_prefix("node");
+        sink.emit(&[Message::new("heater", "Below Threshold", time(), None)]).await.unwrap();
+        sink.flush().await.unwrap();
+        assert!(dir.join("node.jsonl").exists());
+    }
+
+    #[tokio::test]
+    async fn test_sink_rotation() {
+        let dir = TempDir::new("file-sink-emit-rotation");
+        let message = Message::new("heater", "Below Threshold", time(), String::from("68.5"));
+        let length = Format::JsonLines.encode(&message).unwrap().len() as u64;
+
+        // rotated files are compressed on a blocking task before the emit completes
+        let mut sink = FileSink::open(&dir).unwrap()
+            .set_max_size(length)
+            .set_compression()
+            .set_retention(1);
+        for _ in 0..3 {
+            sink.emit(std::slice::from_ref(&message)).await.unwrap();
+        }
+        assert_eq!(sink.rotated_files().unwrap(), vec![dir.join("messages-2024-03-01-002.jsonl.gz")]);
+    }
+}
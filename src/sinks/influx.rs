@@ -0,0 +1,496 @@
+//! InfluxDB line protocol
+//!
+//! An [`InfluxSink`] encodes every [`Message`] as a single line of the
+//! [InfluxDB line protocol](https://docs.influxdata.com/influxdb/v2/reference/syntax/line-protocol/),
+//! and writes the lines to a file, a UDP socket or an HTTP write endpoint. This is understood by
+//! InfluxDB, Telegraf, VictoriaMetrics and QuestDB among others.
+//!
+//! Each line is tagged with the controller name and, if the message has a
+//! [`NodeIdentity`](crate::types::NodeIdentity), with the node ID, hostname, site and tags of the
+//! node. The message content is written as the `content` field, a numeric reading as the
+//! `reading` field, a non-numeric reading as the `raw_reading` field, and the output state as the
+//! `output` field. Lines are kept in a bounded buffer until they have been written successfully,
+//! so a failed write is retried with the next batch. For example:
+//!
+//! ```text
+//! equilibrium,controller=heater,host=pi,node=greenhouse-1 content="Below Threshold",reading=68.5,output=true 1700000000000000000
+//! ```
+//!
+//! # Example
+//! ```
+//! use chrono::Duration;
+//! use equilibrium::{ControllerGroup, Runtime};
+//! use equilibrium::sinks::influx::{InfluxSink, Precision};
+//!
+//! let influx = InfluxSink::http("http://localhost:8086/api/v2/write?org=farm&bucket=sensors")
+//!     .set_token("my-token")
+//!     .set_precision(Precision::Seconds)
+//!     .set_batch_size(100);
+//!
+//! let runtime = Runtime::new(ControllerGroup::new(), Duration::seconds(1))
+//!     .add_sink(influx);
+//! ```
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use chrono::{DateTime, Utc};
+use reqwest::Client;
+use tokio::net::UdpSocket;
+use crate::sinks::{EmitFuture, Sink, SinkError};
+use crate::types::Message;
+
+/// Largest payload that is sent in a single UDP datagram
+const MAX_DATAGRAM: usize = 1400;
+
+/// Resolution of the timestamp of each line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Precision {
+    #[default]
+    Nanoseconds,
+    Microseconds,
+    Milliseconds,
+    Seconds,
+}
+
+impl Precision {
+    /// The value of the `precision` query parameter of the HTTP API
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Precision::Nanoseconds => "ns",
+            Precision::Microseconds => "us",
+            Precision::Milliseconds => "ms",
+            Precision::Seconds => "s",
+        }
+    }
+
+    fn timestamp(&self, time: DateTime<Utc>) -> i64 {
+        match self {
+            Precision::Nanoseconds => time.timestamp_nanos_opt().unwrap_or(i64::MAX),
+            Precision::Microseconds => time.timestamp_micros(),
+            Precision::Milliseconds => time.timestamp_millis(),
+            Precision::Seconds => time.timestamp(),
+        }
+    }
+}
+
+/// Where the lines are written to
+#[derive(Debug, Clone)]
+enum Destination {
+    File(PathBuf),
+    Udp(SocketAddr),
+    Http { client: Client, url: String, token: Option<String> },
+}
+
+/// Writes messages as InfluxDB line protocol
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug)]
+pub struct InfluxSink {
+    destination: Destination,
+    measurement: String,
+    precision: Precision,
+    batch_size: usize,
+    max_buffer: usize,
+    buffer: Vec<String>,
+}
+
+impl InfluxSink {
+    fn new(destination: Destination) -> Self {
+        Self {
+            destination,
+            measurement: String::from("equilibrium"),
+            precision: Precision::default(),
+            batch_size: 1,
+            max_buffer: 10_000,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Create a sink which appends lines to a file
+    pub fn file<P>(path: P) -> Self
+        where P: Into<PathBuf>
+    {
+        Self::new(Destination::File(path.into()))
+    }
+
+    /// Create a sink which sends lines to a UDP listener, such as Telegraf
+    pub fn udp<A>(addr: A) -> Self
+        where A: Into<SocketAddr>
+    {
+        Self::new(Destination::Udp(addr.into()))
+    }
+
+    /// Create a sink which posts lines to an HTTP write endpoint
+    ///
+    /// The url should include the database or bucket, for example
+    /// `http://localhost:8086/api/v2/write?org=farm&bucket=sensors`. The precision is appended
+    /// as a query parameter.
+    pub fn http<S>(url: S) -> Self
+        where S: Into<String>
+    {
+        Self::new(Destination::Http { client: Client::new(), url: url.into(), token: None })
+    }
+
+    /// Builder method to authenticate HTTP writes with an API token
+    pub fn set_token<S>(mut self, token: S) -> Self
+        where S: Into<String>
+    {
+        if let Destination::Http { token: existing, .. } = &mut self.destination {
+            *existing = Some(token.into());
+        }
+        self
+    }
+
+    /// Builder method to set the measurement name, which defaults to "equilibrium"
+    pub fn set_measurement<S>(mut self, measurement: S) -> Self
+        where S: Into<String>
+    {
+        self.measurement = measurement.into();
+        self
+    }
+
+    /// Builder method to set the precision of timestamps, which defaults to nanoseconds
+    pub fn set_precision(mut self, precision: Precision) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Builder method to buffer lines until at least `batch_size` are pending
+    ///
+    /// Defaults to 1, so that every batch of messages is written immediately. Buffered lines are
+    /// written when the sink is flushed.
+    pub fn set_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Builder method to limit how many lines are kept while writes fail
+    ///
+    /// Once the limit is reached, the oldest lines are dropped. Defaults to 10,000 lines.
+    pub fn set_max_buffer(mut self, max_buffer: usize) -> Self {
+        self.max_buffer = max_buffer.max(1);
+        self
+    }
+
+    /// Encode a message as a single line, without a trailing newline
+    pub fn encode(&self, message: &Message) -> String {
+        let mut tags = BTreeMap::new();
+        if let Some(node) = message.get_node() {
+            tags.extend(node.get_tags().iter().map(|(k, v)| (k.as_str(), v.as_str())));
+            tags.insert("node", node.get_id());
+            tags.insert("host", node.get_hostname());
+            if let Some(site) = node.get_site() {
+                tags.insert("site", site);
+            }
+        }
+        let name = message.get_controller_name();
+        tags.insert("controller", &name);
+
+        let mut line = escape(&self.measurement, &[',', ' ']);
+        for (key, value) in tags.iter().filter(|(_, value)| !value.is_empty()) {
+            line.push(',');
+            line.push_str(&escape(key, &[',', '=', ' ']));
+            line.push('=');
+            line.push_str(&escape(value, &[',', '=', ' ']));
+        }
+
+        let mut fields = vec![format!("content={}", quote(&message.get_content()))];
+        if let Some(reading) = message.get_read_state() {
+            match reading.trim().parse::<f64>() {
+                Ok(value) if value.is_finite() => fields.push(format!("reading={}", value)),
+                _ => fields.push(format!("raw_reading={}", quote(&reading))),
+            }
+        }
+        if let Some(state) = message.get_output_state() {
+            fields.push(format!("output={}", state));
+        }
+
+        format!(
+            "{} {} {}",
+            line,
+            fields.join(","),
+            self.precision.timestamp(message.get_timestamp()),
+        )
+    }
+
+    /// Write the buffered lines, which are only removed from the buffer once the write succeeds
+    ///
+    /// Lines may therefore be written twice if a write fails part way through.
+    async fn write(&mut self) -> Result<(), SinkError> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.send(&self.buffer).await?;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Write lines to the destination
+    async fn send(&self, lines: &[String]) -> Result<(), SinkError> {
+        match &self.destination {
+            Destination::File(path) => {
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                for line in lines {
+                    writeln!(file, "{}", line)?;
+                }
+            }
+            Destination::Udp(addr) => {
+                let socket = UdpSocket::bind(match addr {
+                    SocketAddr::V4(_) => "0.0.0.0:0",
+                    SocketAddr::V6(_) => "[::]:0",
+                }).await?;
+                let mut payload = String::new();
+                for line in lines {
+                    if !payload.is_empty() && payload.len() + line.len() + 1 > MAX_DATAGRAM {
+                        socket.send_to(payload.as_bytes(), addr).await?;
+                        payload.clear();
+                    }
+                    payload.push_str(line);
+                    payload.push('\n');
+                }
+                socket.send_to(payload.as_bytes(), addr).await?;
+            }
+            Destination::Http { client, url, token } => {
+                let mut request = client.post(url)
+                    .query(&[("precision", self.precision.as_str())])
+                    .header("content-type", "text/plain; charset=utf-8")
+                    .body(lines.join("\n"));
+                if let Some(token) = token {
+                    request = request.header("authorization", format!("Token {}", token));
+                }
+                request.send().await?.error_for_status()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Sink for InfluxSink {
+    fn emit<'a>(&'a mut self, messages: &'a [Message]) -> EmitFuture<'a> {
+        Box::pin(async move {
+            let lines: Vec<String> = messages.iter().map(|message| self.encode(message)).collect();
+            self.buffer.extend(lines);
+            let excess = self.buffer.len().saturating_sub(self.max_buffer);
+            if excess > 0 {
+                eprintln!("InfluxDB buffer is full, dropping the {} oldest lines", excess);
+                self.buffer.drain(..excess);
+            }
+            if self.buffer.len() >= self.batch_size {
+                self.write().await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn flush(&mut self) -> EmitFuture<'_> {
+        Box::pin(self.write())
+    }
+}
+
+/// Escape the given characters, and backslashes, with a backslash
+fn escape(value: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == '\\' || special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Quote a string field value
+fn quote(value: &str) -> String {
+    format!("\"{}\"", escape(value, &['"']))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::sync::{Arc, Mutex};
+    use chrono::TimeZone;
+    use hyper::{Body, Request, Response, Server};
+    use hyper::service::{make_service_fn, service_fn};
+    use crate::types::NodeIdentity;
+    use super::*;
+
+    fn time() -> DateTime<Utc> {
+        Utc.timestamp_opt(1_700_000_000, 123_456_789).unwrap()
+    }
+
+    #[test]
+    fn test_encode() {
+        let sink = InfluxSink::file("unused");
+        let message = Message::new("heater", "Below Threshold", time(), String::from("68.5"))
+            .set_output_state(true);
+        assert_eq!(
+            sink.encode(&message),
+            r#"equilibrium,controller=heater content="Below Threshold",reading=68.5,output=true 1700000000123456789"#,
+        );
+
+        let message = Message::new("grow light", "Mode Changed: Auto -> Manual On", time(), None);
+        assert_eq!(
+            sink.encode(&message),
+            r#"equilibrium,controller=grow\ light content="Mode Changed: Auto -> Manual On" 1700000000123456789"#,
+        );
+    }
+
+    #[test]
+    fn test_encode_node() {
+        let sink = InfluxSink::file("unused")
+            .set_measurement("farm,north")
+            .set_precision(Precision::Seconds);
+        let node = NodeIdentity::new("greenhouse-1")
+            .set_hostname("pi")
+            .set_site("north farm")
+            .add_tag("zone", "a=1");
+        let message = Message::new("probe", "Read Failed: \"bus\" error", time(), String::from("NaN"))
+            .set_node(node);
+        assert_eq!(
+            sink.encode(&message),
+            r#"farm\,north,controller=probe,host=pi,node=greenhouse-1,site=north\ farm,zone=a\=1 content="Read Failed: \"bus\" error",raw_reading="NaN" 1700000000"#,
+        );
+    }
+
+    #[test]
+    fn test_precision() {
+        let message = Message::new("heater", "Above Threshold", time(), String::from("81"));
+        let encode = |precision| InfluxSink::file("unused").set_precision(precision).encode(&message);
+        assert!(encode(Precision::Microseconds).ends_with("reading=81 1700000000123456"));
+        assert!(encode(Precision::Milliseconds).ends_with("reading=81 1700000000123"));
+    }
+
+    #[tokio::test]
+    async fn test_file() {
+        let path = std::env::temp_dir().join(format!("equilibrium-influx-{}.lp", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut sink = InfluxSink::file(&path).set_batch_size(3);
+        let messages = vec![
+            Message::new("heater", "Above Threshold", time(), String::from("81")),
+            Message::new("heater", "Below Threshold", time(), String::from("68")),
+        ];
+        sink.emit(&messages).await.unwrap();
+        // buffered until the batch is full
+        assert!(!path.exists());
+
+        sink.emit(&messages).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 4);
+
+        sink.emit(&messages[..1]).await.unwrap();
+        sink.flush().await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 5);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_udp() {
+        let listener = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut sink = InfluxSink::udp(listener.local_addr().unwrap());
+        sink.emit(&[Message::new("heater", "Above Threshold", time(), String::from("81"))]).await.unwrap();
+
+        let mut buffer = [0; MAX_DATAGRAM];
+        let length = listener.recv(&mut buffer).await.unwrap();
+        assert_eq!(
+            std::str::from_utf8(&buffer[..length]).unwrap(),
+            "equilibrium,controller=heater content=\"Above Threshold\",reading=81 1700000000123456789\n",
+        );
+    }
+
+    #[tokio::test]
+    async fn test_http() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+        let make_service = make_service_fn(move |_| {
+            let log = log.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let log = log.clone();
+                    async move {
+                        let query = req.uri().query().unwrap_or_default().to_string();
+                        let auth = req.headers().get("authorization").map(|v| v.to_str().unwrap().to_string());
+                        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                        log.lock().unwrap().push((query, auth, String::from_utf8(body.to_vec()).unwrap()));
+                        Ok::<_, Infallible>(Response::builder().status(204).body(Body::empty()).unwrap())
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let mut sink = InfluxSink::http(format!("http://{}/api/v2/write?bucket=sensors", addr))
+            .set_token("secret")
+            .set_precision(Precision::Milliseconds);
+        sink.emit(&[
+            Message::new("heater", "Above Threshold", time(), String::from("81")),
+            Message::new("heater", "Below Threshold", time(), String::from("68")),
+        ]).await.unwrap();
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].0, "bucket=sensors&precision=ms");
+        assert_eq!(received[0].1.as_deref(), Some("Token secret"));
+        assert_eq!(received[0].2, concat!(
+            "equilibrium,controller=heater content=\"Above Threshold\",reading=81 1700000000123\n",
+            "equilibrium,controller=heater content=\"Below Threshold\",reading=68 1700000000123",
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_http_failure() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+        let make_service = make_service_fn(move |_| {
+            let log = log.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let log = log.clone();
+                    async move {
+                        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                        let mut log = log.lock().unwrap();
+                        log.push(String::from_utf8(body.to_vec()).unwrap());
+                        // the first write fails
+                        let status = if log.len() == 1 { 500 } else { 204 };
+                        Ok::<_, Infallible>(Response::builder().status(status).body(Body::empty()).unwrap())
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let mut sink = InfluxSink::http(format!("http://{}/write", addr))
+            .set_precision(Precision::Seconds)
+            .set_max_buffer(2);
+        let first = Message::new("heater", "Above Threshold", time(), None);
+        assert!(sink.emit(std::slice::from_ref(&first)).await.is_err());
+
+        // the failed line is written along with the next batch
+        let second = Message::new("heater", "Below Threshold", time(), None);
+        sink.emit(std::slice::from_ref(&second)).await.unwrap();
+        assert!(sink.buffer.is_empty());
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[1], format!("{}\n{}", sink.encode(&first), sink.encode(&second)));
+    }
+
+    #[tokio::test]
+    async fn test_max_buffer() {
+        let mut sink = InfluxSink::file("/nonexistent/influx.txt")
+            .set_batch_size(10)
+            .set_max_buffer(2);
+        let messages: Vec<Message> = ["Activated", "Deactivated", "Activated"].into_iter()
+            .map(|content| Message::new("heater", content, time(), None))
+            .collect();
+        sink.emit(&messages).await.unwrap();
+
+        // the oldest line is dropped
+        assert_eq!(sink.buffer, vec![sink.encode(&messages[1]), sink.encode(&messages[2])]);
+    }
+}
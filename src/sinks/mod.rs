@@ -0,0 +1,92 @@
+//! Destinations for the messages of a [`Runtime`](crate::Runtime)
+//!
+//! The [`Emitter`] posts messages to a bespoke HTTP endpoint. Any other destination, such as a
+//! time-series database, implements [`Sink`] and is attached with
+//! [`Runtime::add_sink`](crate::Runtime::add_sink). Every sink receives every message that the
+//! runtime emits, and is flushed when the runtime shuts down.
+//!
+//! A sink may instead be wrapped in a [`Route`] and attached with
+//! [`Runtime::add_route`](crate::Runtime::add_route), to select the messages that it receives with
+//! a [`Filter`] and to limit how many it receives. Each sink is written to from its own task, so
+//! one sink which fails or stalls does not hold up the others.
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use crate::Emitter;
+use crate::types::Message;
+
+pub mod file;
+pub mod influx;
+mod route;
+
+pub use route::{Filter, Kind, Route, Severity};
+
+pub type EmitFuture<'a> = Pin<Box<dyn Future<Output = Result<(), SinkError>> + Send + 'a>>;
+
+/// Reasons why a [`Sink`] failed to write messages
+#[derive(Debug)]
+pub enum SinkError {
+    /// A file or socket could not be written to
+    Io(std::io::Error),
+
+    /// An HTTP request failed or was rejected
+    Http(reqwest::Error),
+}
+
+impl fmt::Display for SinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SinkError::Io(e) => write!(f, "{}", e),
+            SinkError::Http(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for SinkError {}
+
+impl From<std::io::Error> for SinkError {
+    fn from(e: std::io::Error) -> Self {
+        SinkError::Io(e)
+    }
+}
+
+impl From<reqwest::Error> for SinkError {
+    fn from(e: reqwest::Error) -> Self {
+        SinkError::Http(e)
+    }
+}
+
+/// A destination that messages are written to
+pub trait Sink: Send {
+    /// Write a batch of messages
+    ///
+    /// Sinks which buffer messages may return before the messages have been written.
+    fn emit<'a>(&'a mut self, messages: &'a [Message]) -> EmitFuture<'a>;
+
+    /// Write any buffered messages
+    ///
+    /// The default implementation does nothing.
+    fn flush(&mut self) -> EmitFuture<'_> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Returns how many times a write has been retried
+    ///
+    /// The default implementation returns 0, for sinks which do not retry.
+    fn get_retries(&self) -> u64 {
+        0
+    }
+}
+
+impl Sink for Emitter {
+    fn emit<'a>(&'a mut self, messages: &'a [Message]) -> EmitFuture<'a> {
+        Box::pin(async move {
+            Emitter::emit(self, messages.to_vec()).await?;
+            Ok(())
+        })
+    }
+
+    fn get_retries(&self) -> u64 {
+        Emitter::get_retries(self)
+    }
+}
@@ -0,0 +1,483 @@
+//! Filtering, rate limiting and isolation of sinks
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use chrono::{DateTime, Duration, Utc};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use crate::metrics::ALARMS;
+use crate::sinks::Sink;
+use crate::types::Message;
+
+/// What a message reports, derived from its content
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kind {
+    /// A change in the state of a controller, such as "Above Threshold"
+    Event,
+
+    /// A failure, such as "Read Failed" (see [`ALARMS`])
+    Alarm,
+
+    /// The acknowledgement or rejection of a command
+    Command,
+
+    /// A periodic [`Heartbeat`](crate::node::Heartbeat) of the node
+    Heartbeat,
+
+    /// A change of the [`Role`](crate::failover::Role) of the node
+    Failover,
+}
+
+impl Kind {
+    pub fn of(message: &Message) -> Self {
+        let content = message.get_content();
+        if content.starts_with("Heartbeat:") {
+            Kind::Heartbeat
+        } else if content.starts_with("Failover:") {
+            Kind::Failover
+        } else if content.starts_with("Command ") {
+            Kind::Command
+        } else if ALARMS.iter().any(|alarm| content.starts_with(alarm)) {
+            Kind::Alarm
+        } else {
+            Kind::Event
+        }
+    }
+}
+
+/// How urgently a message needs attention, derived from its content
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Severity {
+    #[default]
+    Info,
+
+    /// Alarms that the node recovers from on its own, safe states, failovers, rejected commands
+    /// and degraded heartbeats
+    Warning,
+
+    /// Outputs that could not be written and controllers that have been quarantined
+    Critical,
+}
+
+impl Severity {
+    pub fn of(message: &Message) -> Self {
+        let content = message.get_content();
+        let critical = ["Write Failed", "Controller Quarantined"];
+        let warning = ["Safe State", "Failover:", "Command Rejected"];
+        if critical.iter().any(|prefix| content.starts_with(prefix)) {
+            Severity::Critical
+        } else if ALARMS.iter().chain(warning.iter()).any(|prefix| content.starts_with(prefix))
+            || (Kind::of(message) == Kind::Heartbeat && content.contains("Degraded"))
+        {
+            Severity::Warning
+        } else {
+            Severity::Info
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Info => write!(f, "Info"),
+            Severity::Warning => write!(f, "Warning"),
+            Severity::Critical => write!(f, "Critical"),
+        }
+    }
+}
+
+/// Selects the messages that are written to a sink
+///
+/// The default filter matches every message. Each condition narrows the selection, so a message
+/// must match all of them.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    controllers: HashSet<String>,
+    kinds: HashSet<Kind>,
+    severity: Severity,
+}
+
+impl Filter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builder method to match messages of the named controller
+    ///
+    /// Once a controller has been added, messages of other controllers no longer match.
+    pub fn add_controller<S>(mut self, name: S) -> Self
+        where S: Into<String>
+    {
+        self.controllers.insert(name.into());
+        self
+    }
+
+    /// Builder method to match messages of the given kind
+    ///
+    /// Once a kind has been added, messages of other kinds no longer match.
+    pub fn add_kind(mut self, kind: Kind) -> Self {
+        self.kinds.insert(kind);
+        self
+    }
+
+    /// Builder method to match messages of at least the given severity
+    pub fn set_min_severity(mut self, severity: Severity) -> Self {
+        self.severity = severity;
+        self
+    }
+
+    /// Returns true if the message is selected by the filter
+    pub fn matches(&self, message: &Message) -> bool {
+        (self.controllers.is_empty() || self.controllers.contains(&message.get_controller_name()))
+            && (self.kinds.is_empty() || self.kinds.contains(&Kind::of(message)))
+            && Severity::of(message) >= self.severity
+    }
+}
+
+/// A [`Sink`] along with the messages that it receives
+///
+/// Each route writes to its sink from a separate tokio task, through a queue of batches. A sink
+/// that is slow or failing therefore delays neither the runtime nor the other sinks. When the
+/// queue is full, further batches are dropped until the sink catches up. Batches which are
+/// dropped or fail to be written are counted, and a route is failing while its last write failed.
+///
+/// # Example
+/// ```
+/// use chrono::Duration;
+/// use equilibrium::{ControllerGroup, Emitter, Runtime};
+/// use equilibrium::sinks::{Filter, Kind, Route, Severity};
+/// use equilibrium::sinks::file::FileSink;
+///
+/// let path = std::env::temp_dir().join("equilibrium-doc-route");
+/// let log = FileSink::open(&path).unwrap();
+/// let runtime = Runtime::new(ControllerGroup::new(), Duration::seconds(1))
+///     .add_route(Route::new("cloud", Emitter::new("http://localhost:8000"))
+///         .set_filter(Filter::new().set_min_severity(Severity::Warning))
+///         .set_rate_limit(100, Duration::minutes(1)))
+///     .add_route(Route::new("log", log))
+///     .add_route(Route::new("dashboard", Emitter::new("http://localhost:8001"))
+///         .set_filter(Filter::new().add_controller("heater").add_kind(Kind::Event)));
+/// # std::fs::remove_dir_all(&path).unwrap();
+/// ```
+pub struct Route {
+    name: String,
+    filter: Filter,
+    rate_limit: Option<(usize, Duration)>,
+    window: Option<(DateTime<Utc>, usize)>,
+    queue_size: usize,
+    sink: Option<Box<dyn Sink>>,
+    tx: Option<mpsc::Sender<Vec<Message>>>,
+    task: Option<JoinHandle<()>>,
+    stats: Arc<Stats>,
+}
+
+/// Counters of a route, shared with the task that writes to its sink
+#[derive(Debug, Default)]
+struct Stats {
+    failures: AtomicU64,
+    retries: AtomicU64,
+    failing: AtomicBool,
+}
+
+impl Route {
+    /// Create a route which writes every message to the sink
+    ///
+    /// # Arguments
+    /// * `name` - Name of the route, used when logging failures
+    /// * `sink` - The sink to write messages to
+    pub fn new<S, K>(name: S, sink: K) -> Self
+        where S: Into<String>, K: Sink + 'static
+    {
+        Self {
+            name: name.into(),
+            filter: Filter::default(),
+            rate_limit: None,
+            window: None,
+            queue_size: 64,
+            sink: Some(Box::new(sink)),
+            tx: None,
+            task: None,
+            stats: Arc::new(Stats::default()),
+        }
+    }
+
+    /// Builder method to select the messages that are written
+    pub fn set_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Builder method to write at most `count` messages in each `period`
+    ///
+    /// Messages in excess of the limit are dropped, except for [`Severity::Critical`] messages,
+    /// which are always written and take up the limit first.
+    pub fn set_rate_limit(mut self, count: usize, period: Duration) -> Self {
+        self.rate_limit = Some((count, period));
+        self
+    }
+
+    /// Builder method to set how many batches may wait for the sink
+    ///
+    /// The default is 64.
+    pub fn set_queue_size(mut self, batches: usize) -> Self {
+        self.queue_size = batches.max(1);
+        self
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns how many batches were dropped or could not be written to the sink
+    pub fn get_failures(&self) -> u64 {
+        self.stats.failures.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many times the sink retried a write (see [`Sink::get_retries`])
+    pub fn get_retries(&self) -> u64 {
+        self.stats.retries.load(Ordering::Relaxed)
+    }
+
+    /// Returns true if the last write to the sink failed
+    pub fn is_failing(&self) -> bool {
+        self.stats.failing.load(Ordering::Relaxed)
+    }
+
+    /// Apply the filter and the rate limit to a batch of messages
+    fn select(&mut self, messages: &[Message], time: DateTime<Utc>) -> Vec<Message> {
+        let mut selected: Vec<Message> = messages.iter()
+            .filter(|message| self.filter.matches(message))
+            .cloned()
+            .collect();
+
+        if let Some((count, period)) = self.rate_limit {
+            let (start, sent) = match self.window {
+                Some((start, sent)) if time - start < period => (start, sent),
+                _ => (time, 0),
+            };
+            // critical messages are never dropped, so they go first
+            let (mut critical, rest): (Vec<_>, Vec<_>) = selected.into_iter()
+                .partition(|message| Severity::of(message) == Severity::Critical);
+            let allowed = count.saturating_sub(sent + critical.len());
+            if rest.len() > allowed {
+                eprintln!(
+                    "Rate limit of sink {} exceeded, dropping {} messages",
+                    self.name,
+                    rest.len() - allowed,
+                );
+            }
+            critical.extend(rest.into_iter().take(allowed));
+            selected = critical;
+            self.window = Some((start, sent + selected.len()));
+        }
+        selected
+    }
+
+    /// Queue the selected messages for the sink, starting its task on first use
+    pub(crate) fn send(&mut self, messages: &[Message]) {
+        let selected = self.select(messages, Utc::now());
+        if selected.is_empty() {
+            return;
+        }
+
+        if let Some(sink) = self.sink.take() {
+            let (tx, rx) = mpsc::channel(self.queue_size);
+            self.tx = Some(tx);
+            self.task = Some(tokio::spawn(forward(self.name.clone(), sink, rx, self.stats.clone())));
+        }
+        if let Some(tx) = &self.tx {
+            if tx.try_send(selected).is_err() {
+                eprintln!("Sink {} is not keeping up, dropping messages", self.name);
+                self.stats.failures.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Write the queued messages, flush the sink and stop its task
+    ///
+    /// Gives up on a sink that takes longer than the timeout.
+    pub(crate) async fn close(&mut self, timeout: std::time::Duration) {
+        self.tx = None;
+        let result = match (self.task.take(), self.sink.as_mut()) {
+            (Some(task), _) => tokio::time::timeout(timeout, task).await.map(|_| ()),
+            (None, Some(sink)) => match tokio::time::timeout(timeout, sink.flush()).await {
+                Ok(Err(e)) => {
+                    eprintln!("Failed to flush sink {}: {}", self.name, e);
+                    Ok(())
+                }
+                result => result.map(|_| ()),
+            },
+            (None, None) => Ok(()),
+        };
+        if result.is_err() {
+            eprintln!("Sink {} did not shut down in time", self.name);
+        }
+    }
+}
+
+impl fmt::Debug for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Route")
+            .field("name", &self.name)
+            .field("filter", &self.filter)
+            .field("rate_limit", &self.rate_limit)
+            .field("queue_size", &self.queue_size)
+            .finish()
+    }
+}
+
+/// Write each queued batch to the sink until the queue is closed, then flush it
+async fn forward(name: String, mut sink: Box<dyn Sink>, mut rx: mpsc::Receiver<Vec<Message>>, stats: Arc<Stats>) {
+    while let Some(messages) = rx.recv().await {
+        let result = sink.emit(&messages).await;
+        stats.retries.store(sink.get_retries(), Ordering::Relaxed);
+        stats.failing.store(result.is_err(), Ordering::Relaxed);
+        if let Err(e) = result {
+            eprintln!("Failed to write messages to sink {}: {}", name, e);
+            stats.failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    if let Err(e) = sink.flush().await {
+        eprintln!("Failed to flush sink {}: {}", name, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use crate::sinks::{EmitFuture, SinkError};
+    use super::*;
+
+    /// Records every message, optionally never finishing a write
+    #[derive(Clone, Default)]
+    struct Recorder {
+        messages: Arc<Mutex<Vec<Message>>>,
+        stalled: bool,
+    }
+
+    impl Sink for Recorder {
+        fn emit<'a>(&'a mut self, messages: &'a [Message]) -> EmitFuture<'a> {
+            Box::pin(async move {
+                if self.stalled {
+                    std::future::pending::<()>().await;
+                }
+                self.messages.lock().unwrap().extend_from_slice(messages);
+                Ok(())
+            })
+        }
+    }
+
+    struct Failing;
+
+    impl Sink for Failing {
+        fn emit<'a>(&'a mut self, _messages: &'a [Message]) -> EmitFuture<'a> {
+            Box::pin(async { Err(SinkError::Io(std::io::ErrorKind::BrokenPipe.into())) })
+        }
+    }
+
+    fn message(name: &str, content: &str) -> Message {
+        Message::new(name, content, Utc::now(), None)
+    }
+
+    #[test]
+    fn test_kind_and_severity() {
+        let cases = [
+            ("Above Threshold", Kind::Event, Severity::Info),
+            ("Safe State", Kind::Event, Severity::Warning),
+            ("Read Failed: timed out", Kind::Alarm, Severity::Warning),
+            ("Write Failed: bus error", Kind::Alarm, Severity::Critical),
+            ("Controller Quarantined", Kind::Alarm, Severity::Critical),
+            ("Command Acknowledged: Enable", Kind::Command, Severity::Info),
+            ("Command Rejected: Unknown controller", Kind::Command, Severity::Warning),
+            ("Heartbeat: 3 controllers, up 60s, Healthy", Kind::Heartbeat, Severity::Info),
+            ("Heartbeat: 3 controllers, up 60s, Degraded (emitter failing)", Kind::Heartbeat, Severity::Warning),
+            ("Failover: Active (epoch 2)", Kind::Failover, Severity::Warning),
+        ];
+        for (content, kind, severity) in cases {
+            let message = message("heater", content);
+            assert_eq!(Kind::of(&message), kind, "{}", content);
+            assert_eq!(Severity::of(&message), severity, "{}", content);
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        assert!(Filter::new().matches(&message("heater", "Above Threshold")));
+
+        let filter = Filter::new()
+            .add_controller("heater")
+            .add_controller("pump")
+            .add_kind(Kind::Alarm);
+        assert!(filter.matches(&message("pump", "Read Failed: timed out")));
+        assert!(!filter.matches(&message("pump", "Above Threshold")));
+        assert!(!filter.matches(&message("fan", "Read Failed: timed out")));
+
+        let filter = Filter::new().set_min_severity(Severity::Critical);
+        assert!(filter.matches(&message("pump", "Write Failed: bus error")));
+        assert!(!filter.matches(&message("pump", "Read Failed: timed out")));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut route = Route::new("test", Recorder::default())
+            .set_rate_limit(3, Duration::minutes(1));
+        let batch = vec![message("heater", "Above Threshold"); 2];
+        let now = Utc::now();
+
+        assert_eq!(route.select(&batch, now).len(), 2);
+        assert_eq!(route.select(&batch, now + Duration::seconds(10)).len(), 1);
+        assert_eq!(route.select(&batch, now + Duration::seconds(20)).len(), 0);
+        // a new window starts once the period has elapsed
+        assert_eq!(route.select(&batch, now + Duration::seconds(61)).len(), 2);
+
+        // critical messages are written even once the limit is reached, and go first
+        let batch = [
+            message("heater", "Above Threshold"),
+            message("pump", "Write Failed: bus error"),
+        ];
+        assert_eq!(route.select(&batch, now + Duration::seconds(62)).len(), 1);
+        let selected = route.select(&batch, now + Duration::seconds(63));
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].get_content(), "Write Failed: bus error");
+        let selected = route.select(&batch, now + Duration::seconds(122));
+        assert_eq!(selected.len(), 2);
+        assert_eq!(selected[0].get_content(), "Write Failed: bus error");
+    }
+
+    #[tokio::test]
+    async fn test_isolation() {
+        let healthy = Recorder::default();
+        let stalled = Recorder { stalled: true, ..Recorder::default() };
+        let mut routes = [
+            Route::new("failing", Failing),
+            Route::new("stalled", stalled.clone()).set_queue_size(1),
+            Route::new("healthy", healthy.clone())
+                .set_filter(Filter::new().add_kind(Kind::Alarm)),
+        ];
+
+        for _ in 0..5 {
+            for route in routes.iter_mut() {
+                route.send(&[
+                    message("heater", "Above Threshold"),
+                    message("heater", "Read Failed: timed out"),
+                ]);
+            }
+        }
+        let timeout = std::time::Duration::from_millis(100);
+        for route in routes.iter_mut() {
+            route.close(timeout).await;
+        }
+
+        assert_eq!(routes[0].get_failures(), 5);
+        assert!(routes[0].is_failing());
+        // the queue of the stalled sink fills up before its task gets to run
+        assert_eq!(routes[1].get_failures(), 4);
+        assert!(!routes[2].is_failing());
+
+        let written = healthy.messages.lock().unwrap();
+        assert_eq!(written.len(), 5);
+        assert!(written.iter().all(|message| Kind::of(message) == Kind::Alarm));
+        assert!(stalled.messages.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,295 @@
+//! Persistence of controller state across restarts
+//!
+//! All controller state (scheduled events, cached output state, setpoints, etc.) lives in memory.
+//! A [`StateStore`] is used to persist a [`ControllerState`] snapshot for each named controller so
+//! that a [`ControllerGroup`](crate::ControllerGroup) can be restored when the node restarts.
+//!
+//! Names starting with [`RESERVED_PREFIX`] are reserved for snapshots of the runtime itself, such
+//! as the epoch of a [`Failover`](crate::failover::Failover). Controllers with such names are not
+//! saved or restored, so that they cannot overwrite those snapshots.
+//!
+//! Snapshots carry a schema version. When a snapshot is loaded, it is passed through [`migrate`]
+//! which upgrades older snapshots to the current [`STATE_VERSION`], one version at a time, and
+//! rejects snapshots written by a newer version.
+//!
+//! # Example
+//! ```
+//! use equilibrium::store::{FileStateStore, StateStore};
+//! use equilibrium::types::ControllerState;
+//!
+//! let dir = std::env::temp_dir().join("equilibrium-doc-store");
+//! let mut store = FileStateStore::new(&dir).unwrap();
+//!
+//! let state = ControllerState::new(vec![Some(true)], vec![]);
+//! store.save("heater", &state).unwrap();
+//!
+//! assert_eq!(store.load("heater").unwrap(), Some(state));
+//! # std::fs::remove_dir_all(dir).unwrap();
+//! ```
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde_json::Value;
+use crate::types::{ControllerState, STATE_VERSION};
+
+/// Characters that are kept as they are in the file name of a snapshot
+const FILE_NAME: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_');
+
+/// Steps that upgrade a raw snapshot, where the step at index `n` converts a snapshot of version
+/// `n + 1` to version `n + 2`
+///
+/// A step must be appended whenever [`STATE_VERSION`] is incremented.
+const MIGRATIONS: &[fn(&mut Value)] = &[
+    // version 2 records the mode of the controller
+    |value| {
+        value["mode"] = Value::from("auto");
+        value["mode_expires"] = Value::Null;
+    },
+];
+
+/// Errors that can occur while saving or loading controller state
+#[derive(Debug)]
+pub enum StateError {
+    /// The underlying storage could not be accessed
+    Io(io::Error),
+
+    /// A snapshot could not be encoded or decoded
+    Serialization(serde_json::Error),
+
+    /// A snapshot was written by a newer, unknown schema version
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::Io(e) => write!(f, "state store IO error: {}", e),
+            StateError::Serialization(e) => write!(f, "state serialization error: {}", e),
+            StateError::UnsupportedVersion(v) => write!(f, "unsupported state version: {}", v),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl From<io::Error> for StateError {
+    fn from(e: io::Error) -> Self {
+        StateError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for StateError {
+    fn from(e: serde_json::Error) -> Self {
+        StateError::Serialization(e)
+    }
+}
+
+/// Upgrade a raw snapshot to the current [`STATE_VERSION`]
+///
+/// The migration steps from the version of the snapshot onwards are applied in order. Snapshots
+/// without a version field are treated as version 1.
+pub fn migrate(mut value: Value) -> Result<ControllerState, StateError> {
+    let version = value.get("version")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(1)
+        .max(1) as u32;
+
+    if version > STATE_VERSION {
+        return Err(StateError::UnsupportedVersion(version));
+    }
+
+    for step in MIGRATIONS.iter().skip(version as usize - 1) {
+        step(&mut value);
+    }
+    value["version"] = Value::from(STATE_VERSION);
+
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Prefix of the names under which the runtime saves snapshots of its own
+pub const RESERVED_PREFIX: &str = "equilibrium:";
+
+/// Storage backend for controller snapshots
+///
+/// Snapshots are keyed by controller name.
+pub trait StateStore {
+    /// Persist the snapshot for the given controller, replacing any previous snapshot
+    fn save(&mut self, name: &str, state: &ControllerState) -> Result<(), StateError>;
+
+    /// Load the snapshot for the given controller
+    ///
+    /// Returns `None` if no snapshot has been saved for the controller.
+    fn load(&self, name: &str) -> Result<Option<ControllerState>, StateError>;
+}
+
+/// A [`StateStore`] that stores each snapshot as a JSON file within a directory
+///
+/// Files are written to a temporary file, synced to disk and then renamed, after which the
+/// directory is synced as well. This way a snapshot is never left partially written if the node
+/// loses power. Controller names are percent-encoded in the file
+/// names, so that every name has a file of its own.
+#[derive(Debug)]
+pub struct FileStateStore {
+    directory: PathBuf,
+}
+
+impl FileStateStore {
+    /// Create a new store, creating the directory if it does not exist
+    ///
+    /// # Arguments
+    /// * `directory` - Directory where snapshots are stored
+    pub fn new<P>(directory: P) -> Result<Self, StateError>
+        where P: AsRef<Path>
+    {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory)?;
+        Ok(Self { directory })
+    }
+
+    fn path_for(&self, name: &str) -> PathBuf {
+        self.directory.join(format!("{}.json", utf8_percent_encode(name, FILE_NAME)))
+    }
+}
+
+impl StateStore for FileStateStore {
+    fn save(&mut self, name: &str, state: &ControllerState) -> Result<(), StateError> {
+        let path = self.path_for(name);
+        let tmp = path.with_extension("json.tmp");
+
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(&serde_json::to_vec(state)?)?;
+        file.sync_all()?;
+        drop(file);
+
+        fs::rename(tmp, path)?;
+        sync_directory(&self.directory)?;
+        Ok(())
+    }
+
+    fn load(&self, name: &str) -> Result<Option<ControllerState>, StateError> {
+        let contents = match fs::read(self.path_for(name)) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let value = serde_json::from_slice(&contents)?;
+        migrate(value).map(Some)
+    }
+}
+
+/// Sync a directory so that a rename within it survives a loss of power
+#[cfg(unix)]
+fn sync_directory(directory: &Path) -> io::Result<()> {
+    fs::File::open(directory)?.sync_all()
+}
+
+/// Directories cannot be opened for syncing on other platforms
+#[cfg(not(unix))]
+fn sync_directory(_directory: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// A [`StateStore`] that keeps snapshots in memory
+///
+/// This is mainly useful for testing.
+#[derive(Debug, Default)]
+pub struct MemoryStateStore {
+    states: HashMap<String, ControllerState>,
+}
+
+impl MemoryStateStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl StateStore for MemoryStateStore {
+    fn save(&mut self, name: &str, state: &ControllerState) -> Result<(), StateError> {
+        self.states.insert(name.to_string(), state.clone());
+        Ok(())
+    }
+
+    fn load(&self, name: &str) -> Result<Option<ControllerState>, StateError> {
+        Ok(self.states.get(name).cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::TempDir;
+    use chrono::{TimeZone, Utc};
+    use crate::types::{Action, Event, Mode};
+    use super::*;
+
+    #[test]
+    fn test_file_store_round_trip() {
+        let dir = TempDir::new("store-round-trip");
+        let mut store = FileStateStore::new(&dir).unwrap();
+
+        assert_eq!(store.load("heater").unwrap(), None);
+
+        let timestamp = Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap();
+        let state = ControllerState::new(
+            vec![Some(true), None],
+            vec![Event::new(Action::Read, timestamp)],
+        )
+            .with_input("70.0".to_string())
+            .with_setpoint(72.0);
+        store.save("heater", &state).unwrap();
+
+        // a new store instance reads the same snapshot
+        let store = FileStateStore::new(&dir).unwrap();
+        assert_eq!(store.load("heater").unwrap(), Some(state));
+    }
+
+    #[test]
+    fn test_file_store_encodes_names() {
+        let dir = TempDir::new("store-names");
+        let mut store = FileStateStore::new(&dir).unwrap();
+
+        let state = ControllerState::new(vec![Some(false)], vec![]);
+        store.save("../grow light", &state).unwrap();
+
+        assert!(dir.join("%2E%2E%2Fgrow%20light.json").exists());
+        assert_eq!(store.load("../grow light").unwrap(), Some(state));
+
+        // names which only differ in punctuation are stored separately
+        let other = ControllerState::new(vec![Some(true)], vec![]);
+        store.save("grow_light", &other).unwrap();
+        store.save("grow-light", &ControllerState::new(vec![None], vec![])).unwrap();
+        assert_eq!(store.load("grow_light").unwrap(), Some(other));
+        assert_eq!(store.load("grow light").unwrap(), None);
+    }
+
+    #[test]
+    fn test_migrate() {
+        // snapshots without a version are treated as the first version
+        let value = serde_json::json!({
+            "outputs": [true],
+            "input": null,
+            "setpoint": null,
+            "events": [],
+        });
+        let state = migrate(value).unwrap();
+        assert_eq!(state.get_version(), STATE_VERSION);
+        assert_eq!(state.get_outputs(), &vec![Some(true)]);
+        assert_eq!(state.get_mode(), Mode::Auto);
+        assert_eq!(state.get_mode_expires(), None);
+
+        // snapshots from a newer version are rejected
+        let value = serde_json::json!({
+            "version": STATE_VERSION + 1,
+            "outputs": [],
+            "input": null,
+            "setpoint": null,
+            "events": [],
+        });
+        assert!(matches!(migrate(value), Err(StateError::UnsupportedVersion(_))));
+
+        // every version but the current one has a step
+        assert_eq!(MIGRATIONS.len() as u32, STATE_VERSION - 1);
+    }
+}
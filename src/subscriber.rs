@@ -0,0 +1,122 @@
+//! Commands received from a remote broker
+//!
+//! Nodes publish their messages through an [`Emitter`](crate::Emitter), and receive commands for
+//! their controllers through a [`Subscriber`], so that a node behind a firewall can be commanded
+//! without accepting incoming connections.
+use std::time::Duration;
+use reqwest::{Client, StatusCode};
+use crate::types::CommandRequest;
+
+/// Receives commands from a remote broker by HTTP long-polling
+///
+/// This is the counterpart of the [`Emitter`](crate::Emitter). Each call to
+/// [`Subscriber::receive`] makes a `GET` request which the broker holds open until commands are
+/// available, or until `wait` seconds have passed. The broker answers with a JSON array of
+/// [`CommandRequest`]s, or with `204 No Content` if there are none.
+///
+/// A subscriber is attached to a [`Runtime`](crate::Runtime) with
+/// [`Runtime::build_subscriber`](crate::Runtime::build_subscriber), which routes the commands to
+/// the named controllers and acknowledges each with a [`Message`](crate::types::Message).
+pub struct Subscriber {
+    client: Client,
+    url: String,
+    wait: Duration,
+}
+
+impl Subscriber {
+    /// Create a subscriber which asks the broker to hold each request for up to 30 seconds
+    pub fn new<S>(url: S) -> Self
+        where S: Into<String>
+    {
+        Self {
+            client: Client::new(),
+            url: url.into(),
+            wait: Duration::from_secs(30),
+        }
+    }
+
+    /// Builder method to set how long the broker may hold a request open
+    pub fn set_wait(mut self, wait: chrono::Duration) -> Self {
+        self.wait = wait.to_std().unwrap_or_default();
+        self
+    }
+
+    /// Wait for the next batch of commands
+    ///
+    /// Returns an empty vector if no commands arrived before the wait elapsed.
+    pub async fn receive(&self) -> Result<Vec<CommandRequest>, reqwest::Error> {
+        let response = self.client.get(&self.url)
+            .query(&[("wait", self.wait.as_secs())])
+            .timeout(self.wait + Duration::from_secs(10))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        match response.status() {
+            StatusCode::NO_CONTENT => Ok(Vec::new()),
+            _ => response.json().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use hyper::{Body, Request, Response, Server};
+    use hyper::service::{make_service_fn, service_fn};
+    use crate::types::Command;
+    use super::*;
+
+    /// Serve a single fixed response to every request
+    fn serve(status: u16, body: &'static str) -> SocketAddr {
+        let make_service = make_service_fn(move |_| async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| async move {
+                assert_eq!(req.uri().query(), Some("wait=5"));
+                Ok::<_, Infallible>(Response::builder()
+                    .status(status)
+                    .body(Body::from(body))
+                    .unwrap())
+            }))
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_receive() {
+        let addr = serve(200, r#"[
+            {"id": "1", "controller": "heater", "command": {"command": "set_setpoint", "value": 72.0}},
+            {"command": {"command": "reload"}}
+        ]"#);
+        let subscriber = Subscriber::new(format!("http://{}/commands", addr))
+            .set_wait(chrono::Duration::seconds(5));
+
+        let requests = subscriber.receive().await.unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].get_id(), Some("1"));
+        assert_eq!(requests[0].get_controller(), Some("heater"));
+        assert_eq!(requests[1].get_command(), &Command::Reload);
+    }
+
+    #[tokio::test]
+    async fn test_receive_empty() {
+        let addr = serve(204, "");
+        let subscriber = Subscriber::new(format!("http://{}/commands", addr))
+            .set_wait(chrono::Duration::seconds(5));
+
+        assert!(subscriber.receive().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_receive_error() {
+        let addr = serve(500, "");
+        let subscriber = Subscriber::new(format!("http://{}/commands", addr))
+            .set_wait(chrono::Duration::seconds(5));
+
+        assert!(subscriber.receive().await.is_err());
+    }
+}
@@ -0,0 +1,41 @@
+//! Helpers shared by unit tests
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// An empty directory which is removed when dropped
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Create an empty directory named after the test and the process
+    ///
+    /// The process ID keeps concurrent test runs apart, so `name` only has to be unique among the
+    /// tests of the crate.
+    pub(crate) fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir()
+            .join(format!("equilibrium-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
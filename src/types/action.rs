@@ -1,3 +1,5 @@
+use serde::{Deserialize, Serialize};
+
 /// Encapsulate IO actions
 ///
 /// IO actions are used for both input and output devices. For example, a temperature sensor may
@@ -5,7 +7,7 @@
 ///
 /// These actions are used to schedule IO events and is used within [`crate::controllers::Controller`]s
 /// to keep track of future events.
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
 pub enum Action {
     /// Input device should be read
     Read,
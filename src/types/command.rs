@@ -0,0 +1,148 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use crate::types::Mode;
+
+/// A request to change the behavior of a named [`crate::controllers::Controller`] at runtime
+///
+/// Commands are routed to controllers by [`crate::ControllerGroup::execute`].
+///
+/// # Example
+/// Commands are serialized with a `command` tag:
+/// ```
+/// use equilibrium::types::Command;
+///
+/// let command: Command = serde_json::from_str(r#"{"command": "set_setpoint", "value": 72.0}"#).unwrap();
+/// assert_eq!(command, Command::SetSetpoint { value: 72.0 });
+/// ```
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    /// Change the threshold or other setpoint of the controller
+    SetSetpoint { value: f32 },
+
+    /// Change how often the controller reads its input, in seconds
+    ///
+    /// The new interval takes effect after the next scheduled read.
+    SetInterval { seconds: i64 },
+
+    /// Change the [`Mode`] of the controller, optionally reverting to [`Mode::Auto`] after
+    /// `expires_in` seconds
+    SetMode { mode: Mode, expires_in: Option<i64> },
+
+    /// Force the output on or off, optionally expiring after `expires_in` seconds
+    ///
+    /// This is shorthand for [`Mode::ManualOn`] or [`Mode::ManualOff`].
+    Force { state: bool, expires_in: Option<i64> },
+
+    /// Release a forced output and hand back to automatic control
+    ///
+    /// This is shorthand for [`Mode::Auto`].
+    Release,
+
+    /// Resume polling the controller
+    ///
+    /// This is shorthand for [`Mode::Auto`].
+    Enable,
+
+    /// Stop polling the controller
+    ///
+    /// This is shorthand for [`Mode::Disabled`].
+    Disable,
+
+    /// Rebuild the controllers from their configuration
+    ///
+    /// This is not addressed to a controller, and is handled by the [`crate::Runtime`]. See
+    /// [`crate::Runtime::set_reload`].
+    Reload,
+}
+
+impl Command {
+    /// Returns the mode that is requested by the command along with when it expires (in seconds)
+    ///
+    /// Returns `None` if the command does not change the mode.
+    pub fn get_mode(&self) -> Option<(Mode, Option<i64>)> {
+        match self {
+            Command::SetSetpoint { .. } | Command::SetInterval { .. } | Command::Reload => None,
+            Command::SetMode { mode, expires_in } => Some((*mode, *expires_in)),
+            Command::Force { state: true, expires_in } => Some((Mode::ManualOn, *expires_in)),
+            Command::Force { state: false, expires_in } => Some((Mode::ManualOff, *expires_in)),
+            Command::Release | Command::Enable => Some((Mode::Auto, None)),
+            Command::Disable => Some((Mode::Disabled, None)),
+        }
+    }
+}
+
+/// A [`Command`] received from a remote node, addressed to a named controller
+///
+/// The `id` is chosen by the sender, and is included in the message that acknowledges the
+/// command. Commands which are handled by the runtime, such as [`Command::Reload`], do not need
+/// to name a controller.
+///
+/// # Example
+/// ```
+/// use equilibrium::types::{Command, CommandRequest};
+///
+/// let request: CommandRequest = serde_json::from_str(r#"{
+///     "id": "42",
+///     "controller": "heater",
+///     "command": {"command": "set_setpoint", "value": 72.0}
+/// }"#).unwrap();
+/// assert_eq!(request.get_command(), &Command::SetSetpoint { value: 72.0 });
+/// ```
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct CommandRequest {
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    controller: Option<String>,
+    command: Command,
+}
+
+impl CommandRequest {
+    pub fn new<S>(id: Option<String>, controller: Option<S>, command: Command) -> Self
+        where S: Into<String>
+    {
+        Self {
+            id,
+            controller: controller.map(Into::into),
+            command,
+        }
+    }
+
+    pub fn get_id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    pub fn get_controller(&self) -> Option<&str> {
+        self.controller.as_deref()
+    }
+
+    pub fn get_command(&self) -> &Command {
+        &self.command
+    }
+}
+
+/// Reasons why a [`Command`] could not be executed
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub enum CommandError {
+    /// No controller with the given name exists
+    UnknownController(String),
+
+    /// The controller does not support the command
+    Unsupported,
+
+    /// The command could not be carried out
+    Failed(String),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::UnknownController(name) => write!(f, "unknown controller: {}", name),
+            CommandError::Unsupported => write!(f, "command is not supported by controller"),
+            CommandError::Failed(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
@@ -0,0 +1,32 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+
+/// Reasons why an IO driver failed to read from or write to a device
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub enum DriverError {
+    /// The device did not respond within the configured timeout
+    Timeout,
+
+    /// The device could not be accessed, or responded with invalid data
+    Failed(String),
+}
+
+impl fmt::Display for DriverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DriverError::Timeout => write!(f, "timed out"),
+            DriverError::Failed(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for DriverError {}
+
+impl From<std::io::Error> for DriverError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock => DriverError::Timeout,
+            _ => DriverError::Failed(e.to_string()),
+        }
+    }
+}
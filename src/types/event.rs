@@ -1,4 +1,5 @@
 use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use crate::types::action::Action;
 
 /// Encapsulate IO events for scheduling or logging
@@ -8,7 +9,7 @@ use crate::types::action::Action;
 ///
 /// The associated [`Action`] is expected to be handled by the [`crate::controllers::Controller`]
 /// at the time specified by the timestamp.
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Event {
     action: Action,
 
@@ -34,11 +35,7 @@ impl Event {
 
     /// Returns true if the event should be executed at the specified time
     pub fn should_execute(&self, time: DateTime<Utc>) -> bool {
-        if self.timestamp > time {
-            false
-        } else {
-            true
-        }
+        self.timestamp <= time
     }
 
     /// Returns the action associated with the event
@@ -67,6 +64,7 @@ impl Event {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::clone_on_copy)]
 mod tests {
     use super::*;
     use chrono::{TimeZone, Utc};
@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+
+/// Identifies the node that a [`Message`](crate::types::Message) originated from
+///
+/// Controller names only need to be unique within a node. The identity is attached to every
+/// message that is emitted by a [`Runtime`](crate::Runtime) with an identity, so that a broker can
+/// tell apart two nodes which both have a "heater".
+///
+/// # Fields
+/// * `id` - A unique name for the node
+/// * `hostname` - The hostname of the machine
+/// * `site` - Where the node is installed (if applicable)
+/// * `tags` - Arbitrary labels, such as the room or rack
+///
+/// # Example
+/// ```
+/// use equilibrium::types::NodeIdentity;
+///
+/// let identity = NodeIdentity::new("greenhouse-1")
+///     .set_site("north-farm")
+///     .add_tag("zone", "seedlings");
+/// assert_eq!(identity.get_id(), "greenhouse-1");
+/// ```
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct NodeIdentity {
+    id: String,
+    hostname: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    site: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    tags: BTreeMap<String, String>,
+}
+
+impl NodeIdentity {
+    /// Create an identity with the hostname of the machine
+    pub fn new<S>(id: S) -> Self
+        where S: Into<String>
+    {
+        Self {
+            id: id.into(),
+            hostname: hostname(),
+            site: None,
+            tags: BTreeMap::new(),
+        }
+    }
+
+    /// Builder method to override the detected hostname
+    pub fn set_hostname<S>(mut self, hostname: S) -> Self
+        where S: Into<String>
+    {
+        self.hostname = hostname.into();
+        self
+    }
+
+    /// Builder method to set the site
+    pub fn set_site<S>(mut self, site: S) -> Self
+        where S: Into<String>
+    {
+        self.site = Some(site.into());
+        self
+    }
+
+    /// Builder method to add a tag, replacing any tag with the same key
+    pub fn add_tag<K, V>(mut self, key: K, value: V) -> Self
+        where K: Into<String>,
+              V: Into<String>
+    {
+        self.tags.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn get_hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    pub fn get_site(&self) -> Option<&str> {
+        self.site.as_deref()
+    }
+
+    pub fn get_tags(&self) -> &BTreeMap<String, String> {
+        &self.tags
+    }
+}
+
+impl Default for NodeIdentity {
+    /// Use the hostname as the ID
+    fn default() -> Self {
+        Self::new(hostname())
+    }
+}
+
+/// Detect the hostname of the machine, falling back to "localhost"
+fn hostname() -> String {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"].iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .chain(["HOSTNAME", "COMPUTERNAME"].iter().filter_map(|key| std::env::var(key).ok()))
+        .map(|hostname| hostname.trim().to_string())
+        .find(|hostname| !hostname.is_empty())
+        .unwrap_or_else(|| String::from("localhost"))
+}
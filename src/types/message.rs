@@ -1,5 +1,6 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use crate::types::NodeIdentity;
 
 /// A [`Message`] is a named event that is returned for logging.
 ///
@@ -8,9 +9,11 @@ use serde::{Deserialize, Serialize};
 /// # Fields
 /// * `name` - The name of the originating device
 /// * `content` - The content of the message. This is a human-readable string that describes the
-/// event that took place
+///   event that took place
 /// * `timestamp` - The timestamp that the event took place
 /// * `read_state` - Sensor read value (if applicable)
+/// * `output_state` - State of the output after the event (if applicable)
+/// * `node` - The node that the message originated from (if known)
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Message {
     /// The name of the originating device
@@ -24,6 +27,14 @@ pub struct Message {
 
     /// Sensor read value
     read_state: Option<String>,
+
+    /// State of the output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    output_state: Option<bool>,
+
+    /// The originating node
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    node: Option<NodeIdentity>,
 }
 
 impl Message {
@@ -43,9 +54,25 @@ impl Message {
             content: content.into(),
             timestamp,
             read_state: read_state.into(),
+            output_state: None,
+            node: None,
         }
     }
 
+    /// Builder method to set the state of the output after the event
+    pub fn set_output_state<O>(mut self, state: O) -> Self
+        where O: Into<Option<bool>>
+    {
+        self.output_state = state.into();
+        self
+    }
+
+    /// Builder method to set the originating node
+    pub fn set_node(mut self, node: NodeIdentity) -> Self {
+        self.node = Some(node);
+        self
+    }
+
     pub fn get_controller_name(&self) -> String {
         self.name.clone()
     }
@@ -55,10 +82,18 @@ impl Message {
     }
 
     pub fn get_timestamp(&self) -> DateTime<Utc> {
-        self.timestamp.clone()
+        self.timestamp
     }
 
     pub fn get_content(&self) -> String {
         self.content.clone()
     }
+
+    pub fn get_output_state(&self) -> Option<bool> {
+        self.output_state
+    }
+
+    pub fn get_node(&self) -> Option<&NodeIdentity> {
+        self.node.as_ref()
+    }
 }
\ No newline at end of file
@@ -1,9 +1,21 @@
 //! Primitive types used throughout the library
 
 mod action;
+mod command;
+mod error;
 mod event;
+mod identity;
 mod message;
+mod mode;
+mod state;
+mod status;
 
 pub use action::Action;
+pub use command::{Command, CommandError, CommandRequest};
+pub use error::DriverError;
 pub use event::Event;
-pub use message::Message;
\ No newline at end of file
+pub use identity::NodeIdentity;
+pub use message::Message;
+pub use mode::Mode;
+pub use state::{ControllerState, STATE_VERSION};
+pub use status::ControllerStatus;
\ No newline at end of file
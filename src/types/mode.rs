@@ -0,0 +1,40 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+
+/// Operating mode of a [`crate::controllers::Controller`] within a [`crate::ControllerGroup`]
+///
+/// This implements hand-off-auto control. In [`Mode::Auto`], the controller is polled as usual. In
+/// the manual modes, the output is held in a fixed state and the controller is not polled, so the
+/// control logic cannot reverse an operator's change. In [`Mode::Disabled`], the controller is not
+/// polled and the outputs are left untouched.
+#[derive(Debug, PartialEq, Copy, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    /// The controller operates automatically
+    #[default]
+    Auto,
+
+    /// The output is held on
+    ///
+    /// A [`crate::controllers::BidirectionalThreshold`] holds its increase output on and its
+    /// decrease output off.
+    ManualOn,
+
+    /// The output is held off
+    ManualOff,
+
+    /// The controller is not polled
+    Disabled,
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Mode::Auto => "Auto",
+            Mode::ManualOn => "Manual On",
+            Mode::ManualOff => "Manual Off",
+            Mode::Disabled => "Disabled",
+        };
+        write!(f, "{}", name)
+    }
+}
@@ -0,0 +1,114 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use crate::types::{Event, Mode};
+
+/// The current version of the [`ControllerState`] schema
+///
+/// This must be incremented whenever the layout of [`ControllerState`] changes, and a migration
+/// step must be added to [`crate::store::migrate`].
+pub const STATE_VERSION: u32 = 2;
+
+/// A snapshot of the internal state of a [`crate::controllers::Controller`]
+///
+/// Snapshots are taken after a controller has been polled and are persisted by a
+/// [`crate::store::StateStore`] so that the controller can resume where it left off after a restart.
+///
+/// # Fields
+/// * `version` - Schema version of the snapshot
+/// * `outputs` - Cached state of each output, in the order they are defined by the controller
+/// * `input` - Last value read from the input (if applicable)
+/// * `setpoint` - Threshold or other setpoint that may have been changed at runtime (if applicable)
+/// * `events` - Future events that have been scheduled
+/// * `mode` - [`Mode`] that the group applied to the controller
+/// * `mode_expires` - When the mode reverts to [`Mode::Auto`] (if applicable)
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ControllerState {
+    /// Schema version of the snapshot
+    version: u32,
+
+    /// Cached state of each output
+    outputs: Vec<Option<bool>>,
+
+    /// Last value read from the input
+    input: Option<String>,
+
+    /// Setpoint of the controller
+    setpoint: Option<f32>,
+
+    /// Future events that have been scheduled
+    events: Vec<Event>,
+
+    /// Mode that the group applied to the controller
+    mode: Mode,
+
+    /// When the mode reverts to automatic control
+    mode_expires: Option<DateTime<Utc>>,
+}
+
+impl ControllerState {
+    /// Create a new snapshot using the current schema version
+    ///
+    /// # Arguments
+    /// * `outputs` - Cached state of each output
+    /// * `events` - Future events that have been scheduled
+    pub fn new(outputs: Vec<Option<bool>>, events: Vec<Event>) -> Self {
+        Self {
+            version: STATE_VERSION,
+            outputs,
+            input: None,
+            setpoint: None,
+            events,
+            mode: Mode::Auto,
+            mode_expires: None,
+        }
+    }
+
+    /// Builder method to attach the last value read from the input
+    pub fn with_input<O>(mut self, input: O) -> Self
+        where O: Into<Option<String>>
+    {
+        self.input = input.into();
+        self
+    }
+
+    /// Builder method to attach the setpoint of the controller
+    pub fn with_setpoint(mut self, setpoint: f32) -> Self {
+        self.setpoint = Some(setpoint);
+        self
+    }
+
+    /// Builder method to attach the mode of the controller and when it expires
+    pub fn with_mode(mut self, mode: Mode, expires: Option<DateTime<Utc>>) -> Self {
+        self.mode = mode;
+        self.mode_expires = expires;
+        self
+    }
+
+    pub fn get_version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn get_outputs(&self) -> &Vec<Option<bool>> {
+        &self.outputs
+    }
+
+    pub fn get_input(&self) -> &Option<String> {
+        &self.input
+    }
+
+    pub fn get_setpoint(&self) -> Option<f32> {
+        self.setpoint
+    }
+
+    pub fn get_events(&self) -> &Vec<Event> {
+        &self.events
+    }
+
+    pub fn get_mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn get_mode_expires(&self) -> Option<DateTime<Utc>> {
+        self.mode_expires
+    }
+}
@@ -0,0 +1,74 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use crate::types::Mode;
+
+/// A summary of the current state of a [`crate::controllers::Controller`]
+///
+/// This is returned by [`crate::ControllerGroup::status`] for inspecting a running group.
+///
+/// # Fields
+/// * `name` - The name of the controller
+/// * `kind` - The type of controller (e.g. "Threshold")
+/// * `mode` - The operating mode of the controller
+/// * `mode_expires` - When the mode reverts to [`Mode::Auto`] (if applicable)
+/// * `outputs` - Cached state of each output
+/// * `last_reading` - Last value read from the input (if applicable)
+/// * `setpoint` - Setpoint of the controller (if applicable)
+/// * `next_event` - Time of the next scheduled event (if any)
+/// * `pending_events` - Number of scheduled events
+/// * `poll_duration` - How long the last poll took, in seconds (if polled)
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ControllerStatus {
+    pub(crate) name: String,
+    pub(crate) kind: String,
+    pub(crate) mode: Mode,
+    pub(crate) mode_expires: Option<DateTime<Utc>>,
+    pub(crate) outputs: Vec<Option<bool>>,
+    pub(crate) last_reading: Option<String>,
+    pub(crate) setpoint: Option<f32>,
+    pub(crate) next_event: Option<DateTime<Utc>>,
+    pub(crate) pending_events: usize,
+    pub(crate) poll_duration: Option<f64>,
+}
+
+impl ControllerStatus {
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_kind(&self) -> &str {
+        &self.kind
+    }
+
+    pub fn get_mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn get_mode_expires(&self) -> Option<DateTime<Utc>> {
+        self.mode_expires
+    }
+
+    pub fn get_outputs(&self) -> &Vec<Option<bool>> {
+        &self.outputs
+    }
+
+    pub fn get_last_reading(&self) -> &Option<String> {
+        &self.last_reading
+    }
+
+    pub fn get_setpoint(&self) -> Option<f32> {
+        self.setpoint
+    }
+
+    pub fn get_next_event(&self) -> Option<DateTime<Utc>> {
+        self.next_event
+    }
+
+    pub fn get_pending_events(&self) -> usize {
+        self.pending_events
+    }
+
+    pub fn get_poll_duration(&self) -> Option<f64> {
+        self.poll_duration
+    }
+}
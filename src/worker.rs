@@ -0,0 +1,530 @@
+//! Isolated execution of controllers on dedicated threads
+//!
+//! Controllers within a [`ControllerGroup`](crate::ControllerGroup) are polled sequentially, so a
+//! slow [`Input`](crate::Input) delays every other controller. Controllers which are added with
+//! [`Runtime::add_isolated_controller`](crate::Runtime::add_isolated_controller) instead run on a
+//! dedicated thread with their own polling interval.
+//!
+//! Each isolated controller is supervised. If a poll panics, or does not return within the poll
+//! timeout, the controller is handled according to the [`FailurePolicy`]: a new instance is
+//! created using the factory, or the controller is quarantined and no longer polled. A hung thread
+//! cannot be interrupted, but it is retired: once the hung poll returns, the thread exits without
+//! polling the controller again, and any message it produced is discarded.
+//!
+//! The outputs of a controller whose poll panicked are driven to their safe states before it is
+//! restarted or quarantined. The outputs of a hung controller are out of reach until its poll
+//! returns. If it is quarantined, they are driven to their safe states at that point, and until
+//! then they are left as they were.
+//!
+//! # Message Ordering
+//! Messages from a single isolated controller are emitted in the order that they were produced.
+//! Messages from different controllers, including the controllers of the group, are collected on
+//! every iteration of the runtime loop and are emitted together in the order in which they arrived.
+//! There is no ordering guarantee between controllers beyond that.
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+use chrono::{Duration, Utc};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio_util::sync::CancellationToken;
+use crate::controllers::Controller;
+use crate::types::Message;
+
+/// Determines how an isolated controller is handled when a poll panics or times out
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum FailurePolicy {
+    /// The controller is no longer polled
+    Quarantine,
+
+    /// A new instance of the controller is created, up to `max_restarts` times. Once the limit is
+    /// reached, the controller is quarantined.
+    Restart { max_restarts: u32 },
+}
+
+impl Default for FailurePolicy {
+    fn default() -> Self {
+        FailurePolicy::Restart { max_restarts: 3 }
+    }
+}
+
+type Factory = Arc<dyn Fn() -> Box<dyn Controller + Send> + Send + Sync>;
+
+/// Reports sent from a worker thread to the [`Supervisor`]
+#[derive(Debug)]
+enum WorkerEvent {
+    Started { name: String },
+    Polling { since: Instant },
+    Idle,
+    Message(Message),
+    Panicked { reason: String },
+    /// A message from a retired worker, which is reported even though the worker was replaced
+    Retired(Message),
+}
+
+/// A controller definition that can be instantiated on a worker thread
+pub(crate) struct WorkerSpec {
+    factory: Factory,
+    interval: Duration,
+}
+
+impl WorkerSpec {
+    pub(crate) fn new<F, C>(factory: F, interval: Duration) -> Self
+        where
+            F: Fn() -> C + Send + Sync + 'static,
+            C: Controller + Send + 'static,
+    {
+        Self {
+            factory: Arc::new(move || Box::new(factory())),
+            interval,
+        }
+    }
+}
+
+/// Bookkeeping for a single worker
+struct Worker {
+    spec: WorkerSpec,
+    name: String,
+    generation: u32,
+    restarts: u32,
+    polling_since: Option<Instant>,
+    handle: Option<JoinHandle<()>>,
+    /// Child of the shutdown token, which is cancelled when the worker is retired
+    retire: CancellationToken,
+    /// Cancelled before `retire` when the worker is quarantined rather than restarted
+    quarantine: CancellationToken,
+}
+
+/// Describe the payload of a panic
+pub(crate) fn panic_reason(payload: Box<dyn Any + Send>) -> String {
+    if let Some(reason) = payload.downcast_ref::<&str>() {
+        reason.to_string()
+    } else if let Some(reason) = payload.downcast_ref::<String>() {
+        reason.clone()
+    } else {
+        String::from("unknown panic")
+    }
+}
+
+/// Drive the outputs of a controller to their safe states, even if the controller panics again
+fn apply_safe_state(controller: &mut dyn Controller) -> Option<Message> {
+    panic::catch_unwind(AssertUnwindSafe(|| controller.shutdown(Utc::now()))).ok().flatten()
+}
+
+/// Run a controller on the current thread until it panics, or either token is cancelled
+///
+/// `retire` is a child of `token`. If it is cancelled on its own, the worker has been replaced and
+/// exits without polling the controller again. The outputs are only driven to their safe states
+/// if `quarantine` has been cancelled as well, since a replacement instance now drives them
+/// otherwise.
+fn run_worker(
+    factory: Factory,
+    interval: Duration,
+    send: impl Fn(WorkerEvent),
+    token: CancellationToken,
+    retire: CancellationToken,
+    quarantine: CancellationToken,
+) {
+    let retired = |controller: &mut dyn Controller| {
+        if retire.is_cancelled() && !token.is_cancelled() {
+            if quarantine.is_cancelled() {
+                if let Some(message) = apply_safe_state(controller) {
+                    send(WorkerEvent::Retired(message));
+                }
+            }
+            return true;
+        }
+        false
+    };
+
+    let mut controller = match panic::catch_unwind(AssertUnwindSafe(|| factory())) {
+        Ok(controller) => controller,
+        Err(payload) => {
+            send(WorkerEvent::Panicked { reason: panic_reason(payload) });
+            return;
+        }
+    };
+    send(WorkerEvent::Started { name: controller.get_name().unwrap_or_default() });
+
+    let mut next_execution_time = Utc::now() + interval;
+    loop {
+        let now = Utc::now();
+
+        if retired(controller.as_mut()) {
+            return;
+        }
+        if token.is_cancelled() {
+            if let Some(message) = apply_safe_state(controller.as_mut()) {
+                send(WorkerEvent::Message(message));
+            }
+            return;
+        }
+
+        if now >= next_execution_time {
+            send(WorkerEvent::Polling { since: Instant::now() });
+            let result = panic::catch_unwind(AssertUnwindSafe(|| controller.poll(now)));
+            if retired(controller.as_mut()) {
+                return;
+            }
+            send(WorkerEvent::Idle);
+
+            match result {
+                Ok(Some(message)) => send(WorkerEvent::Message(message)),
+                Ok(None) => {},
+                Err(payload) => {
+                    // the outputs are left as they were when the poll panicked
+                    if let Some(message) = apply_safe_state(controller.as_mut()) {
+                        send(WorkerEvent::Message(message));
+                    }
+                    send(WorkerEvent::Panicked { reason: panic_reason(payload) });
+                    return;
+                }
+            }
+            next_execution_time = now + interval;
+        }
+
+        // sleep for at most 100ms so that shutdown is handled promptly
+        let remaining = (next_execution_time - Utc::now())
+            .to_std()
+            .unwrap_or_default()
+            .min(std::time::Duration::from_millis(100));
+        thread::sleep(remaining);
+    }
+}
+
+/// Owns the worker threads of all isolated controllers
+pub(crate) struct Supervisor {
+    workers: Vec<Worker>,
+    tx: UnboundedSender<(usize, u32, WorkerEvent)>,
+    rx: UnboundedReceiver<(usize, u32, WorkerEvent)>,
+    policy: FailurePolicy,
+    timeout: std::time::Duration,
+    token: CancellationToken,
+}
+
+impl Supervisor {
+    pub(crate) fn new(
+        specs: Vec<WorkerSpec>,
+        policy: FailurePolicy,
+        timeout: std::time::Duration,
+        token: CancellationToken,
+    ) -> Self {
+        let (tx, rx) = unbounded_channel();
+        let workers = specs.into_iter()
+            .map(|spec| Worker {
+                spec,
+                name: String::new(),
+                generation: 0,
+                restarts: 0,
+                polling_since: None,
+                handle: None,
+                retire: token.child_token(),
+                quarantine: CancellationToken::new(),
+            })
+            .collect();
+        Self { workers, tx, rx, policy, timeout, token }
+    }
+
+    /// Start a thread for every worker
+    pub(crate) fn start(&mut self) {
+        for index in 0..self.workers.len() {
+            self.spawn(index);
+        }
+    }
+
+    fn spawn(&mut self, index: usize) {
+        let worker = &mut self.workers[index];
+        let generation = worker.generation;
+        let factory = worker.spec.factory.clone();
+        let interval = worker.spec.interval;
+        let tx = self.tx.clone();
+        let token = self.token.clone();
+        let retire = worker.retire.clone();
+        let quarantine = worker.quarantine.clone();
+
+        worker.polling_since = None;
+        worker.handle = Some(thread::spawn(move || {
+            let send = |event| {
+                // the supervisor may have gone away, in which case events are dropped
+                let _ = tx.send((index, generation, event));
+            };
+            run_worker(factory, interval, send, token, retire, quarantine);
+        }));
+    }
+
+    /// Restart or quarantine a worker after a failure
+    fn fail(&mut self, index: usize, reason: String) -> Message {
+        let can_restart = match self.policy {
+            FailurePolicy::Restart { max_restarts } => self.workers[index].restarts < max_restarts,
+            FailurePolicy::Quarantine => false,
+        };
+
+        let worker = &mut self.workers[index];
+        if !can_restart {
+            worker.quarantine.cancel();
+        }
+        // the failed thread stops once its poll returns, and any further events are discarded
+        worker.retire.cancel();
+        worker.retire = self.token.child_token();
+        worker.generation += 1;
+        worker.handle = None;
+
+        let content = if can_restart {
+            worker.restarts += 1;
+            format!("Controller Restarted: {}", reason)
+        } else {
+            format!("Controller Quarantined: {}", reason)
+        };
+        let message = Message::new(worker.name.clone(), content, Utc::now(), None);
+
+        if can_restart {
+            self.spawn(index);
+        }
+        message
+    }
+
+    /// Returns how many isolated controllers are supervised
+    pub(crate) fn count(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Returns how many isolated controllers have been quarantined
+    pub(crate) fn quarantined(&self) -> usize {
+        self.workers.iter().filter(|w| w.quarantine.is_cancelled()).count()
+    }
+
+    /// Collect messages from the workers and handle any failures
+    pub(crate) fn collect(&mut self) -> Vec<Message> {
+        let mut messages = Vec::new();
+
+        while let Ok((index, generation, event)) = self.rx.try_recv() {
+            if self.workers[index].generation != generation && !matches!(event, WorkerEvent::Retired(_)) {
+                continue;
+            }
+            match event {
+                WorkerEvent::Started { name } => self.workers[index].name = name,
+                WorkerEvent::Polling { since } => self.workers[index].polling_since = Some(since),
+                WorkerEvent::Idle => self.workers[index].polling_since = None,
+                WorkerEvent::Message(message) | WorkerEvent::Retired(message) => messages.push(message),
+                WorkerEvent::Panicked { reason } => {
+                    messages.push(self.fail(index, format!("panicked: {}", reason)));
+                }
+            }
+        }
+
+        for index in 0..self.workers.len() {
+            let worker = &self.workers[index];
+            if !worker.quarantine.is_cancelled() && worker.polling_since.is_some_and(|since| since.elapsed() > self.timeout) {
+                messages.push(self.fail(index, String::from("poll timed out")));
+            }
+        }
+        messages
+    }
+
+    /// Wait for the workers to shut down and collect their final messages
+    ///
+    /// The token must be cancelled beforehand. Workers that do not exit within the poll timeout
+    /// are abandoned.
+    pub(crate) async fn shutdown(&mut self) -> Vec<Message> {
+        let deadline = Instant::now() + self.timeout;
+        while Instant::now() < deadline {
+            let finished = self.workers.iter()
+                .filter_map(|w| w.handle.as_ref())
+                .all(|h| h.is_finished());
+            if finished {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let mut messages = Vec::new();
+        while let Ok((index, generation, event)) = self.rx.try_recv() {
+            match event {
+                WorkerEvent::Message(message) if self.workers[index].generation == generation => {
+                    messages.push(message);
+                }
+                WorkerEvent::Retired(message) => messages.push(message),
+                _ => {},
+            }
+        }
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use crate::controllers::Threshold;
+    use crate::{Input, Output};
+    use super::*;
+
+    /// Collect messages until one with the given prefix is found
+    async fn collect_until(supervisor: &mut Supervisor, prefix: &str) -> Vec<Message> {
+        let mut messages = Vec::new();
+        for _ in 0..200 {
+            messages.extend(supervisor.collect());
+            if messages.iter().any(|m| m.get_content().starts_with(prefix)) {
+                return messages;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("no message starting with {:?} in {:?}", prefix, messages);
+    }
+
+    fn named<C: Controller>(mut controller: C, name: &str) -> C {
+        controller.set_name(name.to_string());
+        controller
+    }
+
+    #[tokio::test]
+    async fn test_messages() {
+        let spec = WorkerSpec::new(
+            || named(Threshold::new(
+                70.0,
+                Input::new(|| "69.0".to_string()),
+                Output::default(),
+                Duration::milliseconds(10),
+            ), "heater"),
+            Duration::milliseconds(10),
+        );
+        let token = CancellationToken::new();
+        let mut supervisor = Supervisor::new(
+            vec![spec],
+            FailurePolicy::Quarantine,
+            std::time::Duration::from_secs(1),
+            token.clone(),
+        );
+        supervisor.start();
+
+        let messages = collect_until(&mut supervisor, "Below Threshold").await;
+        assert_eq!(messages[0].get_controller_name(), "heater");
+
+        token.cancel();
+        supervisor.shutdown().await;
+        assert!(supervisor.workers[0].handle.as_ref().unwrap().is_finished());
+    }
+
+    #[tokio::test]
+    async fn test_panic_quarantine() {
+        // a driver that panics takes the threshold controller down with it
+        let writes = Arc::new(Mutex::new(Vec::new()));
+        let recorded = writes.clone();
+        let spec = WorkerSpec::new(
+            move || {
+                let recorded = recorded.clone();
+                named(Threshold::new(
+                    70.0,
+                    Input::new(|| -> String { panic!("driver fault") }),
+                    Output::new(move |state| recorded.lock().unwrap().push(state)).set_safe_state(false),
+                    Duration::milliseconds(10),
+                ), "heater")
+            },
+            Duration::milliseconds(10),
+        );
+        let token = CancellationToken::new();
+        let mut supervisor = Supervisor::new(
+            vec![spec],
+            FailurePolicy::Quarantine,
+            std::time::Duration::from_secs(1),
+            token.clone(),
+        );
+        supervisor.start();
+
+        let messages = collect_until(&mut supervisor, "Controller Quarantined").await;
+        let message = messages.last().unwrap();
+        assert_eq!(message.get_controller_name(), "heater");
+        assert!(message.get_content().contains("panicked"));
+        assert!(supervisor.workers[0].quarantine.is_cancelled());
+
+        // the output is driven to its safe state before the controller is quarantined
+        assert_eq!(messages[0].get_content(), "Safe State");
+        assert_eq!(*writes.lock().unwrap(), vec![false]);
+
+        token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_panic_restart() {
+        let instances = Arc::new(AtomicU32::new(0));
+        let counter = instances.clone();
+        let spec = WorkerSpec::new(
+            move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Threshold::new(
+                    70.0,
+                    Input::new(|| -> String { panic!("driver fault") }),
+                    Output::default(),
+                    Duration::milliseconds(10),
+                )
+            },
+            Duration::milliseconds(10),
+        );
+        let token = CancellationToken::new();
+        let mut supervisor = Supervisor::new(
+            vec![spec],
+            FailurePolicy::Restart { max_restarts: 2 },
+            std::time::Duration::from_secs(1),
+            token.clone(),
+        );
+        supervisor.start();
+
+        let messages = collect_until(&mut supervisor, "Controller Quarantined").await;
+        let restarts = messages.iter()
+            .filter(|m| m.get_content().starts_with("Controller Restarted"))
+            .count();
+        assert_eq!(restarts, 2);
+        assert_eq!(instances.load(Ordering::SeqCst), 3);
+
+        token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_hung_controller() {
+        let writes = Arc::new(Mutex::new(Vec::new()));
+        let recorded = writes.clone();
+        let spec = WorkerSpec::new(
+            move || {
+                let recorded = recorded.clone();
+                named(Threshold::new(
+                    70.0,
+                    Input::new(|| {
+                        thread::sleep(std::time::Duration::from_millis(500));
+                        "69.0".to_string()
+                    }),
+                    Output::new(move |state| recorded.lock().unwrap().push(state)).set_safe_state(false),
+                    Duration::milliseconds(10),
+                ).set_inverted(), "slow")
+            },
+            Duration::milliseconds(10),
+        );
+        let token = CancellationToken::new();
+        let mut supervisor = Supervisor::new(
+            vec![spec],
+            FailurePolicy::Quarantine,
+            std::time::Duration::from_millis(100),
+            token.clone(),
+        );
+        supervisor.start();
+
+        let messages = collect_until(&mut supervisor, "Controller Quarantined").await;
+        let message = messages.last().unwrap();
+        assert_eq!(message.get_controller_name(), "slow");
+        assert!(message.get_content().contains("poll timed out"));
+
+        // the reading of the hung poll is discarded, but the output is driven to its safe state
+        tokio::time::sleep(std::time::Duration::from_millis(600)).await;
+        let messages = supervisor.collect();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].get_controller_name(), "slow");
+        assert_eq!(messages[0].get_content(), "Safe State");
+
+        // the hung poll completes its write, but the retired instance is not polled again
+        assert_eq!(*writes.lock().unwrap(), vec![true, false]);
+        tokio::time::sleep(std::time::Duration::from_millis(600)).await;
+        assert_eq!(writes.lock().unwrap().len(), 2);
+
+        token.cancel();
+    }
+}